@@ -8,6 +8,11 @@ use std::{
 };
 
 fn main() {
+    // The native P2P implementation needs neither the Go library nor the generated protobufs.
+    if env::var_os("CARGO_FEATURE_NATIVE_P2P").is_some() {
+        return;
+    }
+
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     let out_var = env::var("OUT_DIR").unwrap();
 
@@ -0,0 +1,22 @@
+//! Client for the Hermes price service.
+//!
+//! The crate provides the types of the Hermes REST and WebSocket APIs, which are shared with the
+//! Hermes server, along with:
+//!
+//! - `HermesClient`, a typed async client for the REST routes, whose error responses are
+//!   returned as a `HermesError`,
+//! - `ws::subscribe`, a WebSocket stream that reconnects and resumes its subscription without
+//!   missing updates,
+//! - `verify::verify_update_data`, which checks the update data returned by Hermes against a
+//!   trusted Wormhole guardian set.
+
+mod macros;
+pub mod rest;
+pub mod types;
+pub mod verify;
+pub mod ws;
+
+pub use rest::{
+    HermesClient,
+    HermesError,
+};
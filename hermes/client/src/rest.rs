@@ -0,0 +1,471 @@
+//! Typed client for the Hermes REST API.
+
+use {
+    crate::types::{
+        CandleResolution,
+        Encoding,
+        ErrorCode,
+        ErrorResponse,
+        GetVaaCcipResponse,
+        GetVaaResponse,
+        RpcCandle,
+        RpcPriceFeed,
+        RpcProductMetadata,
+        RpcTwap,
+        RpcTwapWindow,
+        Slot,
+        UnixTimestamp,
+    },
+    anyhow::{
+        anyhow,
+        Result,
+    },
+    pyth_sdk::PriceIdentifier,
+    reqwest::{
+        header,
+        Response,
+        StatusCode,
+    },
+    serde::de::DeserializeOwned,
+    std::collections::HashSet,
+};
+
+/// Options of the routes returning price feeds or TWAPs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResponseOptions {
+    /// Include the metadata (slot, receive time, ...) of the updates.
+    pub verbose:  bool,
+    /// Include the update data of the updates.
+    pub binary:   bool,
+    /// Encoding of the update data.
+    pub encoding: Encoding,
+}
+
+impl ResponseOptions {
+    fn query(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("verbose", self.verbose.to_string()),
+            ("binary", self.binary.to_string()),
+            ("encoding", encoding_param(self.encoding)),
+        ]
+    }
+}
+
+/// Update data covering several feeds with a single VAA, returned by `latest_aggregated_vaa`.
+#[derive(Debug, Clone)]
+pub struct AggregatedUpdateData {
+    /// The slot in which all the feeds were updated.
+    pub slot:        Slot,
+    pub update_data: Vec<u8>,
+}
+
+/// A page of `get_price_feeds_range` results.
+#[derive(Debug, Clone)]
+pub struct PriceFeedsRangePage {
+    pub price_feeds: Vec<RpcPriceFeed>,
+    /// Cursor to pass to the next request if there are more results.
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HermesClient {
+    http:     reqwest::Client,
+    base_url: String,
+}
+
+impl HermesClient {
+    /// Creates a client for the Hermes instance at `base_url`, e.g. `https://hermes.pyth.network`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(reqwest::Client::new(), base_url)
+    }
+
+    pub fn with_http_client(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Self {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub async fn live(&self) -> Result<bool> {
+        Ok(self.get("/live", &[]).await?.status().is_success())
+    }
+
+    pub async fn ready(&self) -> Result<bool> {
+        Ok(self.get("/ready", &[]).await?.status().is_success())
+    }
+
+    pub async fn price_feed_ids(&self) -> Result<HashSet<PriceIdentifier>> {
+        self.get_json("/api/price_feed_ids", &[]).await
+    }
+
+    /// Returns the metadata of the price feeds whose symbol, base, quote or description contains
+    /// `query`, optionally restricted to an asset class. Both filters are case-insensitive.
+    pub async fn price_feeds_metadata(
+        &self,
+        query: Option<&str>,
+        asset_class: Option<&str>,
+    ) -> Result<Vec<RpcProductMetadata>> {
+        let mut params = vec![];
+        if let Some(query) = query {
+            params.push(("query", query.to_string()));
+        }
+        if let Some(asset_class) = asset_class {
+            params.push(("asset_class", asset_class.to_string()));
+        }
+        self.get_json("/api/price_feeds/metadata", &params).await
+    }
+
+    pub async fn latest_price_feeds(
+        &self,
+        ids: &[PriceIdentifier],
+        options: ResponseOptions,
+    ) -> Result<Vec<RpcPriceFeed>> {
+        let mut query = ids_query(ids);
+        query.extend(options.query());
+        self.get_json("/api/latest_price_feeds", &query).await
+    }
+
+    /// Returns the latest update data of the given ids, decoded.
+    pub async fn latest_vaas(&self, ids: &[PriceIdentifier]) -> Result<Vec<Vec<u8>>> {
+        self.get_json::<Vec<String>>("/api/latest_vaas", &ids_query(ids))
+            .await?
+            .iter()
+            .map(|vaa| Encoding::Base64.decode(vaa))
+            .collect()
+    }
+
+    /// Returns a single update data for the given ids, taken from the latest slot in which all of
+    /// them were updated and at most `max_slot_lag` slots behind their latest update (Hermes picks
+    /// a default if unset).
+    pub async fn latest_aggregated_vaa(
+        &self,
+        ids: &[PriceIdentifier],
+        max_slot_lag: Option<Slot>,
+    ) -> Result<AggregatedUpdateData> {
+        let mut query = ids_query(ids);
+        query.push(("aggregate", true.to_string()));
+        if let Some(max_slot_lag) = max_slot_lag {
+            query.push(("max_slot_lag", max_slot_lag.to_string()));
+        }
+
+        let response = check_status(self.get("/api/latest_vaas", &query).await?).await?;
+        let slot = response
+            .headers()
+            .get("x-update-slot")
+            .and_then(|slot| slot.to_str().ok())
+            .and_then(|slot| slot.parse().ok())
+            .ok_or(anyhow!("Missing slot of the aggregated update data"))?;
+
+        let vaas = response.json::<Vec<String>>().await?;
+        let vaa = vaas
+            .first()
+            .ok_or(anyhow!("Missing aggregated update data"))?;
+
+        Ok(AggregatedUpdateData {
+            slot,
+            update_data: Encoding::Base64.decode(vaa)?,
+        })
+    }
+
+    pub async fn get_price_feed(
+        &self,
+        id: PriceIdentifier,
+        publish_time: UnixTimestamp,
+        options: ResponseOptions,
+    ) -> Result<RpcPriceFeed> {
+        let mut query = id_query(id, publish_time);
+        query.extend(options.query());
+        self.get_json("/api/get_price_feed", &query).await
+    }
+
+    /// Returns a page of the price feed updates of the given ids published within the inclusive
+    /// `[start_time, end_time]` range. Pass the `next_cursor` of a page to get the next one.
+    pub async fn get_price_feeds_range(
+        &self,
+        ids: &[PriceIdentifier],
+        start_time: UnixTimestamp,
+        end_time: UnixTimestamp,
+        limit: Option<usize>,
+        cursor: Option<&str>,
+        options: ResponseOptions,
+    ) -> Result<PriceFeedsRangePage> {
+        let mut query = ids_query(ids);
+        query.push(("start_time", start_time.to_string()));
+        query.push(("end_time", end_time.to_string()));
+        if let Some(limit) = limit {
+            query.push(("limit", limit.to_string()));
+        }
+        if let Some(cursor) = cursor {
+            query.push(("cursor", cursor.to_string()));
+        }
+        query.extend(options.query());
+
+        let response = check_status(self.get("/api/get_price_feeds_range", &query).await?).await?;
+        let next_cursor = response
+            .headers()
+            .get("x-next-cursor")
+            .and_then(|cursor| cursor.to_str().ok())
+            .map(|cursor| cursor.to_string());
+
+        // The updates are sent as newline-delimited JSON.
+        let price_feeds = response
+            .text()
+            .await?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|e| anyhow!(e)))
+            .collect::<Result<_>>()?;
+
+        Ok(PriceFeedsRangePage {
+            price_feeds,
+            next_cursor,
+        })
+    }
+
+    pub async fn latest_twaps(
+        &self,
+        ids: &[PriceIdentifier],
+        options: ResponseOptions,
+    ) -> Result<Vec<RpcTwap>> {
+        let mut query = ids_query(ids);
+        query.extend(options.query());
+        self.get_json("/api/latest_twaps", &query).await
+    }
+
+    pub async fn get_twap(
+        &self,
+        id: PriceIdentifier,
+        publish_time: UnixTimestamp,
+        options: ResponseOptions,
+    ) -> Result<RpcTwap> {
+        let mut query = id_query(id, publish_time);
+        query.extend(options.query());
+        self.get_json("/api/get_twap", &query).await
+    }
+
+    pub async fn get_twap_window(
+        &self,
+        id: PriceIdentifier,
+        start_time: UnixTimestamp,
+        end_time: UnixTimestamp,
+        binary: bool,
+        encoding: Encoding,
+    ) -> Result<RpcTwapWindow> {
+        let query = vec![
+            ("id", hex_id(id)),
+            ("start_time", start_time.to_string()),
+            ("end_time", end_time.to_string()),
+            ("binary", binary.to_string()),
+            ("encoding", encoding_param(encoding)),
+        ];
+        self.get_json("/api/get_twap_window", &query).await
+    }
+
+    /// Returns the candles of a feed for the intervals overlapping the inclusive
+    /// `[start_time, end_time]` range. Only served by Hermes instances with a persistent storage.
+    pub async fn get_candles(
+        &self,
+        id: PriceIdentifier,
+        resolution: CandleResolution,
+        start_time: UnixTimestamp,
+        end_time: UnixTimestamp,
+    ) -> Result<Vec<RpcCandle>> {
+        let query = vec![
+            ("id", hex_id(id)),
+            ("resolution", resolution.as_str().to_string()),
+            ("start_time", start_time.to_string()),
+            ("end_time", end_time.to_string()),
+        ];
+        self.get_json("/api/get_candles", &query).await
+    }
+
+    pub async fn get_vaa(
+        &self,
+        id: PriceIdentifier,
+        publish_time: UnixTimestamp,
+    ) -> Result<GetVaaResponse> {
+        self.get_json("/api/get_vaa", &id_query(id, publish_time))
+            .await
+    }
+
+    /// Returns the update data of the first price feed update published at or after
+    /// `publish_time`, as raw bytes.
+    pub async fn get_vaa_bytes(
+        &self,
+        id: PriceIdentifier,
+        publish_time: UnixTimestamp,
+    ) -> Result<Vec<u8>> {
+        let response = self
+            .http
+            .get(format!("{}/api/get_vaa", self.base_url))
+            .query(&id_query(id, publish_time))
+            .header(header::ACCEPT, "application/octet-stream")
+            .send()
+            .await?;
+        Ok(check_status(response).await?.bytes().await?.to_vec())
+    }
+
+    /// Returns the CCIP read response for the given `<price_id><publish_time>` request data.
+    pub async fn get_vaa_ccip(
+        &self,
+        id: PriceIdentifier,
+        publish_time: UnixTimestamp,
+    ) -> Result<GetVaaCcipResponse> {
+        let mut data = id.to_bytes().to_vec();
+        data.extend_from_slice(&publish_time.to_be_bytes());
+        self.get_json(
+            "/api/get_vaa_ccip",
+            &[("data", format!("0x{}", hex::encode(data)))],
+        )
+        .await
+    }
+
+    async fn get(&self, path: &str, query: &[(&str, String)]) -> Result<Response> {
+        Ok(self
+            .http
+            .get(format!("{}{}", self.base_url, path))
+            .query(query)
+            .send()
+            .await?)
+    }
+
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T> {
+        Ok(check_status(self.get(path, query).await?)
+            .await?
+            .json()
+            .await?)
+    }
+}
+
+fn hex_id(id: PriceIdentifier) -> String {
+    hex::encode(id.to_bytes())
+}
+
+/// Hermes parses `ids[]=<id>&ids[]=<id>` lists in the query string.
+fn ids_query(ids: &[PriceIdentifier]) -> Vec<(&'static str, String)> {
+    ids.iter().map(|id| ("ids[]", hex_id(*id))).collect()
+}
+
+fn id_query(id: PriceIdentifier, publish_time: UnixTimestamp) -> Vec<(&'static str, String)> {
+    vec![
+        ("id", hex_id(id)),
+        ("publish_time", publish_time.to_string()),
+    ]
+}
+
+fn encoding_param(encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Base64 => "base64".to_string(),
+        Encoding::Hex => "hex".to_string(),
+    }
+}
+
+/// An error response of Hermes. The methods of the client return it as an `anyhow::Error`, which
+/// can be downcast to tell it apart from the network and decoding failures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HermesError {
+    pub status: StatusCode,
+    /// The description of the error, or `None` if the body is not an `ErrorResponse`, e.g. when
+    /// it comes from a proxy in front of Hermes.
+    pub error:  Option<ErrorResponse>,
+    pub body:   String,
+}
+
+impl HermesError {
+    fn new(status: StatusCode, body: String) -> Self {
+        Self {
+            status,
+            error: serde_json::from_str(&body).ok(),
+            body,
+        }
+    }
+
+    pub fn code(&self) -> Option<ErrorCode> {
+        self.error.as_ref().map(|error| error.code)
+    }
+}
+
+impl std::fmt::Display for HermesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.error {
+            Some(error) => write!(
+                f,
+                "Hermes returned {} ({:?}): {}",
+                self.status, error.code, error.message
+            ),
+            None => write!(f, "Hermes returned {}: {}", self.status, self.body),
+        }
+    }
+}
+
+impl std::error::Error for HermesError {
+}
+
+/// Turns the error responses of Hermes into a `HermesError`.
+async fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    Err(HermesError::new(status, response.text().await?).into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_query_parameters() {
+        let id = PriceIdentifier::new([0xab; 32]);
+        assert_eq!(
+            ids_query(&[id, id]),
+            vec![("ids[]", "ab".repeat(32)), ("ids[]", "ab".repeat(32))]
+        );
+        assert_eq!(
+            ResponseOptions {
+                verbose:  true,
+                binary:   false,
+                encoding: Encoding::Hex,
+            }
+            .query(),
+            vec![
+                ("verbose", "true".to_string()),
+                ("binary", "false".to_string()),
+                ("encoding", "hex".to_string()),
+            ]
+        );
+        assert_eq!(
+            HermesClient::new("http://localhost:33999/").base_url,
+            "http://localhost:33999"
+        );
+    }
+
+    #[test]
+    pub fn test_error_responses_are_parsed() {
+        let error = HermesError::new(
+            StatusCode::NOT_FOUND,
+            r#"{"code":"price_feed_not_found","message":"Price feeds not found"}"#.to_string(),
+        );
+        assert_eq!(error.code(), Some(ErrorCode::PriceFeedNotFound));
+        assert_eq!(
+            error.to_string(),
+            "Hermes returned 404 Not Found (PriceFeedNotFound): Price feeds not found"
+        );
+
+        // The body is kept when it is not an error response.
+        let error = HermesError::new(StatusCode::BAD_GATEWAY, "Bad Gateway".to_string());
+        assert_eq!(error.code(), None);
+        assert_eq!(
+            error.to_string(),
+            "Hermes returned 502 Bad Gateway: Bad Gateway"
+        );
+
+        // And the error can be told apart from the other failures.
+        let error: anyhow::Error = error.into();
+        assert!(error.downcast_ref::<HermesError>().is_some());
+    }
+}
@@ -0,0 +1,592 @@
+//! Types of the Hermes REST and WebSocket APIs, shared by the server and the client.
+
+use {
+    anyhow::{
+        anyhow,
+        Result,
+    },
+    base64::{
+        engine::general_purpose::STANDARD as base64_standard_engine,
+        Engine as _,
+    },
+    pyth_sdk::{
+        Price,
+        PriceIdentifier,
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+pub type Slot = u64;
+pub type UnixTimestamp = i64;
+
+/// A price feed given by its 32-byte id or by its symbol.
+///
+/// Symbols, such as `Crypto.BTC/USD` or `BTC/USD`, are told apart by the `/` between their base
+/// and quote, and the server resolves them with the price feed metadata it has loaded. Anything
+/// else must be a hex encoded id, with or without a `0x` prefix and in lower or upper case, and
+/// is rejected if it is not 32 bytes long.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PriceIdInput {
+    Id([u8; 32]),
+    Symbol(String),
+}
+
+impl PriceIdInput {
+    fn parse(input: &str) -> Option<Self> {
+        if input.contains('/') {
+            return Some(Self::Symbol(input.to_string()));
+        }
+        let bytes = hex::decode(input.strip_prefix("0x").unwrap_or(input)).ok()?;
+        Some(Self::Id(bytes.try_into().ok()?))
+    }
+}
+
+impl<'de> Deserialize<'de> for PriceIdInput {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let input = String::deserialize(deserializer)?;
+        Self::parse(&input).ok_or_else(|| {
+            serde::de::Error::invalid_value(
+                serde::de::Unexpected::Str(&input),
+                &"a 32-byte hex encoded price feed id or a symbol such as BTC/USD",
+            )
+        })
+    }
+}
+
+impl Serialize for PriceIdInput {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Self::Id(id) => serializer.serialize_str(&hex::encode(id)),
+            Self::Symbol(symbol) => serializer.serialize_str(symbol),
+        }
+    }
+}
+
+impl From<PriceIdentifier> for PriceIdInput {
+    fn from(id: PriceIdentifier) -> Self {
+        Self::Id(id.to_bytes())
+    }
+}
+
+/// Encoding of the binary data (e.g. update data) embedded in the responses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Base64,
+    /// Lowercase hex with a `0x` prefix, as expected by EVM tooling.
+    Hex,
+}
+
+impl Encoding {
+    pub fn encode(&self, bytes: impl AsRef<[u8]>) -> String {
+        match self {
+            Encoding::Base64 => base64_standard_engine.encode(bytes),
+            Encoding::Hex => format!("0x{}", hex::encode(bytes)),
+        }
+    }
+
+    pub fn decode(&self, encoded: &str) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Base64 => base64_standard_engine
+                .decode(encoded)
+                .map_err(|e| anyhow!("Invalid base64 data: {}", e)),
+            Encoding::Hex => hex::decode(encoded.trim_start_matches("0x"))
+                .map_err(|e| anyhow!("Invalid hex data: {}", e)),
+        }
+    }
+}
+
+/// Binary data encoded with the requested `Encoding`.
+pub type EncodedBinary = String;
+
+/// Schema of the `pyth_sdk::Price` of the responses in the OpenAPI document. The price and
+/// confidence are strings as they may not fit in a JSON number.
+#[cfg(feature = "openapi")]
+#[derive(utoipa::ToSchema)]
+#[schema(as = Price)]
+#[allow(dead_code)]
+pub struct PriceSchema {
+    #[schema(example = "2920679499999")]
+    price:        String,
+    #[schema(example = "509500001")]
+    conf:         String,
+    #[schema(example = -8)]
+    expo:         i32,
+    publish_time: UnixTimestamp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RpcPriceFeedMetadata {
+    pub slot:                       Slot,
+    pub emitter_chain:              u16,
+    pub price_service_receive_time: UnixTimestamp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RpcPriceFeed {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub id:        PriceIdentifier,
+    #[cfg_attr(feature = "openapi", schema(value_type = PriceSchema))]
+    pub price:     Price,
+    #[cfg_attr(feature = "openapi", schema(value_type = PriceSchema))]
+    pub ema_price: Price,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata:  Option<RpcPriceFeedMetadata>,
+    /// Vaa binary in the requested encoding.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vaa:       Option<EncodedBinary>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RpcTwap {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub id:                PriceIdentifier,
+    /// Cumulative sums are represented as strings because they do not fit in a JSON number.
+    pub cumulative_price:  String,
+    pub cumulative_conf:   String,
+    pub num_down_slots:    u64,
+    pub expo:              i32,
+    pub publish_time:      UnixTimestamp,
+    pub prev_publish_time: UnixTimestamp,
+    pub publish_slot:      Slot,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata:          Option<RpcPriceFeedMetadata>,
+    /// Update data binary in the requested encoding.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vaa:               Option<EncodedBinary>,
+}
+
+/// A TWAP computed over the window between two `TwapMessage`s of a feed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RpcTwapWindow {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub id:             PriceIdentifier,
+    /// The average price and confidence. Its publish time is the end of the window.
+    #[cfg_attr(feature = "openapi", schema(value_type = PriceSchema))]
+    pub twap:           Price,
+    pub start_time:     UnixTimestamp,
+    pub end_time:       UnixTimestamp,
+    pub num_slots:      u64,
+    pub num_down_slots: u64,
+    /// Update data of the start and end messages in the requested encoding.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vaas:           Option<Vec<EncodedBinary>>,
+}
+
+/// Interval covered by each candle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum CandleResolution {
+    #[serde(rename = "1s")]
+    OneSecond,
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+}
+
+impl CandleResolution {
+    pub fn seconds(&self) -> i64 {
+        match self {
+            CandleResolution::OneSecond => 1,
+            CandleResolution::OneMinute => 60,
+            CandleResolution::FiveMinutes => 5 * 60,
+            CandleResolution::OneHour => 60 * 60,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CandleResolution::OneSecond => "1s",
+            CandleResolution::OneMinute => "1m",
+            CandleResolution::FiveMinutes => "5m",
+            CandleResolution::OneHour => "1h",
+        }
+    }
+}
+
+/// Open, high, low and close prices of a feed over the `[start_time, end_time)` interval. The
+/// prices have the `expo` exponent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RpcCandle {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub id:          PriceIdentifier,
+    pub start_time:  UnixTimestamp,
+    pub end_time:    UnixTimestamp,
+    pub open:        i64,
+    pub high:        i64,
+    pub low:         i64,
+    pub close:       i64,
+    /// Average confidence interval of the updates within the interval.
+    pub avg_conf:    u64,
+    pub expo:        i32,
+    pub num_updates: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GetVaaResponse {
+    pub vaa:          EncodedBinary,
+    #[serde(rename = "publishTime")]
+    pub publish_time: UnixTimestamp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GetVaaCcipResponse {
+    pub data: String, // TODO: Use a typed wrapper for the hex output with leading 0x.
+}
+
+/// Update data of the `/v2` API, with the encoding of the blobs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BinaryUpdate {
+    pub encoding: Encoding,
+    pub data:     Vec<EncodedBinary>,
+}
+
+/// Update data of the `/v2` API proving several price feeds against the VAA of a single slot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AggregatedUpdate {
+    /// Slot in which all the price feeds were updated.
+    pub slot:        Slot,
+    pub encoding:    Encoding,
+    pub data:        EncodedBinary,
+    pub price_feeds: Vec<RpcPriceFeed>,
+}
+
+/// Machine-readable code of the errors of the REST and WebSocket APIs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The query parameters or the request body are malformed.
+    InvalidParams,
+    /// No update of the price feeds in `feed_ids` is stored.
+    PriceFeedNotFound,
+    /// The requested publish time is older than the updates retained for the price feeds in
+    /// `feed_ids`, see `retained_window`.
+    PublishTimeTooOld,
+    /// No update of the price feeds in `feed_ids` was published at or after the requested publish
+    /// time yet, see `retained_window`.
+    PublishTimeInFuture,
+    UpdateDataNotFound,
+    /// The latest updates of the requested price feeds are too far apart to be served with a
+    /// single update data, one of them is probably stale.
+    NoCommonSlot,
+    InvalidUpdateData,
+    InvalidRange,
+    InvalidTwapWindow,
+    /// The server does not keep the history needed by the request.
+    HistoryUnavailable,
+    /// The API key of the request is unknown.
+    InvalidApiKey,
+    RateLimited,
+    /// No price feed has the requested symbol.
+    UnknownSymbol,
+    /// Several price feeds have the requested short symbol, use the full symbol instead.
+    AmbiguousSymbol,
+    Internal,
+}
+
+/// Inclusive range of publish times of the updates retained by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TimeWindow {
+    pub start: UnixTimestamp,
+    pub end:   UnixTimestamp,
+}
+
+/// Body of the error responses of the REST API, also sent in the WebSocket error responses.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ErrorResponse {
+    pub code:            ErrorCode,
+    /// Human-readable description of the error.
+    pub message:         String,
+    /// Ids of the price feeds that caused the error.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub feed_ids:        Vec<PriceIdentifier>,
+    /// Publish times that can be requested for the price feeds in `feed_ids`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retained_window: Option<TimeWindow>,
+}
+
+impl ErrorResponse {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            feed_ids: vec![],
+            retained_window: None,
+        }
+    }
+}
+
+/// Metadata of a price feed, taken from its Pythnet product account.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RpcProductMetadata {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub id:          PriceIdentifier,
+    /// Full symbol of the feed, e.g. `Crypto.BTC/USD`.
+    pub symbol:      String,
+    /// Asset class of the feed, e.g. `Crypto` or `FX`.
+    pub asset_class: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base:        Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote:       Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum ClientMessage {
+    #[serde(rename = "subscribe")]
+    Subscribe {
+        ids:                  Vec<PriceIdInput>,
+        #[serde(default)]
+        verbose:              bool,
+        #[serde(default)]
+        binary:               bool,
+        /// Also send the latest TWAP message of the feeds on every update.
+        #[serde(default)]
+        twap:                 bool,
+        /// Encoding of the update data sent when `binary` is set.
+        #[serde(default)]
+        encoding:             Encoding,
+        /// Minimum interval between two updates of the same feed, in milliseconds.
+        #[serde(default)]
+        min_interval_ms:      Option<u64>,
+        /// Only send the updates where the price moved by at least this many basis points, or
+        /// where the confidence changed.
+        #[serde(default)]
+        min_price_change_bps: Option<u64>,
+        /// Group the updates of a slot in a single `price_updates` message.
+        #[serde(default)]
+        batch:                bool,
+        /// Resume the subscription by first sending every stored update of the slots after this
+        /// one, e.g. the last slot received before a reconnection. The subscription fails
+        /// instead if the updates are no longer stored, or if there are too many or too old
+        /// ones to replay.
+        #[serde(default)]
+        from_slot:            Option<Slot>,
+        /// Same as `from_slot`, starting with the updates published at or after this time.
+        #[serde(default)]
+        from_publish_time:    Option<UnixTimestamp>,
+    },
+    #[serde(rename = "unsubscribe")]
+    Unsubscribe { ids: Vec<PriceIdInput> },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum ServerMessage {
+    #[serde(rename = "response")]
+    Response(ServerResponseMessage),
+    #[serde(rename = "price_update")]
+    PriceUpdate { price_feed: RpcPriceFeed },
+    #[serde(rename = "price_updates")]
+    PriceUpdates { price_feeds: Vec<RpcPriceFeed> },
+    #[serde(rename = "twap_update")]
+    TwapUpdate { twap: RpcTwap },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "status")]
+pub enum ServerResponseMessage {
+    #[serde(rename = "ok")]
+    Ok,
+    #[serde(rename = "error")]
+    Err {
+        error:   String,
+        /// Structured description of the error, the `error` message is kept for compatibility.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        details: Option<ErrorResponse>,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_encoding_encodes_bytes() {
+        assert_eq!(
+            Encoding::Base64.encode([0xde, 0xad, 0xbe, 0xef]),
+            "3q2+7w=="
+        );
+        assert_eq!(Encoding::Hex.encode([0xde, 0xad, 0xbe, 0xef]), "0xdeadbeef");
+        assert_eq!(Encoding::default(), Encoding::Base64);
+    }
+
+    #[test]
+    pub fn test_encoding_decodes_encoded_bytes() {
+        for encoding in [Encoding::Base64, Encoding::Hex] {
+            let encoded = encoding.encode([0xde, 0xad, 0xbe, 0xef]);
+            assert_eq!(
+                encoding.decode(&encoded).unwrap(),
+                vec![0xde, 0xad, 0xbe, 0xef]
+            );
+        }
+        assert!(Encoding::Hex.decode("0xzz").is_err());
+    }
+
+    #[test]
+    pub fn test_encoding_deserializes_from_query() {
+        #[derive(serde::Deserialize)]
+        struct Params {
+            #[serde(default)]
+            encoding: Encoding,
+        }
+
+        let params: Params = serde_qs::from_str("encoding=hex").unwrap();
+        assert_eq!(params.encoding, Encoding::Hex);
+        let params: Params = serde_qs::from_str("").unwrap();
+        assert_eq!(params.encoding, Encoding::Base64);
+        assert!(serde_qs::from_str::<Params>("encoding=base58").is_err());
+    }
+
+    #[test]
+    pub fn test_candle_resolution_deserializes_from_query() {
+        #[derive(serde::Deserialize)]
+        struct Params {
+            resolution: CandleResolution,
+        }
+
+        for resolution in [
+            CandleResolution::OneSecond,
+            CandleResolution::OneMinute,
+            CandleResolution::FiveMinutes,
+            CandleResolution::OneHour,
+        ] {
+            let params: Params =
+                serde_qs::from_str(&format!("resolution={}", resolution.as_str())).unwrap();
+            assert_eq!(params.resolution, resolution);
+        }
+        assert_eq!(CandleResolution::FiveMinutes.seconds(), 300);
+        assert!(serde_qs::from_str::<Params>("resolution=1d").is_err());
+    }
+
+    #[test]
+    pub fn test_error_response_serialization() {
+        let error = ErrorResponse::new(ErrorCode::UpdateDataNotFound, "Update data not found");
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"code":"update_data_not_found","message":"Update data not found"}"#
+        );
+
+        let error = ErrorResponse {
+            code:            ErrorCode::PublishTimeTooOld,
+            message:         "Publish time too old".to_string(),
+            feed_ids:        vec![PriceIdentifier::new([1; 32])],
+            retained_window: Some(TimeWindow {
+                start: 10,
+                end:   20,
+            }),
+        };
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(
+            json,
+            format!(
+                r#"{{"code":"publish_time_too_old","message":"Publish time too old","feed_ids":["{}"],"retained_window":{{"start":10,"end":20}}}}"#,
+                "01".repeat(32)
+            )
+        );
+        assert_eq!(serde_json::from_str::<ErrorResponse>(&json).unwrap(), error);
+    }
+
+    #[test]
+    pub fn test_client_message_round_trips() {
+        let message = ClientMessage::Subscribe {
+            ids:                  vec![PriceIdInput::Id([1; 32])],
+            verbose:              true,
+            binary:               false,
+            twap:                 false,
+            encoding:             Encoding::Hex,
+            min_interval_ms:      None,
+            min_price_change_bps: None,
+            batch:                false,
+            from_slot:            Some(10),
+            from_publish_time:    None,
+        };
+
+        match serde_json::from_str(&serde_json::to_string(&message).unwrap()).unwrap() {
+            ClientMessage::Subscribe {
+                ids,
+                verbose,
+                encoding,
+                from_slot,
+                ..
+            } => {
+                assert_eq!(ids[0], PriceIdInput::Id([1; 32]));
+                assert!(verbose);
+                assert_eq!(encoding, Encoding::Hex);
+                assert_eq!(from_slot, Some(10));
+            }
+            _ => panic!("Unexpected message"),
+        }
+
+        // Only the ids are required, and they may be 0x-prefixed.
+        let message: ClientMessage = serde_json::from_str(&format!(
+            r#"{{"type": "subscribe", "ids": ["0x{}"]}}"#,
+            "01".repeat(32)
+        ))
+        .unwrap();
+        assert!(matches!(
+            message,
+            ClientMessage::Subscribe { batch: false, .. }
+        ));
+    }
+
+    #[test]
+    pub fn test_price_id_input_accepts_ids_and_symbols() {
+        let parse = |input: &str| serde_json::from_str::<PriceIdInput>(&format!(r#""{input}""#));
+
+        assert_eq!(
+            parse(&format!("0x{}", "AB".repeat(32))).unwrap(),
+            PriceIdInput::Id([0xab; 32])
+        );
+        assert_eq!(
+            parse("Crypto.BTC/USD").unwrap(),
+            PriceIdInput::Symbol("Crypto.BTC/USD".to_string())
+        );
+        assert_eq!(
+            parse("BTC/USD").unwrap(),
+            PriceIdInput::Symbol("BTC/USD".to_string())
+        );
+
+        // Inputs without a `/` are ids, malformed ones are rejected instead of taken as symbols.
+        assert!(parse("0xdead").is_err());
+        assert!(parse(&"z".repeat(64)).is_err());
+        assert!(parse("BTC").is_err());
+        assert!(parse("").is_err());
+
+        assert_eq!(
+            serde_json::to_string(&PriceIdInput::Symbol("BTC/USD".to_string())).unwrap(),
+            r#""BTC/USD""#
+        );
+    }
+}
@@ -0,0 +1,417 @@
+//! Local verification of the update data returned by Hermes.
+//!
+//! Update data (`AccumulatorUpdateData`) holds a VAA signed by the Wormhole guardians that
+//! attests a merkle root, and the messages of the update along with their merkle proofs. Checking
+//! the signatures and the proofs against a trusted guardian set gives the same guarantees as the
+//! on-chain Pyth contracts without trusting the Hermes instance that served the data.
+
+use {
+    anyhow::{
+        anyhow,
+        Result,
+    },
+    pythnet_sdk::{
+        accumulators::merkle::MerkleRoot,
+        hashers::keccak256_160::Keccak160,
+        messages::Message,
+        wire::{
+            from_slice,
+            v1::{
+                AccumulatorUpdateData,
+                Proof,
+                WormholeMessage,
+                WormholePayload,
+            },
+        },
+    },
+    secp256k1::{
+        ecdsa::{
+            RecoverableSignature,
+            RecoveryId,
+        },
+        Message as SecpMessage,
+        Secp256k1,
+    },
+    serde_wormhole::RawMessage,
+    sha3::{
+        Digest,
+        Keccak256,
+    },
+    wormhole_sdk::{
+        vaa::{
+            Body,
+            Header,
+            Signature,
+        },
+        Address,
+        Chain,
+        Vaa,
+    },
+};
+
+/// A Wormhole guardian set trusted to sign the update data.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct GuardianSet {
+    pub index: u32,
+    pub keys:  Vec<[u8; 20]>,
+}
+
+/// The messages of a verified update data, along with the slot of their merkle root.
+#[derive(Clone, PartialEq, Debug)]
+pub struct VerifiedUpdate {
+    pub slot:     u64,
+    pub messages: Vec<Message>,
+}
+
+/// Verifies the VAA of the update data against the guardian set and the merkle proof of each
+/// message against the root attested by the VAA, and returns the messages.
+pub fn verify_update_data(
+    update_data: &[u8],
+    guardian_set: &GuardianSet,
+) -> Result<VerifiedUpdate> {
+    let update_data = AccumulatorUpdateData::try_from_slice(update_data)
+        .map_err(|e| anyhow!("Invalid update data: {:?}", e))?;
+
+    let (vaa, updates) = match update_data.proof {
+        Proof::WormholeMerkle { vaa, updates } => (Vec::<u8>::from(vaa), updates),
+    };
+
+    let vaa = serde_wormhole::from_slice::<Vaa<&RawMessage>>(&vaa)?;
+    if vaa.emitter_chain != Chain::Pythnet
+        || vaa.emitter_address != Address(pythnet_sdk::ACCUMULATOR_EMITTER_ADDRESS)
+    {
+        return Err(anyhow!("VAA is not emitted by the Pythnet accumulator"));
+    }
+
+    let (header, body): (Header, Body<&RawMessage>) = vaa.into();
+    verify_signatures(&header, &body, guardian_set)?;
+
+    let WormholePayload::Merkle(root) = WormholeMessage::try_from_bytes(body.payload)
+        .map_err(|e| anyhow!("Invalid VAA payload: {:?}", e))?
+        .payload;
+
+    let merkle_root = MerkleRoot::<Keccak160>::new(root.root);
+    let messages = updates
+        .into_iter()
+        .map(|update| {
+            let message = Vec::<u8>::from(update.message);
+            if !merkle_root.check(update.proof, &message) {
+                return Err(anyhow!("Invalid merkle proof"));
+            }
+            from_slice::<byteorder::BE, Message>(&message)
+                .map_err(|e| anyhow!("Invalid message: {:?}", e))
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(VerifiedUpdate {
+        slot: root.slot,
+        messages,
+    })
+}
+
+/// Checks that a quorum of the guardian set signed the VAA body, the same way the Wormhole
+/// contracts do.
+fn verify_signatures(
+    header: &Header,
+    body: &Body<&RawMessage>,
+    guardian_set: &GuardianSet,
+) -> Result<()> {
+    if header.guardian_set_index != guardian_set.index {
+        return Err(anyhow!(
+            "VAA signed by guardian set {}, expected {}",
+            header.guardian_set_index,
+            guardian_set.index
+        ));
+    }
+
+    verify_guardian_signatures(
+        &body.digest()?.secp256k_hash,
+        &header.signatures,
+        &guardian_set.keys,
+    )
+}
+
+/// Why the guardian signatures of a VAA were rejected. It is returned through `anyhow`, so that
+/// callers can tell the failures apart with `downcast_ref`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum GuardianSignatureError {
+    /// The signatures are not sorted by strictly increasing guardian index.
+    Unsorted {
+        index:          u8,
+        previous_index: u8,
+    },
+    /// The signer of a signature could not be recovered.
+    Malformed { index: u8 },
+    /// Fewer guardians of the set than the quorum signed.
+    NoQuorum {
+        quorum:              usize,
+        num_correct_signers: usize,
+    },
+}
+
+impl std::fmt::Display for GuardianSignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GuardianSignatureError::Unsorted {
+                index,
+                previous_index,
+            } => write!(
+                f,
+                "Guardian signatures are not sorted by strictly increasing index: {index} after \
+                 {previous_index}"
+            ),
+            GuardianSignatureError::Malformed { index } => {
+                write!(f, "Malformed signature of guardian {index}")
+            }
+            GuardianSignatureError::NoQuorum {
+                quorum,
+                num_correct_signers,
+            } => write!(
+                f,
+                "Not enough correct signatures. Expected {quorum}, received {num_correct_signers}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GuardianSignatureError {
+}
+
+/// Checks that a quorum of the guardians with the given keys signed the VAA body hash. Like the
+/// Wormhole contracts, the signatures must be sorted by strictly increasing guardian index, so
+/// that a guardian cannot be counted twice. Fails with a `GuardianSignatureError`.
+pub fn verify_guardian_signatures(
+    hash: &[u8; 32],
+    signatures: &[Signature],
+    guardian_keys: &[[u8; 20]],
+) -> Result<()> {
+    let mut num_correct_signers = 0;
+    let mut previous_index = None;
+    for sig in signatures.iter() {
+        if let Some(previous_index) = previous_index.filter(|index| sig.index <= *index) {
+            return Err(GuardianSignatureError::Unsorted {
+                index: sig.index,
+                previous_index,
+            }
+            .into());
+        }
+        previous_index = Some(sig.index);
+
+        let signer = recover_signer(hash, &sig.signature)
+            .map_err(|_| GuardianSignatureError::Malformed { index: sig.index })?;
+        let signer_id: usize = sig.index.into();
+        if guardian_keys.get(signer_id) == Some(&signer) {
+            num_correct_signers += 1;
+        }
+    }
+
+    let quorum = quorum(guardian_keys.len());
+    if num_correct_signers < quorum {
+        return Err(GuardianSignatureError::NoQuorum {
+            quorum,
+            num_correct_signers,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Recovers the address of the guardian that produced a signature of the given VAA body hash.
+pub fn recover_signer(hash: &[u8; 32], signature: &[u8; 65]) -> Result<[u8; 20]> {
+    // Recover the public key from ecdsa signature from [u8; 65] that has (v, r, s) format
+    let recid = RecoveryId::from_i32(signature[64].into())?;
+    let pubkey: &[u8; 65] = &Secp256k1::new()
+        .recover_ecdsa(
+            &SecpMessage::from_slice(hash)?,
+            &RecoverableSignature::from_compact(&signature[..64], recid)?,
+        )?
+        .serialize_uncompressed();
+
+    // The address is the last 20 bytes of the Keccak256 hash of the public key
+    let mut keccak = Keccak256::new();
+    keccak.update(&pubkey[1..]);
+    let address: [u8; 32] = keccak.finalize().into();
+    Ok(address[address.len() - 20..].try_into()?)
+}
+
+/// Number of valid signatures a VAA needs from a guardian set of the given size.
+pub fn quorum(num_guardians: usize) -> usize {
+    (num_guardians * 2 + 2) / 3
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        secp256k1::{
+            PublicKey,
+            SecretKey,
+        },
+    };
+
+    fn guardian_secret_keys(num_guardians: u8) -> Vec<SecretKey> {
+        (1..=num_guardians)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect()
+    }
+
+    fn guardian_keys(secret_keys: &[SecretKey]) -> Vec<[u8; 20]> {
+        let secp = Secp256k1::new();
+        secret_keys
+            .iter()
+            .map(|secret_key| {
+                let pubkey = PublicKey::from_secret_key(&secp, secret_key);
+                let mut keccak = Keccak256::new();
+                keccak.update(&pubkey.serialize_uncompressed()[1..]);
+                let address: [u8; 32] = keccak.finalize().into();
+                address[12..].try_into().unwrap()
+            })
+            .collect()
+    }
+
+    /// Signs the hash with the given guardians, identified by their index in the set and their
+    /// key.
+    fn sign(hash: &[u8; 32], signers: &[(u8, &SecretKey)]) -> Vec<Signature> {
+        let secp = Secp256k1::new();
+        signers
+            .iter()
+            .map(|(index, secret_key)| {
+                let (recovery_id, compact) = secp
+                    .sign_ecdsa_recoverable(&SecpMessage::from_slice(hash).unwrap(), secret_key)
+                    .serialize_compact();
+                let mut signature = [0; 65];
+                signature[..64].copy_from_slice(&compact);
+                signature[64] = recovery_id.to_i32() as u8;
+                Signature {
+                    index: *index,
+                    signature,
+                }
+            })
+            .collect()
+    }
+
+
+    #[test]
+    pub fn test_verify_update_data_rejects_invalid_data() {
+        let guardian_set = GuardianSet {
+            index: 0,
+            keys:  vec![[1; 20]],
+        };
+
+        assert!(verify_update_data(&[], &guardian_set).is_err());
+        assert!(verify_update_data(b"PNAU\x01\x00\x00", &guardian_set).is_err());
+    }
+
+    #[test]
+    pub fn test_verify_guardian_signatures() {
+        let secret_keys = guardian_secret_keys(4);
+        let keys = guardian_keys(&secret_keys);
+        let hash = [7; 32];
+
+        // A quorum of 3 out of 4 guardians, in order.
+        let signatures = sign(
+            &hash,
+            &[
+                (0, &secret_keys[0]),
+                (1, &secret_keys[1]),
+                (3, &secret_keys[3]),
+            ],
+        );
+        assert!(verify_guardian_signatures(&hash, &signatures, &keys).is_ok());
+
+        // The signatures of another hash do not count.
+        assert!(verify_guardian_signatures(&[8; 32], &signatures, &keys).is_err());
+    }
+
+    #[test]
+    pub fn test_verify_guardian_signatures_rejects_below_quorum() {
+        let secret_keys = guardian_secret_keys(4);
+        let keys = guardian_keys(&secret_keys);
+        let hash = [7; 32];
+
+        let signatures = sign(&hash, &[(0, &secret_keys[0]), (1, &secret_keys[1])]);
+        assert_eq!(
+            verify_guardian_signatures(&hash, &signatures, &keys)
+                .unwrap_err()
+                .downcast_ref::<GuardianSignatureError>(),
+            Some(&GuardianSignatureError::NoQuorum {
+                quorum:              3,
+                num_correct_signers: 2,
+            })
+        );
+
+        // Signatures under the wrong index do not count either.
+        let signatures = sign(
+            &hash,
+            &[
+                (0, &secret_keys[0]),
+                (1, &secret_keys[1]),
+                (2, &secret_keys[3]),
+            ],
+        );
+        assert!(verify_guardian_signatures(&hash, &signatures, &keys).is_err());
+    }
+
+    #[test]
+    pub fn test_verify_guardian_signatures_rejects_duplicate_signers() {
+        let secret_keys = guardian_secret_keys(4);
+        let keys = guardian_keys(&secret_keys);
+        let hash = [7; 32];
+
+        // A single guardian signing three times is not a quorum.
+        let signatures = sign(
+            &hash,
+            &[
+                (0, &secret_keys[0]),
+                (0, &secret_keys[0]),
+                (0, &secret_keys[0]),
+            ],
+        );
+        assert!(verify_guardian_signatures(&hash, &signatures, &keys).is_err());
+
+        // The indices must be strictly increasing, even without duplicates.
+        let signatures = sign(
+            &hash,
+            &[
+                (1, &secret_keys[1]),
+                (0, &secret_keys[0]),
+                (3, &secret_keys[3]),
+            ],
+        );
+        assert_eq!(
+            verify_guardian_signatures(&hash, &signatures, &keys)
+                .unwrap_err()
+                .downcast_ref::<GuardianSignatureError>(),
+            Some(&GuardianSignatureError::Unsorted {
+                index:          0,
+                previous_index: 1,
+            })
+        );
+    }
+
+    #[test]
+    pub fn test_verify_guardian_signatures_rejects_wrong_guardian_set() {
+        let secret_keys = guardian_secret_keys(4);
+        let other_keys = guardian_keys(&guardian_secret_keys(8)[4..]);
+        let hash = [7; 32];
+
+        let signatures = sign(
+            &hash,
+            &[
+                (0, &secret_keys[0]),
+                (1, &secret_keys[1]),
+                (2, &secret_keys[2]),
+            ],
+        );
+        assert!(verify_guardian_signatures(&hash, &signatures, &other_keys).is_err());
+    }
+
+    #[test]
+    pub fn test_quorum() {
+        assert_eq!(quorum(19), 13);
+        assert_eq!(quorum(4), 3);
+        assert_eq!(quorum(1), 1);
+        assert_eq!(quorum(0), 0);
+    }
+}
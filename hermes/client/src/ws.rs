@@ -0,0 +1,270 @@
+//! Reconnecting stream of the Hermes WebSocket API.
+//!
+//! The stream keeps track of the slot of the last update received for each feed. When the
+//! connection drops, it reconnects and resumes the subscription from the oldest of these slots so
+//! that no update is missed, and drops the updates it already delivered.
+
+use {
+    crate::types::{
+        ClientMessage,
+        Encoding,
+        RpcPriceFeed,
+        ServerMessage,
+        ServerResponseMessage,
+        Slot,
+    },
+    anyhow::{
+        anyhow,
+        Result,
+    },
+    futures::{
+        SinkExt,
+        Stream,
+        StreamExt,
+    },
+    pyth_sdk::PriceIdentifier,
+    std::{
+        collections::HashMap,
+        pin::Pin,
+        task::{
+            Context,
+            Poll,
+        },
+        time::Duration,
+    },
+    tokio::sync::mpsc,
+    tokio_tungstenite::tungstenite::Message,
+};
+
+/// Delay before reconnecting after the connection drops.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MESSAGES_CHAN_LEN: usize = 1000;
+
+/// Options of a WebSocket subscription. Subscriptions are always verbose, as the slots of the
+/// updates are needed to resume them.
+#[derive(Debug, Clone, Default)]
+pub struct Subscription {
+    pub ids:                  Vec<PriceIdentifier>,
+    pub binary:               bool,
+    pub twap:                 bool,
+    pub encoding:             Encoding,
+    pub min_interval_ms:      Option<u64>,
+    pub min_price_change_bps: Option<u64>,
+    pub batch:                bool,
+}
+
+impl Subscription {
+    fn message(&self, from_slot: Option<Slot>) -> ClientMessage {
+        ClientMessage::Subscribe {
+            ids: self.ids.iter().map(|id| (*id).into()).collect(),
+            verbose: true,
+            binary: self.binary,
+            twap: self.twap,
+            encoding: self.encoding,
+            min_interval_ms: self.min_interval_ms,
+            min_price_change_bps: self.min_price_change_bps,
+            batch: self.batch,
+            from_slot,
+            from_publish_time: None,
+        }
+    }
+}
+
+/// Stream of the messages sent by Hermes for a subscription, across reconnections. Responses to
+/// the subscription requests are included, an error response after a reconnection means that the
+/// missed updates could not be replayed.
+pub struct PriceFeedStream {
+    receiver: mpsc::Receiver<ServerMessage>,
+}
+
+impl Stream for PriceFeedStream {
+    type Item = ServerMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Subscribes to the WebSocket API at `ws_url` (e.g. `wss://hermes.pyth.network/ws`). The
+/// connection is handled by a background task that stops once the stream is dropped.
+pub fn subscribe(ws_url: impl Into<String>, subscription: Subscription) -> PriceFeedStream {
+    let (sender, receiver) = mpsc::channel(MESSAGES_CHAN_LEN);
+    tokio::spawn(run(ws_url.into(), subscription, sender));
+    PriceFeedStream { receiver }
+}
+
+async fn run(ws_url: String, subscription: Subscription, sender: mpsc::Sender<ServerMessage>) {
+    let mut resume_state = ResumeState::default();
+
+    loop {
+        match forward_messages(&ws_url, &subscription, &mut resume_state, &sender).await {
+            Ok(()) => return,
+            Err(e) => log::warn!("Hermes WebSocket connection lost: {:?}", e),
+        }
+
+        if sender.is_closed() {
+            return;
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Connects and forwards the messages until the connection drops, which returns an error, or the
+/// stream is dropped.
+async fn forward_messages(
+    ws_url: &str,
+    subscription: &Subscription,
+    resume_state: &mut ResumeState,
+    sender: &mpsc::Sender<ServerMessage>,
+) -> Result<()> {
+    let (mut socket, _) = tokio_tungstenite::connect_async(ws_url).await?;
+
+    let from_slot = resume_state.resume_slot();
+    socket
+        .send(Message::Text(serde_json::to_string(
+            &subscription.message(from_slot),
+        )?))
+        .await?;
+    let mut resuming = from_slot.is_some();
+
+    while let Some(message) = socket.next().await {
+        let message = match message? {
+            Message::Text(text) => serde_json::from_str::<ServerMessage>(&text)?,
+            Message::Close(_) => return Err(anyhow!("Connection closed by Hermes")),
+            _ => continue,
+        };
+
+        if let ServerMessage::Response(ref response) = message {
+            // The resume point is no longer stored by Hermes, so the subscription restarts from
+            // the live updates. The error is still forwarded to report the gap.
+            if resuming {
+                if let ServerResponseMessage::Err { error, .. } = response {
+                    log::warn!("Failed to resume the subscription: {}", error);
+                    socket
+                        .send(Message::Text(serde_json::to_string(
+                            &subscription.message(None),
+                        )?))
+                        .await?;
+                }
+            }
+            resuming = false;
+        }
+
+        if let Some(message) = resume_state.filter(message) {
+            if sender.send(message).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    Err(anyhow!("Connection closed"))
+}
+
+/// Slot of the last update delivered for each feed.
+#[derive(Debug, Default)]
+struct ResumeState {
+    last_slots: HashMap<PriceIdentifier, Slot>,
+}
+
+impl ResumeState {
+    /// The subscription resumes after the oldest of the last slots, as the newer slots might be
+    /// missing updates of the other feeds.
+    fn resume_slot(&self) -> Option<Slot> {
+        self.last_slots.values().min().copied()
+    }
+
+    /// Records the slots of the price updates and drops the ones that were already delivered.
+    fn filter(&mut self, message: ServerMessage) -> Option<ServerMessage> {
+        match message {
+            ServerMessage::PriceUpdate { price_feed } => self
+                .is_new(&price_feed)
+                .then_some(ServerMessage::PriceUpdate { price_feed }),
+            ServerMessage::PriceUpdates { price_feeds } => {
+                let price_feeds: Vec<_> = price_feeds
+                    .into_iter()
+                    .filter(|price_feed| self.is_new(price_feed))
+                    .collect();
+                (!price_feeds.is_empty()).then_some(ServerMessage::PriceUpdates { price_feeds })
+            }
+            message => Some(message),
+        }
+    }
+
+    fn is_new(&mut self, price_feed: &RpcPriceFeed) -> bool {
+        let slot = match &price_feed.metadata {
+            Some(metadata) => metadata.slot,
+            None => return true,
+        };
+
+        match self.last_slots.get(&price_feed.id) {
+            Some(last_slot) if *last_slot >= slot => false,
+            _ => {
+                self.last_slots.insert(price_feed.id, slot);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::types::RpcPriceFeedMetadata,
+        pyth_sdk::Price,
+    };
+
+    fn price_feed(id: u8, slot: Slot) -> RpcPriceFeed {
+        RpcPriceFeed {
+            id:        PriceIdentifier::new([id; 32]),
+            price:     Price::default(),
+            ema_price: Price::default(),
+            metadata:  Some(RpcPriceFeedMetadata {
+                slot,
+                emitter_chain: 26,
+                price_service_receive_time: 0,
+            }),
+            vaa:       None,
+        }
+    }
+
+    #[test]
+    pub fn test_resume_state_drops_delivered_updates() {
+        let mut resume_state = ResumeState::default();
+        assert_eq!(resume_state.resume_slot(), None);
+
+        assert!(resume_state
+            .filter(ServerMessage::PriceUpdate {
+                price_feed: price_feed(1, 10),
+            })
+            .is_some());
+        assert!(resume_state
+            .filter(ServerMessage::PriceUpdate {
+                price_feed: price_feed(2, 12),
+            })
+            .is_some());
+        assert_eq!(resume_state.resume_slot(), Some(10));
+
+        // Replayed updates are dropped, the new ones of a batch are kept.
+        assert!(resume_state
+            .filter(ServerMessage::PriceUpdate {
+                price_feed: price_feed(1, 10),
+            })
+            .is_none());
+        match resume_state.filter(ServerMessage::PriceUpdates {
+            price_feeds: vec![price_feed(1, 12), price_feed(2, 12)],
+        }) {
+            Some(ServerMessage::PriceUpdates { price_feeds }) => {
+                assert_eq!(price_feeds.len(), 1);
+                assert_eq!(price_feeds[0].id, PriceIdentifier::new([1; 32]));
+            }
+            _ => panic!("Unexpected message"),
+        }
+        assert_eq!(resume_state.resume_slot(), Some(12));
+
+        // Other messages go through.
+        assert!(resume_state
+            .filter(ServerMessage::Response(ServerResponseMessage::Ok))
+            .is_some());
+    }
+}
@@ -1,88 +1,240 @@
 use {
-    self::ws::notify_updates,
-    crate::store::Store,
+    self::{
+        rate_limit::RateLimiter,
+        ws::notify_updates,
+    },
+    crate::{
+        config::profile::RateLimitConfig,
+        store::{
+            types::UpdateEvent,
+            Store,
+        },
+    },
     anyhow::Result,
     axum::{
-        routing::get,
+        http::HeaderValue,
+        middleware,
+        routing::{
+            get,
+            post,
+        },
         Router,
     },
-    std::sync::Arc,
-    tokio::{
-        signal,
-        sync::mpsc::Receiver,
+    std::{
+        net::SocketAddr,
+        sync::Arc,
+        time::{
+            Duration,
+            Instant,
+        },
+    },
+    tokio::sync::{
+        broadcast,
+        mpsc::Receiver,
+        watch,
     },
-    tower_http::cors::CorsLayer,
+    tower_http::cors::{
+        AllowOrigin,
+        CorsLayer,
+    },
+    utoipa_swagger_ui::SwaggerUi,
 };
 
+mod error;
+mod extract;
+mod metadata;
+mod metrics_middleware;
+mod rate_limit;
 mod rest;
+mod sse;
 mod types;
+mod v2;
+mod verify;
 mod ws;
 
 #[derive(Clone)]
 pub struct State {
-    pub store: Arc<Store>,
-    pub ws:    Arc<ws::WsState>,
+    pub store:        Arc<Store>,
+    pub ws:           Arc<ws::WsState>,
+    /// Notifies the Server-Sent Events streams of new updates.
+    pub sse_tx:       broadcast::Sender<Arc<UpdateEvent>>,
+    /// Rate limits of the API, `None` when it is not rate limited.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub exit_rx:      watch::Receiver<bool>,
 }
 
 impl State {
-    pub fn new(store: Arc<Store>) -> Self {
+    pub fn new(
+        store: Arc<Store>,
+        rate_limit: Option<RateLimitConfig>,
+        exit_rx: watch::Receiver<bool>,
+    ) -> Self {
         Self {
             store,
             ws: Arc::new(ws::WsState::new()),
+            sse_tx: broadcast::channel(sse::NOTIFICATIONS_CHAN_LEN).0,
+            rate_limiter: rate_limit.map(|config| Arc::new(RateLimiter::new(config))),
+            exit_rx,
         }
     }
 }
 
+/// How long to wait for the WebSocket subscribers to close their connections on shutdown.
+const WS_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the rate limiter drops the state of the idle clients.
+const RATE_LIMITER_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
 /// This method provides a background service that responds to REST requests
 ///
 /// Currently this is based on Axum due to the simplicity and strong ecosystem support for the
 /// packages they are based on (tokio & hyper).
-pub async fn run(store: Arc<Store>, mut update_rx: Receiver<()>, rpc_addr: String) -> Result<()> {
-    let state = State::new(store);
+///
+/// The server stops accepting new connections once `exit_rx` signals a shutdown and returns after
+/// the in-flight requests are served and the WebSocket subscribers are closed.
+pub async fn run(
+    store: Arc<Store>,
+    mut update_rx: Receiver<UpdateEvent>,
+    rpc_addr: String,
+    cors_origins: Option<Vec<HeaderValue>>,
+    rate_limit: Option<RateLimitConfig>,
+    exit_rx: watch::Receiver<bool>,
+) -> Result<()> {
+    let state = State::new(store, rate_limit, exit_rx.clone());
+
+    // Allow all origins unless they are restricted by the configuration.
+    let cors = match cors_origins {
+        Some(origins) => CorsLayer::permissive().allow_origin(AllowOrigin::list(origins)),
+        None => CorsLayer::permissive(),
+    };
 
     // Initialize Axum Router. Note the type here is a `Router<State>` due to the use of the
     // `with_state` method which replaces `Body` with `State` in the type signature.
     let app = Router::new();
     let app = app
-        .route("/", get(rest::index))
-        .route("/live", get(rest::live))
-        .route("/ready", get(rest::ready))
         .route("/ws", get(ws::ws_route_handler))
+        .route("/api/price_feeds/stream", get(sse::price_feeds_stream))
         .route("/api/latest_price_feeds", get(rest::latest_price_feeds))
         .route("/api/latest_vaas", get(rest::latest_vaas))
         .route("/api/get_price_feed", get(rest::get_price_feed))
+        .route(
+            "/api/get_price_feeds_range",
+            get(rest::get_price_feeds_range),
+        )
+        .route("/api/latest_twaps", get(rest::latest_twaps))
+        .route("/api/get_twap", get(rest::get_twap))
+        .route("/api/get_twap_window", get(rest::get_twap_window))
+        .route("/api/get_candles", get(rest::get_candles))
         .route("/api/get_vaa", get(rest::get_vaa))
         .route("/api/get_vaa_ccip", get(rest::get_vaa_ccip))
         .route("/api/price_feed_ids", get(rest::price_feed_ids))
+        .route(
+            "/api/price_feeds/metadata",
+            get(metadata::price_feeds_metadata),
+        )
+        .route("/api/verify_update_data", post(verify::verify_update_data))
+        .route("/v2/price_feeds/ids", get(v2::price_feed_ids))
+        .route("/v2/price_feeds/metadata", get(v2::price_feeds_metadata))
+        .route("/v2/price_feeds/latest", get(v2::latest_price_feeds))
+        .route("/v2/price_feeds/at_time", get(v2::price_feeds_at_time))
+        .route("/v2/price_feeds/range", get(v2::price_feeds_range))
+        .route("/v2/price_feeds/stream", get(v2::price_feeds_stream))
+        .route("/v2/updates/latest", get(v2::latest_updates))
+        .route("/v2/updates/at_time", get(v2::updates_at_time))
+        .route("/v2/updates/aggregated", get(v2::aggregated_updates))
+        .route("/v2/updates/vaa", get(v2::vaa))
+        .route("/v2/updates/ccip", get(v2::vaa_ccip))
+        .route("/v2/updates/verify", post(v2::verify_updates))
+        .route("/v2/twaps/latest", get(v2::latest_twaps))
+        .route("/v2/twaps/window", get(v2::twap_window))
+        .route("/v2/candles", get(v2::candles))
+        .route("/v2/ws", get(v2::ws_subscribe))
+        // Only the routes above are rate limited, the health checks and metrics are not.
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::limit_requests,
+        ))
+        .route("/", get(rest::index))
+        .route("/live", get(rest::live))
+        .route("/ready", get(rest::ready))
+        .route("/metrics", get(rest::metrics))
+        .route_layer(middleware::from_fn(
+            metrics_middleware::track_request_latency,
+        ))
         .with_state(state.clone())
-        .layer(CorsLayer::permissive()); // Permissive CORS layer to allow all origins
+        // Swagger UI at `/docs`, showing the OpenAPI document of the `/v2` API.
+        .merge(SwaggerUi::new("/docs").url("/docs/openapi.json", v2::OPENAPI.clone()))
+        .layer(cors);
 
+    // Dispatch the update events of the store to the websocket subscribers and SSE streams.
+    {
+        let ws_state = state.ws.clone();
+        let sse_tx = state.sse_tx.clone();
+        let mut exit_rx = exit_rx.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    update = update_rx.recv() => {
+                        // Panics if the update channel is closed, which should never happen.
+                        // If it happens we have no way to recover, so we just panic.
+                        let event = Arc::new(update.expect("state update channel is closed"));
+                        notify_updates(ws_state.clone(), event.clone()).await;
+                        // Sending only fails when there are no SSE streams open.
+                        let _ = sse_tx.send(event);
+                    }
+                    _ = exit_rx.changed() => break,
+                }
+            }
+        });
+    }
 
-    // Call dispatch updates to websocket every 1 seconds
-    // FIXME use a channel to get updates from the store
-    tokio::spawn(async move {
-        loop {
-            // Panics if the update channel is closed, which should never happen.
-            // If it happens we have no way to recover, so we just panic.
-            update_rx
-                .recv()
-                .await
-                .expect("state update channel is closed");
-
-            notify_updates(state.ws.clone()).await;
-        }
-    });
+    // Drop the rate limits of the idle clients so that their number stays bounded.
+    if let Some(rate_limiter) = state.rate_limiter.clone() {
+        let mut exit_rx = exit_rx.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(RATE_LIMITER_PURGE_INTERVAL) => {
+                        rate_limiter.purge_idle(Instant::now());
+                    }
+                    _ = exit_rx.changed() => break,
+                }
+            }
+        });
+    }
 
     // Binds the axum's server to the configured address and port. This is a blocking call and will
     // not return until the server is shutdown.
+    let mut shutdown_rx = exit_rx.clone();
     axum::Server::try_bind(&rpc_addr.parse()?)?
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(async {
-            signal::ctrl_c()
-                .await
-                .expect("Ctrl-c signal handler failed.");
+        // The address of the clients is needed to rate limit them.
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            while !*shutdown_rx.borrow() {
+                if shutdown_rx.changed().await.is_err() {
+                    break;
+                }
+            }
+            log::info!("Shutting down RPC server...");
         })
         .await?;
 
+    // Upgraded WebSocket connections are not tracked by the server so we wait for the subscribers
+    // to send their close frames separately.
+    let wait_for_subscribers = async {
+        while !state.ws.subscribers.is_empty() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    if tokio::time::timeout(WS_SHUTDOWN_TIMEOUT, wait_for_subscribers)
+        .await
+        .is_err()
+    {
+        log::warn!(
+            "{} WebSocket subscribers did not close in time",
+            state.ws.subscribers.len()
+        );
+    }
+
     Ok(())
 }
@@ -0,0 +1,378 @@
+//! Errors of the REST and WebSocket APIs.
+//!
+//! They are returned as an `ErrorResponse` with a machine-readable code and, when they concern
+//! some price feeds, the ids of the feeds and the publish times retained for them. The store
+//! failures are told apart by downcasting them to a `LookupError`, any other failure is internal.
+
+use {
+    super::types::{
+        ErrorCode,
+        ErrorResponse,
+        ServerMessage,
+        ServerResponseMessage,
+        TimeWindow,
+    },
+    crate::store::types::{
+        LookupError,
+        Slot,
+    },
+    axum::{
+        extract::rejection::JsonRejection,
+        http::{
+            header,
+            StatusCode,
+        },
+        response::{
+            IntoResponse,
+            Response,
+        },
+        Json,
+    },
+    pyth_sdk::PriceIdentifier,
+    serde_qs::axum::QsQueryRejection,
+    std::time::Duration,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum ApiError {
+    InvalidParams(String),
+    PriceFeedNotFound(Vec<PriceIdentifier>),
+    /// The window is `None` if the retained windows of the feeds do not overlap.
+    PublishTimeTooOld {
+        feed_ids:        Vec<PriceIdentifier>,
+        retained_window: Option<TimeWindow>,
+    },
+    PublishTimeInFuture {
+        feed_ids:        Vec<PriceIdentifier>,
+        retained_window: Option<TimeWindow>,
+    },
+    UpdateDataNotFound,
+    NoCommonSlot {
+        latest_slot:  Slot,
+        max_slot_lag: Slot,
+    },
+    /// Same as `UpdateDataNotFound`, for the CCIP gateway which only retries on server errors.
+    CcipUpdateDataNotFound,
+    InvalidUpdateData,
+    InvalidRange,
+    InvalidTwapWindow,
+    HistoryUnavailable,
+    InvalidApiKey,
+    RateLimited {
+        retry_after: Duration,
+    },
+    UnknownSymbol(String),
+    AmbiguousSymbol {
+        symbol:  String,
+        matches: Vec<String>,
+    },
+    /// An unexpected failure, such as update data that cannot be built. It is logged rather than
+    /// described to the client.
+    Internal,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidParams(_)
+            | ApiError::InvalidUpdateData
+            | ApiError::InvalidRange
+            | ApiError::InvalidTwapWindow
+            | ApiError::AmbiguousSymbol { .. } => StatusCode::BAD_REQUEST,
+            ApiError::PriceFeedNotFound(_)
+            | ApiError::UnknownSymbol(_)
+            | ApiError::PublishTimeTooOld { .. }
+            | ApiError::PublishTimeInFuture { .. }
+            | ApiError::UpdateDataNotFound
+            | ApiError::NoCommonSlot { .. } => StatusCode::NOT_FOUND,
+            // Returning Bad Gateway error because CCIP expects a 5xx error if it needs to
+            // retry or try other endpoints. Bad Gateway seems the best choice here as this
+            // is not an internal error and could happen on two scenarios:
+            // 1. DB Api is not responding well (Bad Gateway is appropriate here)
+            // 2. Publish time is a few seconds before current time and a VAA
+            //    Will be available in a few seconds. So we want the client to retry.
+            ApiError::CcipUpdateDataNotFound => StatusCode::BAD_GATEWAY,
+            ApiError::HistoryUnavailable => StatusCode::NOT_IMPLEMENTED,
+            ApiError::InvalidApiKey => StatusCode::UNAUTHORIZED,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn to_error_response(&self) -> ErrorResponse {
+        match self {
+            ApiError::InvalidParams(message) => {
+                ErrorResponse::new(ErrorCode::InvalidParams, message.clone())
+            }
+            ApiError::PriceFeedNotFound(feed_ids) => ErrorResponse {
+                feed_ids: feed_ids.clone(),
+                ..ErrorResponse::new(ErrorCode::PriceFeedNotFound, "Price feeds not found")
+            },
+            ApiError::PublishTimeTooOld {
+                feed_ids,
+                retained_window,
+            } => ErrorResponse {
+                feed_ids: feed_ids.clone(),
+                retained_window: *retained_window,
+                ..ErrorResponse::new(
+                    ErrorCode::PublishTimeTooOld,
+                    match retained_window {
+                        Some(window) => format!(
+                            "Publish time older than the retained updates, which start at {}",
+                            window.start
+                        ),
+                        None => "Publish time older than the retained updates, which have no \
+                                 publish time in common"
+                            .to_string(),
+                    },
+                )
+            },
+            ApiError::PublishTimeInFuture {
+                feed_ids,
+                retained_window,
+            } => ErrorResponse {
+                feed_ids: feed_ids.clone(),
+                retained_window: *retained_window,
+                ..ErrorResponse::new(
+                    ErrorCode::PublishTimeInFuture,
+                    match retained_window {
+                        Some(window) => format!(
+                            "No update published at or after the publish time yet, the latest \
+                             one is at {}",
+                            window.end
+                        ),
+                        None => "No update published at or after the publish time yet".to_string(),
+                    },
+                )
+            },
+            ApiError::UpdateDataNotFound => {
+                ErrorResponse::new(ErrorCode::UpdateDataNotFound, "Update data not found")
+            }
+            ApiError::NoCommonSlot {
+                latest_slot,
+                max_slot_lag,
+            } => ErrorResponse::new(
+                ErrorCode::NoCommonSlot,
+                format!(
+                    "No slot within {max_slot_lag} slots of {latest_slot} has an update of every \
+                     price feed"
+                ),
+            ),
+            ApiError::CcipUpdateDataNotFound => {
+                ErrorResponse::new(ErrorCode::UpdateDataNotFound, "CCIP update data not found")
+            }
+            ApiError::InvalidUpdateData => {
+                ErrorResponse::new(ErrorCode::InvalidUpdateData, "Invalid update data")
+            }
+            ApiError::InvalidRange => {
+                ErrorResponse::new(ErrorCode::InvalidRange, "Invalid time range")
+            }
+            ApiError::InvalidTwapWindow => {
+                ErrorResponse::new(ErrorCode::InvalidTwapWindow, "Invalid TWAP window")
+            }
+            ApiError::HistoryUnavailable => ErrorResponse::new(
+                ErrorCode::HistoryUnavailable,
+                "This request requires a persistent storage backend",
+            ),
+            ApiError::InvalidApiKey => {
+                ErrorResponse::new(ErrorCode::InvalidApiKey, "Unknown API key")
+            }
+            ApiError::RateLimited { retry_after } => ErrorResponse::new(
+                ErrorCode::RateLimited,
+                format!(
+                    "Rate limit exceeded, retry in {}ms",
+                    retry_after.as_millis()
+                ),
+            ),
+            ApiError::UnknownSymbol(symbol) => ErrorResponse::new(
+                ErrorCode::UnknownSymbol,
+                format!("No price feed has the symbol {symbol}"),
+            ),
+            ApiError::AmbiguousSymbol { symbol, matches } => ErrorResponse::new(
+                ErrorCode::AmbiguousSymbol,
+                format!(
+                    "Several price feeds have the symbol {}, use one of: {}",
+                    symbol,
+                    matches.join(", ")
+                ),
+            ),
+            ApiError::Internal => ErrorResponse::new(ErrorCode::Internal, "Internal error"),
+        }
+    }
+
+    /// The WebSocket error response, which keeps the message in `error` for the clients that
+    /// predate the structured errors.
+    pub fn to_server_message(&self) -> ServerMessage {
+        let details = self.to_error_response();
+        ServerMessage::Response(ServerResponseMessage::Err {
+            error:   details.message.clone(),
+            details: Some(details),
+        })
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let response = (self.status(), Json(self.to_error_response()));
+        match self {
+            ApiError::RateLimited { retry_after } => {
+                // Rounded up so that retrying after the advertised delay succeeds.
+                let retry_after = retry_after.as_secs_f64().ceil() as u64;
+                ([(header::RETRY_AFTER, retry_after.to_string())], response).into_response()
+            }
+            _ => response.into_response(),
+        }
+    }
+}
+
+impl From<QsQueryRejection> for ApiError {
+    fn from(rejection: QsQueryRejection) -> Self {
+        ApiError::InvalidParams(rejection.to_string())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::InvalidParams(rejection.body_text())
+    }
+}
+
+impl From<LookupError> for ApiError {
+    fn from(error: LookupError) -> Self {
+        let window = |window: crate::store::types::TimeWindow| TimeWindow {
+            start: window.start,
+            end:   window.end,
+        };
+
+        match error {
+            LookupError::FeedsNotFound(feed_ids) => ApiError::PriceFeedNotFound(feed_ids),
+            LookupError::PublishTimeTooOld {
+                feed_ids,
+                retained_window,
+            } => ApiError::PublishTimeTooOld {
+                feed_ids,
+                retained_window: retained_window.map(window),
+            },
+            LookupError::PublishTimeInFuture {
+                feed_ids,
+                retained_window,
+            } => ApiError::PublishTimeInFuture {
+                feed_ids,
+                retained_window: retained_window.map(window),
+            },
+            LookupError::NoCommonSlot {
+                latest_slot,
+                max_slot_lag,
+            } => ApiError::NoCommonSlot {
+                latest_slot,
+                max_slot_lag,
+            },
+            LookupError::UnknownSymbol(symbol) => ApiError::UnknownSymbol(symbol),
+            LookupError::AmbiguousSymbol { symbol, matches } => {
+                ApiError::AmbiguousSymbol { symbol, matches }
+            }
+        }
+    }
+}
+
+/// The store returns its lookup failures as a `LookupError`, anything else is unexpected.
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<LookupError>() {
+            Ok(error) => error.into(),
+            Err(error) => {
+                log::error!("Internal error while handling a request: {:?}", error);
+                ApiError::Internal
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        anyhow::anyhow,
+    };
+
+    #[test]
+    pub fn test_api_errors_have_a_code() {
+        let error = ApiError::InvalidParams("missing field `ids`".to_string());
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.to_error_response().code, ErrorCode::InvalidParams);
+        assert_eq!(error.to_error_response().message, "missing field `ids`");
+
+        assert_eq!(ApiError::UpdateDataNotFound.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            ApiError::HistoryUnavailable.to_error_response().code,
+            ErrorCode::HistoryUnavailable
+        );
+    }
+
+    #[test]
+    pub fn test_store_errors_are_told_apart() {
+        let feed_ids = vec![PriceIdentifier::new([1; 32])];
+
+        let error = ApiError::from(anyhow::Error::from(LookupError::FeedsNotFound(
+            feed_ids.clone(),
+        )));
+        assert_eq!(error, ApiError::PriceFeedNotFound(feed_ids.clone()));
+        assert_eq!(error.to_error_response().feed_ids, feed_ids);
+
+        let error = ApiError::from(anyhow::Error::from(LookupError::PublishTimeTooOld {
+            feed_ids,
+            retained_window: Some(crate::store::types::TimeWindow {
+                start: 10,
+                end:   20,
+            }),
+        }));
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+        let response = error.to_error_response();
+        assert_eq!(response.code, ErrorCode::PublishTimeTooOld);
+        assert_eq!(
+            response.retained_window,
+            Some(TimeWindow {
+                start: 10,
+                end:   20,
+            })
+        );
+
+        let error = ApiError::from(anyhow::Error::from(LookupError::AmbiguousSymbol {
+            symbol:  "ETH/USD".to_string(),
+            matches: vec![
+                "Crypto.ETH/USD".to_string(),
+                "Equity.US.ETH/USD".to_string(),
+            ],
+        }));
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.to_error_response().code, ErrorCode::AmbiguousSymbol);
+
+        let error = ApiError::from(anyhow::Error::from(LookupError::NoCommonSlot {
+            latest_slot:  100,
+            max_slot_lag: 10,
+        }));
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+        let response = error.to_error_response();
+        assert_eq!(response.code, ErrorCode::NoCommonSlot);
+        assert_eq!(
+            response.message,
+            "No slot within 10 slots of 100 has an update of every price feed"
+        );
+
+        let error = ApiError::from(anyhow!("Failed to serialize the update data"));
+        assert_eq!(error, ApiError::Internal);
+        assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    pub fn test_ws_error_keeps_the_message() {
+        let message = ApiError::InvalidRange.to_server_message();
+        match message {
+            ServerMessage::Response(ServerResponseMessage::Err { error, details }) => {
+                assert_eq!(error, "Invalid time range");
+                assert_eq!(details.unwrap().code, ErrorCode::InvalidRange);
+            }
+            _ => panic!("Expected an error response"),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Extractors of the request parameters that reject malformed ones with an `ApiError`, so that
+//! they get the same `ErrorResponse` as the other errors instead of the plain text rejections of
+//! `QsQuery` and `Json`.
+
+use {
+    super::error::ApiError,
+    axum::{
+        async_trait,
+        extract::{
+            rejection::JsonRejection,
+            FromRequest,
+            FromRequestParts,
+        },
+        http::{
+            request::Parts,
+            Request,
+        },
+        Json,
+    },
+    serde_qs::axum::{
+        QsQuery,
+        QsQueryRejection,
+    },
+};
+
+/// The query parameters of a request, parsed with `serde_qs` like `QsQuery`.
+#[derive(Debug)]
+pub struct Query<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    QsQuery<T>: FromRequestParts<S, Rejection = QsQueryRejection>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let QsQuery(query) = QsQuery::<T>::from_request_parts(parts, state).await?;
+        Ok(Self(query))
+    }
+}
+
+/// The JSON body of a request.
+#[derive(Debug)]
+pub struct JsonBody<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for JsonBody<T>
+where
+    Json<T>: FromRequest<S, B, Rejection = JsonRejection>,
+    S: Send + Sync,
+    B: Send + 'static,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(body) = Json::<T>::from_request(request, state).await?;
+        Ok(Self(body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        axum::{
+            body::Body,
+            http::header,
+        },
+    };
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Params {
+        publish_time: i64,
+    }
+
+    #[tokio::test]
+    pub async fn test_malformed_parameters_are_api_errors() {
+        let (mut parts, _) = Request::builder()
+            .uri("/?publish_time=10")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let Query(params) = Query::<Params>::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        assert_eq!(params.publish_time, 10);
+
+        let (mut parts, _) = Request::builder()
+            .uri("/?publish_time=yesterday")
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert!(matches!(
+            Query::<Params>::from_request_parts(&mut parts, &()).await,
+            Err(ApiError::InvalidParams(_))
+        ));
+
+        let request = Request::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"publish_time": "yesterday"}"#))
+            .unwrap();
+        assert!(matches!(
+            JsonBody::<Params>::from_request(request, &()).await,
+            Err(ApiError::InvalidParams(_))
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+//! Metadata of the price feeds, and the symbols accepted wherever a price feed id is.
+//!
+//! Symbols are resolved with the metadata loaded in the store, which is empty unless a source of
+//! metadata is configured. Otherwise only the hex encoded ids are accepted. Malformed ids are
+//! rejected with the parameters, when the `PriceIdInput` is deserialized.
+
+use {
+    super::{
+        error::ApiError,
+        extract::Query,
+        types::{
+            PriceIdInput,
+            RpcProductMetadata,
+            RpcProductMetadataExt,
+        },
+    },
+    crate::store::Store,
+    axum::{
+        extract::State,
+        Json,
+    },
+    pyth_sdk::PriceIdentifier,
+};
+
+pub async fn resolve_price_id(
+    store: &Store,
+    id: PriceIdInput,
+) -> Result<PriceIdentifier, ApiError> {
+    match id {
+        PriceIdInput::Id(id) => Ok(PriceIdentifier::new(id)),
+        PriceIdInput::Symbol(symbol) => Ok(store.resolve_symbol(&symbol).await?),
+    }
+}
+
+pub async fn resolve_price_ids(
+    store: &Store,
+    ids: Vec<PriceIdInput>,
+) -> Result<Vec<PriceIdentifier>, ApiError> {
+    let mut price_ids = Vec::with_capacity(ids.len());
+    for id in ids {
+        price_ids.push(resolve_price_id(store, id).await?);
+    }
+    Ok(price_ids)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PriceFeedsMetadataQueryParams {
+    /// Case-insensitive substring of the symbol, base, quote or description of the feeds.
+    query:       Option<String>,
+    asset_class: Option<String>,
+}
+
+pub async fn price_feeds_metadata(
+    State(state): State<super::State>,
+    Query(params): Query<PriceFeedsMetadataQueryParams>,
+) -> Json<Vec<RpcProductMetadata>> {
+    Json(
+        state
+            .store
+            .search_price_feed_metadata(params.query.as_deref(), params.asset_class.as_deref())
+            .await
+            .into_iter()
+            .map(RpcProductMetadata::from_product_metadata)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::store::{
+            metadata::ProductMetadata,
+            storage::local_storage::LocalStorage,
+        },
+    };
+
+    #[tokio::test]
+    pub async fn test_resolve_price_ids() {
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(10);
+        let store = Store::new(update_tx, LocalStorage::new_instance(10), None);
+
+        // Without metadata only the ids are accepted.
+        assert_eq!(
+            resolve_price_id(&store, PriceIdInput::Symbol("BTC/USD".to_string())).await,
+            Err(ApiError::UnknownSymbol("BTC/USD".to_string()))
+        );
+
+        store
+            .update_price_feed_metadata(vec![ProductMetadata {
+                id:          PriceIdentifier::new([1; 32]),
+                symbol:      "Crypto.BTC/USD".to_string(),
+                asset_class: "Crypto".to_string(),
+                base:        None,
+                quote:       None,
+                description: None,
+            }])
+            .await;
+
+        assert_eq!(
+            resolve_price_ids(
+                &store,
+                vec![
+                    PriceIdInput::Id([2; 32]),
+                    PriceIdInput::Symbol("BTC/USD".to_string()),
+                    PriceIdInput::Symbol("crypto.btc/usd".to_string()),
+                ]
+            )
+            .await,
+            Ok(vec![
+                PriceIdentifier::new([2; 32]),
+                PriceIdentifier::new([1; 32]),
+                PriceIdentifier::new([1; 32]),
+            ])
+        );
+    }
+}
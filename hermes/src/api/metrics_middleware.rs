@@ -0,0 +1,38 @@
+use {
+    crate::metrics::{
+        RequestLabels,
+        METRICS,
+    },
+    axum::{
+        extract::MatchedPath,
+        http::Request,
+        middleware::Next,
+        response::IntoResponse,
+    },
+    tokio::time::Instant,
+};
+
+/// Records the latency of every request in the `request_latency_seconds` metric, labeled by the
+/// matched route (not the raw path, to keep the label cardinality bounded) and response status.
+pub async fn track_request_latency<B>(
+    matched_path: Option<MatchedPath>,
+    request: Request<B>,
+    next: Next<B>,
+) -> impl IntoResponse {
+    let route = matched_path
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+
+    METRICS
+        .request_latency
+        .get_or_create(&RequestLabels {
+            route,
+            status: response.status().as_u16(),
+        })
+        .observe(start.elapsed().as_secs_f64());
+
+    response
+}
@@ -0,0 +1,482 @@
+//! Token bucket rate limiting of the API.
+//!
+//! Requests with an API key are limited per key with the tier of the key, the other requests are
+//! limited per IP address with the anonymous tier. The messages sent over WebSocket connections
+//! are limited separately, per client as well, so that opening more connections does not raise
+//! the limit.
+//!
+//! Behind a reverse proxy, all the requests come from the address of the proxy. The proxies listed
+//! in `trusted_proxies` are trusted to put the address of the client in the `X-Forwarded-For`
+//! header, which is then used instead.
+
+use {
+    super::error::ApiError,
+    crate::{
+        config::profile::{
+            RateLimitConfig,
+            TierConfig,
+        },
+        metrics::{
+            RateLimitScope,
+            METRICS,
+        },
+    },
+    axum::{
+        extract::{
+            ConnectInfo,
+            State,
+        },
+        http::{
+            HeaderMap,
+            Request,
+        },
+        middleware::Next,
+        response::{
+            IntoResponse,
+            Response,
+        },
+    },
+    dashmap::DashMap,
+    std::{
+        collections::{
+            HashMap,
+            HashSet,
+        },
+        net::{
+            IpAddr,
+            SocketAddr,
+        },
+        time::{
+            Duration,
+            Instant,
+        },
+    },
+};
+
+/// Header carrying the API key of a request. The key can also be given with the `api_key` query
+/// parameter, as browsers cannot set headers on WebSocket connections.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Header in which the trusted proxies append the address of the peer they received the request
+/// from.
+pub const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// Identifies whose limits a request counts against.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Client {
+    Ip(IpAddr),
+    ApiKey(String),
+}
+
+/// A bucket holding up to `burst` tokens and refilled at `per_second` tokens per second. Each
+/// request takes a token.
+#[derive(Clone, Debug)]
+struct TokenBucket {
+    tokens:     f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(burst: u32, now: Instant) -> Self {
+        Self {
+            tokens:     burst as f64,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, per_second: f64, burst: u32, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(burst as f64);
+        self.updated_at = now;
+    }
+
+    /// Takes a token, or returns how long to wait for the next one.
+    fn try_acquire(&mut self, per_second: f64, burst: u32, now: Instant) -> Result<(), Duration> {
+        self.refill(per_second, burst, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+        }
+    }
+
+    /// Takes the given number of tokens even if the bucket holds fewer, the next requests then
+    /// wait until the debt is refilled.
+    fn charge(&mut self, tokens: usize, per_second: f64, burst: u32, now: Instant) {
+        self.refill(per_second, burst, now);
+        self.tokens -= tokens as f64;
+    }
+}
+
+pub struct RateLimiter {
+    anonymous:          TierConfig,
+    /// Tier name and limits of each API key.
+    api_keys:           HashMap<String, (String, TierConfig)>,
+    trusted_proxies:    HashSet<IpAddr>,
+    request_buckets:    DashMap<Client, TokenBucket>,
+    ws_message_buckets: DashMap<Client, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let api_keys = config
+            .api_keys
+            .into_iter()
+            .filter_map(|(key, tier)| {
+                let limits = config.tiers.get(&tier)?.clone();
+                Some((key, (tier, limits)))
+            })
+            .collect();
+
+        Self {
+            anonymous: config.anonymous,
+            api_keys,
+            trusted_proxies: config.trusted_proxies.into_iter().collect(),
+            request_buckets: DashMap::new(),
+            ws_message_buckets: DashMap::new(),
+        }
+    }
+
+    /// Returns the address of the client of a request received from `peer`. If the peer is a
+    /// trusted proxy, the address is the last one of `forwarded_for` that is not a trusted proxy,
+    /// as the addresses before it could have been set by the client itself.
+    pub fn client_ip(&self, peer: IpAddr, forwarded_for: &[&str]) -> IpAddr {
+        if !self.trusted_proxies.contains(&peer) {
+            return peer;
+        }
+
+        let mut client_ip = peer;
+        for address in forwarded_for
+            .iter()
+            .flat_map(|header| header.split(','))
+            .rev()
+        {
+            match address.trim().parse::<IpAddr>() {
+                Ok(ip) => {
+                    client_ip = ip;
+                    if !self.trusted_proxies.contains(&ip) {
+                        break;
+                    }
+                }
+                // The proxy appends a valid address, anything before an invalid one cannot be
+                // trusted.
+                Err(_) => break,
+            }
+        }
+        client_ip
+    }
+
+    /// Returns the client of a request, or `None` if its API key is unknown.
+    pub fn client(&self, ip: IpAddr, api_key: Option<&str>) -> Option<Client> {
+        match api_key {
+            Some(api_key) if self.api_keys.contains_key(api_key) => {
+                Some(Client::ApiKey(api_key.to_string()))
+            }
+            Some(_) => None,
+            None => Some(Client::Ip(ip)),
+        }
+    }
+
+    fn tier(&self, client: &Client) -> (&str, &TierConfig) {
+        match client {
+            Client::ApiKey(api_key) => match self.api_keys.get(api_key) {
+                Some((name, limits)) => (name, limits),
+                None => ("anonymous", &self.anonymous),
+            },
+            Client::Ip(_) => ("anonymous", &self.anonymous),
+        }
+    }
+
+    /// Counts a request of the client, or returns the error to reject it with.
+    pub fn check_request(&self, client: &Client, now: Instant) -> Result<(), RateLimitError> {
+        let (tier, limits) = self.tier(client);
+        self.request_buckets
+            .entry(client.clone())
+            .or_insert_with(|| TokenBucket::full(limits.request_burst, now))
+            .try_acquire(limits.requests_per_second, limits.request_burst, now)
+            .map_err(|retry_after| {
+                METRICS.record_rate_limited(RateLimitScope::Request);
+                RateLimitError::TooManyRequests {
+                    tier: tier.to_string(),
+                    retry_after,
+                }
+            })
+    }
+
+    /// Counts a WebSocket message of the client, or returns how long to wait before sending
+    /// another one.
+    pub fn check_ws_message(&self, client: &Client, now: Instant) -> Result<(), Duration> {
+        let (_, limits) = self.tier(client);
+        self.ws_message_buckets
+            .entry(client.clone())
+            .or_insert_with(|| TokenBucket::full(limits.ws_message_burst, now))
+            .try_acquire(limits.ws_messages_per_second, limits.ws_message_burst, now)
+            .map_err(|retry_after| {
+                METRICS.record_rate_limited(RateLimitScope::WsMessage);
+                retry_after
+            })
+    }
+
+    /// Counts the messages sent to the client on its behalf, such as the updates replayed when
+    /// resuming a subscription. They are not rejected, but delay the next messages of the client.
+    pub fn charge_ws_messages(&self, client: &Client, count: usize, now: Instant) {
+        let (_, limits) = self.tier(client);
+        self.ws_message_buckets
+            .entry(client.clone())
+            .or_insert_with(|| TokenBucket::full(limits.ws_message_burst, now))
+            .charge(
+                count,
+                limits.ws_messages_per_second,
+                limits.ws_message_burst,
+                now,
+            );
+    }
+
+    /// Drops the buckets that refilled completely, they are equivalent to new ones. This bounds
+    /// the memory used by the clients that stopped making requests.
+    pub fn purge_idle(&self, now: Instant) {
+        self.request_buckets.retain(|client, bucket| {
+            let (_, limits) = self.tier(client);
+            bucket.refill(limits.requests_per_second, limits.request_burst, now);
+            bucket.tokens < limits.request_burst as f64
+        });
+        self.ws_message_buckets.retain(|client, bucket| {
+            let (_, limits) = self.tier(client);
+            bucket.refill(limits.ws_messages_per_second, limits.ws_message_burst, now);
+            bucket.tokens < limits.ws_message_burst as f64
+        });
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RateLimitError {
+    InvalidApiKey,
+    TooManyRequests {
+        tier:        String,
+        retry_after: Duration,
+    },
+}
+
+impl From<RateLimitError> for ApiError {
+    fn from(error: RateLimitError) -> Self {
+        match error {
+            RateLimitError::InvalidApiKey => ApiError::InvalidApiKey,
+            RateLimitError::TooManyRequests { retry_after, .. } => {
+                ApiError::RateLimited { retry_after }
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ApiKeyQuery {
+    api_key: Option<String>,
+}
+
+/// Returns the API key of a request, from its header or its query.
+fn api_key<B>(request: &Request<B>) -> Option<String> {
+    if let Some(api_key) = request.headers().get(API_KEY_HEADER) {
+        return api_key.to_str().ok().map(|api_key| api_key.to_string());
+    }
+
+    request
+        .uri()
+        .query()
+        .and_then(|query| serde_qs::from_str::<ApiKeyQuery>(query).ok())
+        .and_then(|query| query.api_key)
+}
+
+/// Returns the values of the `X-Forwarded-For` headers of a request.
+fn forwarded_for(headers: &HeaderMap) -> Vec<&str> {
+    headers
+        .get_all(FORWARDED_FOR_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect()
+}
+
+/// Rejects the requests over the rate limit of their client, and makes the client of the other
+/// requests available to the handlers as an extension.
+pub async fn limit_requests<B>(
+    State(state): State<super::State>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let rate_limiter = match state.rate_limiter.as_ref() {
+        Some(rate_limiter) => rate_limiter,
+        None => return next.run(request).await,
+    };
+
+    let ip = rate_limiter.client_ip(addr.ip(), &forwarded_for(request.headers()));
+    let client = match rate_limiter.client(ip, api_key(&request).as_deref()) {
+        Some(client) => client,
+        None => return ApiError::from(RateLimitError::InvalidApiKey).into_response(),
+    };
+
+    if let Err(err) = rate_limiter.check_request(&client, Instant::now()) {
+        if let RateLimitError::TooManyRequests { tier, .. } = &err {
+            log::debug!("Rate limit of the {} tier exceeded by {:?}", tier, client);
+        }
+        return ApiError::from(err).into_response();
+    }
+
+    request.extensions_mut().insert(client);
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        std::{
+            collections::BTreeMap,
+            net::Ipv4Addr,
+        },
+    };
+
+    fn tier(per_second: f64, burst: u32) -> TierConfig {
+        TierConfig {
+            requests_per_second:    per_second,
+            request_burst:          burst,
+            ws_messages_per_second: per_second,
+            ws_message_burst:       burst,
+        }
+    }
+
+    fn rate_limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            trusted_proxies: vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))],
+            anonymous:       tier(1.0, 2),
+            tiers:           BTreeMap::from([("partner".to_string(), tier(10.0, 5))]),
+            api_keys:        BTreeMap::from([("key".to_string(), "partner".to_string())]),
+        })
+    }
+
+    #[test]
+    pub fn test_client_identification() {
+        let rate_limiter = rate_limiter();
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        assert_eq!(rate_limiter.client(ip, None), Some(Client::Ip(ip)));
+        assert_eq!(
+            rate_limiter.client(ip, Some("key")),
+            Some(Client::ApiKey("key".to_string()))
+        );
+        assert_eq!(rate_limiter.client(ip, Some("unknown")), None);
+    }
+
+    #[test]
+    pub fn test_client_ip_behind_trusted_proxies() {
+        let rate_limiter = rate_limiter();
+        let peer = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let proxy = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let client = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+        // The header is ignored unless the peer is a trusted proxy.
+        assert_eq!(rate_limiter.client_ip(peer, &["192.0.2.1"]), peer);
+        assert_eq!(rate_limiter.client_ip(proxy, &[]), proxy);
+        assert_eq!(rate_limiter.client_ip(proxy, &["192.0.2.1"]), client);
+
+        // The addresses before the last untrusted one could be spoofed by the client.
+        assert_eq!(
+            rate_limiter.client_ip(proxy, &["203.0.113.7, 192.0.2.1", "10.0.0.2"]),
+            client
+        );
+        assert_eq!(
+            rate_limiter.client_ip(proxy, &["192.0.2.1, not an ip"]),
+            proxy
+        );
+    }
+
+    #[test]
+    pub fn test_rate_limit_errors_are_api_errors() {
+        assert_eq!(
+            ApiError::from(RateLimitError::InvalidApiKey).status(),
+            axum::http::StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            ApiError::from(RateLimitError::TooManyRequests {
+                tier:        "anonymous".to_string(),
+                retry_after: Duration::from_millis(1500),
+            }),
+            ApiError::RateLimited {
+                retry_after: Duration::from_millis(1500),
+            }
+        );
+    }
+
+    #[test]
+    pub fn test_requests_are_limited_per_client() {
+        let rate_limiter = rate_limiter();
+        let now = Instant::now();
+        let ip = Client::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let other_ip = Client::Ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        let api_key = Client::ApiKey("key".to_string());
+
+        // The anonymous tier allows a burst of 2 requests, then 1 per second.
+        assert!(rate_limiter.check_request(&ip, now).is_ok());
+        assert!(rate_limiter.check_request(&ip, now).is_ok());
+        assert_eq!(
+            rate_limiter.check_request(&ip, now),
+            Err(RateLimitError::TooManyRequests {
+                tier:        "anonymous".to_string(),
+                retry_after: Duration::from_secs(1),
+            })
+        );
+        assert!(rate_limiter
+            .check_request(&ip, now + Duration::from_millis(500))
+            .is_err());
+        assert!(rate_limiter
+            .check_request(&ip, now + Duration::from_secs(1))
+            .is_ok());
+
+        // Other clients have their own buckets.
+        assert!(rate_limiter.check_request(&other_ip, now).is_ok());
+        for _ in 0..5 {
+            assert!(rate_limiter.check_request(&api_key, now).is_ok());
+        }
+        assert!(rate_limiter.check_request(&api_key, now).is_err());
+
+        // WebSocket messages are limited separately.
+        assert!(rate_limiter.check_ws_message(&ip, now).is_ok());
+    }
+
+    #[test]
+    pub fn test_replayed_messages_delay_the_next_ones() {
+        let rate_limiter = rate_limiter();
+        let now = Instant::now();
+        let ip = Client::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        // The replay takes more than the burst of 2 messages, the debt is refilled at 1 per second.
+        assert!(rate_limiter.check_ws_message(&ip, now).is_ok());
+        rate_limiter.charge_ws_messages(&ip, 4, now);
+        assert_eq!(
+            rate_limiter.check_ws_message(&ip, now),
+            Err(Duration::from_secs(4))
+        );
+        assert!(rate_limiter
+            .check_ws_message(&ip, now + Duration::from_secs(3))
+            .is_err());
+        assert!(rate_limiter
+            .check_ws_message(&ip, now + Duration::from_secs(4))
+            .is_ok());
+    }
+
+    #[test]
+    pub fn test_purge_idle_buckets() {
+        let rate_limiter = rate_limiter();
+        let now = Instant::now();
+        let ip = Client::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        assert!(rate_limiter.check_request(&ip, now).is_ok());
+        assert!(rate_limiter.check_request(&ip, now).is_ok());
+
+        rate_limiter.purge_idle(now + Duration::from_secs(1));
+        assert_eq!(rate_limiter.request_buckets.len(), 1);
+
+        rate_limiter.purge_idle(now + Duration::from_secs(2));
+        assert!(rate_limiter.request_buckets.is_empty());
+    }
+}
@@ -1,128 +1,300 @@
 use {
-    super::types::{
-        PriceIdInput,
-        RpcPriceFeed,
+    super::{
+        error::ApiError,
+        extract::Query,
+        metadata::{
+            resolve_price_id,
+            resolve_price_ids,
+        },
+        types::{
+            CandleResolution,
+            Encoding,
+            GetVaaCcipResponse,
+            GetVaaResponse,
+            PriceIdInput,
+            RpcCandle,
+            RpcCandleExt,
+            RpcPriceFeed,
+            RpcPriceFeedExt,
+            RpcTwap,
+            RpcTwapExt,
+            RpcTwapWindow,
+            RpcTwapWindowExt,
+        },
     },
     crate::{
-        impl_deserialize_for_hex_string_wrapper,
-        store::types::{
-            RequestTime,
-            UnixTimestamp,
+        metrics::METRICS,
+        store::{
+            candles::candles_range,
+            twap::calculate_twap,
+            types::{
+                RangePosition,
+                RequestTime,
+                Slot,
+                UnixTimestamp,
+            },
+            Store,
         },
     },
-    anyhow::Result,
+    anyhow::{
+        anyhow,
+        Result,
+    },
     axum::{
+        body::StreamBody,
         extract::State,
-        http::StatusCode,
+        http::{
+            header,
+            HeaderMap,
+            HeaderValue,
+            StatusCode,
+        },
         response::{
             IntoResponse,
             Response,
         },
         Json,
     },
-    base64::{
-        engine::general_purpose::STANDARD as base64_standard_engine,
-        Engine as _,
-    },
     derive_more::{
         Deref,
         DerefMut,
     },
+    hermes_client::impl_deserialize_for_hex_string_wrapper,
     pyth_sdk::PriceIdentifier,
-    serde_qs::axum::QsQuery,
-    std::collections::HashSet,
+    std::{
+        collections::HashSet,
+        str::FromStr,
+    },
+    utoipa::IntoParams,
 };
 
-pub enum RestError {
-    UpdateDataNotFound,
-    CcipUpdateDataNotFound,
-    InvalidCCIPInput,
+pub async fn price_feed_ids(
+    State(state): State<super::State>,
+) -> Result<Json<HashSet<PriceIdentifier>>, ApiError> {
+    let price_feeds = state.store.get_price_feed_ids().await;
+    Ok(Json(price_feeds))
 }
 
-impl IntoResponse for RestError {
-    fn into_response(self) -> Response {
-        match self {
-            RestError::UpdateDataNotFound => {
-                (StatusCode::NOT_FOUND, "Update data not found").into_response()
-            }
-            RestError::CcipUpdateDataNotFound => {
-                // Returning Bad Gateway error because CCIP expects a 5xx error if it needs to
-                // retry or try other endpoints. Bad Gateway seems the best choice here as this
-                // is not an internal error and could happen on two scenarios:
-                // 1. DB Api is not responding well (Bad Gateway is appropriate here)
-                // 2. Publish time is a few seconds before current time and a VAA
-                //    Will be available in a few seconds. So we want the client to retry.
-
-                (StatusCode::BAD_GATEWAY, "CCIP update data not found").into_response()
-            }
-            RestError::InvalidCCIPInput => {
-                (StatusCode::BAD_REQUEST, "Invalid CCIP input").into_response()
+/// How multiple update data blobs are laid out in an `application/octet-stream` response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    /// Each blob is preceded by its length as a big-endian u32.
+    #[default]
+    LengthPrefixed,
+    /// The blobs are written back to back. `AccumulatorUpdateData` is self-describing so they
+    /// can still be parsed one after the other.
+    Concatenated,
+}
+
+impl Framing {
+    pub fn frame(&self, blobs: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for blob in blobs {
+            if *self == Framing::LengthPrefixed {
+                bytes.extend_from_slice(&(blob.len() as u32).to_be_bytes());
             }
+            bytes.extend_from_slice(blob);
         }
+        bytes
     }
 }
 
-pub async fn price_feed_ids(
-    State(state): State<super::State>,
-) -> Result<Json<HashSet<PriceIdentifier>>, RestError> {
-    let price_feeds = state.store.get_price_feed_ids().await;
-    Ok(Json(price_feeds))
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Encoding of the update data returned by the VAA endpoints. On top of the JSON encodings, `raw`
+/// returns the bytes as `application/octet-stream`, same as an `Accept: application/octet-stream`
+/// header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateDataEncoding {
+    #[default]
+    Base64,
+    Hex,
+    Raw,
+}
+
+impl UpdateDataEncoding {
+    /// The encoding of the update data in a JSON response, or `None` if the raw bytes should be
+    /// returned instead.
+    fn json_encoding(&self, headers: &HeaderMap) -> Option<Encoding> {
+        if accepts_octet_stream(headers) {
+            return None;
+        }
+
+        match self {
+            UpdateDataEncoding::Base64 => Some(Encoding::Base64),
+            UpdateDataEncoding::Hex => Some(Encoding::Hex),
+            UpdateDataEncoding::Raw => None,
+        }
+    }
 }
 
+/// Whether the client asked for the raw update data instead of JSON through the `Accept` header.
+/// A media range with a quality of 0 is not acceptable, as in RFC 9110.
+fn accepts_octet_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_range| {
+            let mut parts = media_range.split(';').map(str::trim);
+            let media_type = parts.next().unwrap_or_default();
+            let quality = parts
+                .filter_map(|parameter| parameter.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map_or(Some(1.0), |(_, quality)| quality.trim().parse::<f32>().ok());
+
+            media_type.eq_ignore_ascii_case(OCTET_STREAM)
+                && quality.map_or(false, |quality| quality > 0.0)
+        })
+}
+
+fn octet_stream_response(blobs: &[Vec<u8>], framing: Framing) -> Response {
+    ([(header::CONTENT_TYPE, OCTET_STREAM)], framing.frame(blobs)).into_response()
+}
+
+/// Default number of slots an aggregated update can lag behind the latest message of the feeds.
+pub const DEFAULT_MAX_SLOT_LAG: Slot = 10;
+
+/// Header holding the slot chosen for an aggregated update.
+const UPDATE_SLOT_HEADER: &str = "x-update-slot";
+
 #[derive(Debug, serde::Deserialize)]
 pub struct LatestVaasQueryParams {
-    ids: Vec<PriceIdInput>,
+    ids:          Vec<PriceIdInput>,
+    #[serde(default)]
+    encoding:     UpdateDataEncoding,
+    #[serde(default)]
+    framing:      Framing,
+    /// Return a single update data for all the ids, taken from the latest slot in which every
+    /// feed was updated.
+    #[serde(default)]
+    aggregate:    bool,
+    /// How many slots the aggregated update can lag behind the latest message of the feeds.
+    max_slot_lag: Option<Slot>,
 }
 
 
+/// Returns the latest update data of the given ids, as a JSON list of encoded blobs or, with the
+/// `raw` encoding or if the request accepts `application/octet-stream`, as raw bytes framed
+/// according to `framing`.
+///
+/// With `aggregate`, the list holds a single update data and the `x-update-slot` header holds its
+/// slot.
 pub async fn latest_vaas(
     State(state): State<super::State>,
-    QsQuery(params): QsQuery<LatestVaasQueryParams>,
-) -> Result<Json<Vec<String>>, RestError> {
-    let price_ids: Vec<PriceIdentifier> = params.ids.into_iter().map(|id| id.into()).collect();
-    let price_feeds_with_update_data = state
-        .store
-        .get_price_feeds_with_update_data(price_ids, RequestTime::Latest)
-        .await
-        .map_err(|_| RestError::UpdateDataNotFound)?;
-    Ok(Json(
-        price_feeds_with_update_data
-            .wormhole_merkle_update_data
-            .iter()
-            .map(|bytes| base64_standard_engine.encode(bytes)) // TODO: Support multiple
-            // encoding formats
-            .collect(),
-    ))
+    headers: HeaderMap,
+    Query(params): Query<LatestVaasQueryParams>,
+) -> Result<Response, ApiError> {
+    let price_ids = resolve_price_ids(&state.store, params.ids).await?;
+    let (update_data, slot) = if params.aggregate {
+        let aggregated = state
+            .store
+            .get_price_feeds_with_aggregated_update_data(
+                price_ids,
+                params.max_slot_lag.unwrap_or(DEFAULT_MAX_SLOT_LAG),
+            )
+            .await?;
+        (
+            vec![aggregated.wormhole_merkle_update_data],
+            Some(aggregated.slot),
+        )
+    } else {
+        let price_feeds_with_update_data = state
+            .store
+            .get_price_feeds_with_update_data(price_ids, RequestTime::Latest)
+            .await?;
+        (
+            price_feeds_with_update_data.wormhole_merkle_update_data,
+            None,
+        )
+    };
+
+    let mut response = match params.encoding.json_encoding(&headers) {
+        Some(encoding) => Json(
+            update_data
+                .iter()
+                .map(|bytes| encoding.encode(bytes))
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        None => octet_stream_response(&update_data, params.framing),
+    };
+
+    if let Some(slot) = slot {
+        response
+            .headers_mut()
+            .insert(UPDATE_SLOT_HEADER, HeaderValue::from(slot));
+    }
+
+    Ok(response)
 }
 
 #[derive(Debug, serde::Deserialize)]
 pub struct LatestPriceFeedsQueryParams {
-    ids:     Vec<PriceIdInput>,
+    ids:          Vec<PriceIdInput>,
+    #[serde(default)]
+    verbose:      bool,
     #[serde(default)]
-    verbose: bool,
+    binary:       bool,
     #[serde(default)]
-    binary:  bool,
+    encoding:     Encoding,
+    /// Return the price feeds of the latest slot in which every feed was updated, so that their
+    /// update data share the same VAA.
+    #[serde(default)]
+    aggregate:    bool,
+    /// How many slots the aggregated update can lag behind the latest message of the feeds.
+    max_slot_lag: Option<Slot>,
 }
 
+/// Returns the latest price feeds of the given ids. With `aggregate`, the `x-update-slot` header
+/// holds the slot of the price feeds.
 pub async fn latest_price_feeds(
     State(state): State<super::State>,
-    QsQuery(params): QsQuery<LatestPriceFeedsQueryParams>,
-) -> Result<Json<Vec<RpcPriceFeed>>, RestError> {
-    let price_ids: Vec<PriceIdentifier> = params.ids.into_iter().map(|id| id.into()).collect();
-    let price_feeds_with_update_data = state
-        .store
-        .get_price_feeds_with_update_data(price_ids, RequestTime::Latest)
-        .await
-        .map_err(|_| RestError::UpdateDataNotFound)?;
-    Ok(Json(
-        price_feeds_with_update_data
-            .price_feeds
+    Query(params): Query<LatestPriceFeedsQueryParams>,
+) -> Result<Response, ApiError> {
+    let price_ids = resolve_price_ids(&state.store, params.ids).await?;
+    let (price_feeds, slot) = if params.aggregate {
+        let aggregated = state
+            .store
+            .get_price_feeds_with_aggregated_update_data(
+                price_ids,
+                params.max_slot_lag.unwrap_or(DEFAULT_MAX_SLOT_LAG),
+            )
+            .await?;
+        (aggregated.price_feeds, Some(aggregated.slot))
+    } else {
+        let price_feeds_with_update_data = state
+            .store
+            .get_price_feeds_with_update_data(price_ids, RequestTime::Latest)
+            .await?;
+        (price_feeds_with_update_data.price_feeds, None)
+    };
+
+    let mut response = Json(
+        price_feeds
             .into_iter()
             .map(|price_feed| {
-                RpcPriceFeed::from_price_feed_update(price_feed, params.verbose, params.binary)
+                RpcPriceFeed::from_price_feed_update(
+                    price_feed,
+                    params.verbose,
+                    params.binary,
+                    params.encoding,
+                )
             })
-            .collect(),
-    ))
+            .collect::<Vec<_>>(),
+    )
+    .into_response();
+
+    if let Some(slot) = slot {
+        response
+            .headers_mut()
+            .insert(UPDATE_SLOT_HEADER, HeaderValue::from(slot));
+    }
+
+    Ok(response)
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -133,13 +305,15 @@ pub struct GetPriceFeedQueryParams {
     verbose:      bool,
     #[serde(default)]
     binary:       bool,
+    #[serde(default)]
+    encoding:     Encoding,
 }
 
 pub async fn get_price_feed(
     State(state): State<super::State>,
-    QsQuery(params): QsQuery<GetPriceFeedQueryParams>,
-) -> Result<Json<RpcPriceFeed>, RestError> {
-    let price_id: PriceIdentifier = params.id.into();
+    Query(params): Query<GetPriceFeedQueryParams>,
+) -> Result<Json<RpcPriceFeed>, ApiError> {
+    let price_id = resolve_price_id(&state.store, params.id).await?;
 
     let price_feeds_with_update_data = state
         .store
@@ -147,62 +321,429 @@ pub async fn get_price_feed(
             vec![price_id],
             RequestTime::FirstAfter(params.publish_time),
         )
-        .await
-        .map_err(|_| RestError::UpdateDataNotFound)?;
+        .await?;
 
     Ok(Json(RpcPriceFeed::from_price_feed_update(
         price_feeds_with_update_data
             .price_feeds
             .into_iter()
             .next()
-            .ok_or(RestError::UpdateDataNotFound)?,
+            .ok_or(ApiError::UpdateDataNotFound)?,
         params.verbose,
         params.binary,
+        params.encoding,
     )))
 }
 
+/// Default and maximum number of price feed updates returned in a single page of
+/// `get_price_feeds_range`.
+const RANGE_DEFAULT_LIMIT: usize = 100;
+const RANGE_MAX_LIMIT: usize = 1000;
+
+/// Position of the last price feed update of a page. Updates are ordered by publish time, slot
+/// and feed id, and the cursor is encoded as `<publish_time>:<slot>:<feed_id>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangeCursor(RangePosition);
+
+impl FromStr for RangeCursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(3, ':');
+        let mut next_part = || parts.next().ok_or_else(|| anyhow!("Invalid cursor: {}", s));
+        Ok(Self(RangePosition {
+            publish_time: next_part()?.parse()?,
+            slot:         next_part()?.parse()?,
+            feed_id:      hex::decode(next_part()?)?
+                .try_into()
+                .map_err(|_| anyhow!("Invalid cursor feed id"))?,
+        }))
+    }
+}
+
+impl std::fmt::Display for RangeCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.0.publish_time,
+            self.0.slot,
+            hex::encode(self.0.feed_id)
+        )
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for RangeCursor {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetPriceFeedsRangeQueryParams {
+    /// Ids of the price feeds, hex encoded with or without a `0x` prefix, or their symbols.
+    #[param(rename = "ids[]", value_type = Vec<String>)]
+    ids:        Vec<PriceIdInput>,
+    /// Unix timestamp, in seconds, of the start of the range.
+    start_time: UnixTimestamp,
+    /// Unix timestamp, in seconds, of the end of the range (inclusive).
+    end_time:   UnixTimestamp,
+    /// Most updates returned, 100 by default and at most 1000.
+    limit:      Option<usize>,
+    /// The `x-next-cursor` header of the previous page.
+    #[param(value_type = Option<String>)]
+    cursor:     Option<RangeCursor>,
+    /// Include the metadata of the updates.
+    #[serde(default)]
+    verbose:    bool,
+    /// Include the update data of each price feed.
+    #[serde(default)]
+    binary:     bool,
+    /// Encoding of the update data.
+    #[serde(default)]
+    #[param(inline)]
+    encoding:   Encoding,
+}
+
+/// Returns every price feed update of the given ids published between `start_time` and
+/// `end_time` (inclusive), ordered by publish time. A `start_time` older than the retained updates
+/// is rejected, the error holds the retained window.
+///
+/// The updates are streamed as newline-delimited JSON, one `RpcPriceFeed` per line. At most
+/// `limit` updates are returned per request; if there are more, the `x-next-cursor` header
+/// holds the cursor to pass to the next request.
+pub async fn get_price_feeds_range(
+    State(state): State<super::State>,
+    Query(params): Query<GetPriceFeedsRangeQueryParams>,
+) -> Result<Response, ApiError> {
+    price_feeds_range(&state, params).await
+}
+
+/// Streams a page of the price feed updates of a range, see `get_price_feeds_range`.
+pub async fn price_feeds_range(
+    state: &super::State,
+    params: GetPriceFeedsRangeQueryParams,
+) -> Result<Response, ApiError> {
+    if params.start_time > params.end_time {
+        return Err(ApiError::InvalidRange);
+    }
+
+    let limit = params
+        .limit
+        .unwrap_or(RANGE_DEFAULT_LIMIT)
+        .clamp(1, RANGE_MAX_LIMIT);
+
+    let price_ids = resolve_price_ids(&state.store, params.ids).await?;
+    let page = state
+        .store
+        .get_price_feed_updates_in_range(
+            price_ids,
+            params.start_time,
+            params.end_time,
+            params.cursor.map(|cursor| cursor.0),
+            limit,
+        )
+        .await?;
+
+    // The update data of each line is built as the body is streamed.
+    let (verbose, binary, encoding) = (params.verbose, params.binary, params.encoding);
+    let lines = futures::stream::iter(page.updates.map(move |update| -> Result<Vec<u8>> {
+        let mut line = serde_json::to_vec(&RpcPriceFeed::from_price_feed_update(
+            update?, verbose, binary, encoding,
+        ))?;
+        line.push(b'\n');
+        Ok(line)
+    }));
+
+    let mut response = (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(lines),
+    )
+        .into_response();
+
+    let next_cursor = page.next.map(|position| RangeCursor(position).to_string());
+    if let Some(next_cursor) = next_cursor.and_then(|c| HeaderValue::from_str(&c).ok()) {
+        response.headers_mut().insert("x-next-cursor", next_cursor);
+    }
+
+    Ok(response)
+}
+
 #[derive(Debug, serde::Deserialize)]
-pub struct GetVaaQueryParams {
+pub struct LatestTwapsQueryParams {
+    ids:      Vec<PriceIdInput>,
+    #[serde(default)]
+    verbose:  bool,
+    #[serde(default)]
+    binary:   bool,
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+pub async fn latest_twaps(
+    State(state): State<super::State>,
+    Query(params): Query<LatestTwapsQueryParams>,
+) -> Result<Json<Vec<RpcTwap>>, ApiError> {
+    let price_ids = resolve_price_ids(&state.store, params.ids).await?;
+    let twaps_with_update_data = state
+        .store
+        .get_twaps_with_update_data(price_ids, RequestTime::Latest)
+        .await?;
+    Ok(Json(
+        twaps_with_update_data
+            .twaps
+            .into_iter()
+            .map(|twap| {
+                RpcTwap::from_twap_update(twap, params.verbose, params.binary, params.encoding)
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct GetTwapQueryParams {
     id:           PriceIdInput,
     publish_time: UnixTimestamp,
+    #[serde(default)]
+    verbose:      bool,
+    #[serde(default)]
+    binary:       bool,
+    #[serde(default)]
+    encoding:     Encoding,
 }
 
-#[derive(Debug, serde::Serialize)]
-pub struct GetVaaResponse {
-    vaa:          String,
-    #[serde(rename = "publishTime")]
-    publish_time: UnixTimestamp,
+pub async fn get_twap(
+    State(state): State<super::State>,
+    Query(params): Query<GetTwapQueryParams>,
+) -> Result<Json<RpcTwap>, ApiError> {
+    let price_id = resolve_price_id(&state.store, params.id).await?;
+
+    let twaps_with_update_data = state
+        .store
+        .get_twaps_with_update_data(vec![price_id], RequestTime::FirstAfter(params.publish_time))
+        .await?;
+
+    Ok(Json(RpcTwap::from_twap_update(
+        twaps_with_update_data
+            .twaps
+            .into_iter()
+            .next()
+            .ok_or(ApiError::UpdateDataNotFound)?,
+        params.verbose,
+        params.binary,
+        params.encoding,
+    )))
 }
 
-pub async fn get_vaa(
+#[derive(Debug, serde::Deserialize)]
+pub struct GetTwapWindowQueryParams {
+    id:         PriceIdInput,
+    start_time: UnixTimestamp,
+    end_time:   UnixTimestamp,
+    #[serde(default)]
+    binary:     bool,
+    #[serde(default)]
+    encoding:   Encoding,
+}
+
+/// Computes the TWAP of a feed between the first TWAP messages published at or after
+/// `start_time` and `end_time`. The actual window is returned in the response.
+pub async fn get_twap_window(
     State(state): State<super::State>,
-    QsQuery(params): QsQuery<GetVaaQueryParams>,
-) -> Result<Json<GetVaaResponse>, RestError> {
-    let price_id: PriceIdentifier = params.id.into();
+    Query(params): Query<GetTwapWindowQueryParams>,
+) -> Result<Json<RpcTwapWindow>, ApiError> {
+    Ok(Json(
+        twap_window(
+            &state.store,
+            params.id,
+            params.start_time,
+            params.end_time,
+            params.binary,
+            params.encoding,
+        )
+        .await?,
+    ))
+}
 
-    let price_feeds_with_update_data = state
+/// Computes the TWAP of a feed between the first TWAP messages published at or after
+/// `start_time` and `end_time`, shared by the `/api` and `/v2` routes.
+pub async fn twap_window(
+    store: &Store,
+    id: PriceIdInput,
+    start_time: UnixTimestamp,
+    end_time: UnixTimestamp,
+    binary: bool,
+    encoding: Encoding,
+) -> Result<RpcTwapWindow, ApiError> {
+    if start_time >= end_time {
+        return Err(ApiError::InvalidTwapWindow);
+    }
+
+    let price_id = resolve_price_id(store, id).await?;
+
+    let mut updates = vec![];
+    for time in [start_time, end_time] {
+        updates.push(
+            store
+                .get_twaps_with_update_data(vec![price_id], RequestTime::FirstAfter(time))
+                .await?
+                .twaps
+                .into_iter()
+                .next()
+                .ok_or(ApiError::UpdateDataNotFound)?,
+        );
+    }
+
+    let end = updates.pop().ok_or(ApiError::UpdateDataNotFound)?;
+    let start = updates.pop().ok_or(ApiError::UpdateDataNotFound)?;
+    let twap = calculate_twap(&start.twap, &end.twap).map_err(|_| ApiError::InvalidTwapWindow)?;
+
+    Ok(RpcTwapWindow::from_twap(twap, start, end, binary, encoding))
+}
+
+/// Maximum number of candles returned by a single request.
+pub const CANDLES_MAX_COUNT: i64 = 1000;
+
+/// Checks that a candles request covers at most `CANDLES_MAX_COUNT` intervals of the resolution,
+/// in seconds, and that the range widened to whole intervals fits in a timestamp.
+pub fn check_candles_range(
+    resolution: i64,
+    start_time: UnixTimestamp,
+    end_time: UnixTimestamp,
+) -> Result<(), ApiError> {
+    let num_intervals = end_time
+        .checked_sub(start_time)
+        .filter(|duration| *duration >= 0)
+        .map(|duration| duration / resolution);
+    match num_intervals {
+        Some(num_intervals)
+            if num_intervals < CANDLES_MAX_COUNT
+                && candles_range(start_time, end_time, resolution).is_some() =>
+        {
+            Ok(())
+        }
+        _ => Err(ApiError::InvalidRange),
+    }
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetCandlesQueryParams {
+    /// Id of the price feed, hex encoded with or without a `0x` prefix, or its symbol.
+    #[param(value_type = String)]
+    id:         PriceIdInput,
+    #[param(inline)]
+    resolution: CandleResolution,
+    /// Unix timestamp, in seconds, of the start of the range.
+    start_time: UnixTimestamp,
+    /// Unix timestamp, in seconds, of the end of the range (inclusive).
+    end_time:   UnixTimestamp,
+}
+
+/// Returns the OHLC candles of a feed for the intervals overlapping the inclusive
+/// `[start_time, end_time]` range. Intervals without any update have no candle. At most
+/// `CANDLES_MAX_COUNT` intervals can be requested at once.
+pub async fn get_candles(
+    State(state): State<super::State>,
+    Query(params): Query<GetCandlesQueryParams>,
+) -> Result<Json<Vec<RpcCandle>>, ApiError> {
+    candles(&state, params).await
+}
+
+/// Builds the candles of a request, see `get_candles`.
+pub async fn candles(
+    state: &super::State,
+    params: GetCandlesQueryParams,
+) -> Result<Json<Vec<RpcCandle>>, ApiError> {
+    if !state.store.storage.is_persistent() {
+        return Err(ApiError::HistoryUnavailable);
+    }
+
+    let resolution = params.resolution.seconds();
+    check_candles_range(resolution, params.start_time, params.end_time)?;
+
+    let candles = state
         .store
-        .get_price_feeds_with_update_data(
-            vec![price_id],
-            RequestTime::FirstAfter(params.publish_time),
+        .get_candles(
+            resolve_price_id(&state.store, params.id).await?,
+            resolution,
+            params.start_time,
+            params.end_time,
         )
-        .await
-        .map_err(|_| RestError::UpdateDataNotFound)?;
+        .await?;
 
-    let vaa = price_feeds_with_update_data
-        .wormhole_merkle_update_data
-        .get(0)
-        .map(|bytes| base64_standard_engine.encode(bytes))
-        .ok_or(RestError::UpdateDataNotFound)?;
+    Ok(Json(
+        candles
+            .into_iter()
+            .map(|candle| RpcCandle::from_candle(candle, params.resolution))
+            .collect(),
+    ))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct GetVaaQueryParams {
+    id:           PriceIdInput,
+    publish_time: UnixTimestamp,
+    #[serde(default)]
+    encoding:     UpdateDataEncoding,
+}
+
+/// Returns the update data of the first price feed update published at or after `publish_time`.
+/// With the `raw` encoding or if the request accepts `application/octet-stream`, the raw update
+/// data is returned instead of JSON.
+pub async fn get_vaa(
+    State(state): State<super::State>,
+    headers: HeaderMap,
+    Query(params): Query<GetVaaQueryParams>,
+) -> Result<Response, ApiError> {
+    let (vaa, publish_time) = vaa_at_time(&state.store, params.id, params.publish_time).await?;
+
+    match params.encoding.json_encoding(&headers) {
+        Some(encoding) => Ok(Json(GetVaaResponse {
+            vaa: encoding.encode(&vaa),
+            publish_time,
+        })
+        .into_response()),
+        // A single blob needs no framing.
+        None => Ok(octet_stream_response(
+            std::slice::from_ref(&vaa),
+            Framing::Concatenated,
+        )),
+    }
+}
+
+/// Returns the update data of the first price feed update published at or after `publish_time`
+/// along with its publish time, shared by the `/api` and `/v2` routes.
+pub async fn vaa_at_time(
+    store: &Store,
+    id: PriceIdInput,
+    publish_time: UnixTimestamp,
+) -> Result<(Vec<u8>, UnixTimestamp), ApiError> {
+    let price_id = resolve_price_id(store, id).await?;
+
+    let price_feeds_with_update_data = store
+        .get_price_feeds_with_update_data(vec![price_id], RequestTime::FirstAfter(publish_time))
+        .await?;
 
     let publish_time = price_feeds_with_update_data
         .price_feeds
         .get(0)
-        .ok_or(RestError::UpdateDataNotFound)?
+        .ok_or(ApiError::UpdateDataNotFound)?
         .price_feed
         .publish_time;
 
-    Ok(Json(GetVaaResponse { vaa, publish_time }))
+    let vaa = price_feeds_with_update_data
+        .wormhole_merkle_update_data
+        .into_iter()
+        .next()
+        .ok_or(ApiError::UpdateDataNotFound)?;
+
+    Ok((vaa, publish_time))
 }
 
 #[derive(Debug, Clone, Deref, DerefMut)]
@@ -214,40 +755,43 @@ pub struct GetVaaCcipQueryParams {
     data: GetVaaCcipInput,
 }
 
-#[derive(Debug, serde::Serialize)]
-pub struct GetVaaCcipResponse {
-    data: String, // TODO: Use a typed wrapper for the hex output with leading 0x.
-}
-
 pub async fn get_vaa_ccip(
     State(state): State<super::State>,
-    QsQuery(params): QsQuery<GetVaaCcipQueryParams>,
-) -> Result<Json<GetVaaCcipResponse>, RestError> {
+    Query(params): Query<GetVaaCcipQueryParams>,
+) -> Result<Json<GetVaaCcipResponse>, ApiError> {
+    Ok(Json(vaa_ccip(&state.store, &params.data).await?))
+}
+
+/// Returns the update data requested by a CCIP gateway, shared by the `/api` and `/v2` routes.
+/// The failures to find it are server errors so that the gateway retries.
+pub async fn vaa_ccip(
+    store: &Store,
+    data: &GetVaaCcipInput,
+) -> Result<GetVaaCcipResponse, ApiError> {
     let price_id: PriceIdentifier = PriceIdentifier::new(
-        params.data[0..32]
+        data[0..32]
             .try_into()
-            .map_err(|_| RestError::InvalidCCIPInput)?,
+            .map_err(|_| ApiError::InvalidParams("Invalid CCIP input".to_string()))?,
     );
     let publish_time = UnixTimestamp::from_be_bytes(
-        params.data[32..40]
+        data[32..40]
             .try_into()
-            .map_err(|_| RestError::InvalidCCIPInput)?,
+            .map_err(|_| ApiError::InvalidParams("Invalid CCIP input".to_string()))?,
     );
 
-    let price_feeds_with_update_data = state
-        .store
+    let price_feeds_with_update_data = store
         .get_price_feeds_with_update_data(vec![price_id], RequestTime::FirstAfter(publish_time))
         .await
-        .map_err(|_| RestError::CcipUpdateDataNotFound)?;
+        .map_err(|_| ApiError::CcipUpdateDataNotFound)?;
 
     let bytes = price_feeds_with_update_data
         .wormhole_merkle_update_data
         .get(0) // One price feed has only a single VAA as proof.
-        .ok_or(RestError::UpdateDataNotFound)?;
+        .ok_or(ApiError::UpdateDataNotFound)?;
 
-    Ok(Json(GetVaaCcipResponse {
+    Ok(GetVaaCcipResponse {
         data: format!("0x{}", hex::encode(bytes)),
-    }))
+    })
 }
 
 pub async fn live() -> Response {
@@ -261,17 +805,138 @@ pub async fn ready(State(state): State<super::State>) -> Response {
     }
 }
 
-// This is the index page for the REST service. It will list all the available endpoints.
-// TODO: Dynamically generate this list if possible.
+pub async fn metrics() -> Response {
+    match METRICS.encode() {
+        Ok(metrics) => (
+            [(
+                header::CONTENT_TYPE,
+                "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            )],
+            metrics,
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to encode metrics",
+        )
+            .into_response(),
+    }
+}
+
+// This is the index page for the REST service. It will list all the available endpoints. The
+// `/v2` routes are taken from their OpenAPI document, the `/api` ones are kept as is.
 pub async fn index() -> impl IntoResponse {
-    Json([
+    let v2_routes = super::v2::OPENAPI.paths.paths.keys().cloned();
+
+    let routes = [
         "/live",
         "/ready",
+        "/metrics",
         "/api/price_feed_ids",
-        "/api/latest_price_feeds?ids[]=<price_feed_id>&ids[]=<price_feed_id_2>&..(&verbose=true)(&binary=true)",
-        "/api/latest_vaas?ids[]=<price_feed_id>&ids[]=<price_feed_id_2>&...",
-        "/api/get_price_feed?id=<price_feed_id>&publish_time=<publish_time_in_unix_timestamp>(&verbose=true)(&binary=true)",
-        "/api/get_vaa?id=<price_feed_id>&publish_time=<publish_time_in_unix_timestamp>",
+        "/api/price_feeds/metadata(?query=<query>)(&asset_class=<asset_class>)",
+        "/api/latest_price_feeds?ids[]=<price_feed_id>&ids[]=<price_feed_id_2>&..(&verbose=true)(&binary=true)(&encoding=<base64|hex>)(&aggregate=true)(&max_slot_lag=<slots>)",
+        "/api/latest_vaas?ids[]=<price_feed_id>&ids[]=<price_feed_id_2>&...(&encoding=<base64|hex|raw>)(&framing=<length_prefixed|concatenated>)(&aggregate=true)(&max_slot_lag=<slots>)",
+        "/api/price_feeds/stream?ids[]=<price_feed_id>&ids[]=<price_feed_id_2>&..(&verbose=true)(&binary=true)(&encoding=<base64|hex>)",
+        "/api/get_price_feed?id=<price_feed_id>&publish_time=<publish_time_in_unix_timestamp>(&verbose=true)(&binary=true)(&encoding=<base64|hex>)",
+        "/api/get_price_feeds_range?ids[]=<price_feed_id>&ids[]=<price_feed_id_2>&..&start_time=<unix_timestamp>&end_time=<unix_timestamp>(&limit=<limit>)(&cursor=<cursor>)(&verbose=true)(&binary=true)(&encoding=<base64|hex>)",
+        "/api/latest_twaps?ids[]=<price_feed_id>&ids[]=<price_feed_id_2>&..(&verbose=true)(&binary=true)(&encoding=<base64|hex>)",
+        "/api/get_twap?id=<price_feed_id>&publish_time=<publish_time_in_unix_timestamp>(&verbose=true)(&binary=true)(&encoding=<base64|hex>)",
+        "/api/get_twap_window?id=<price_feed_id>&start_time=<unix_timestamp>&end_time=<unix_timestamp>(&binary=true)(&encoding=<base64|hex>)",
+        "/api/get_candles?id=<price_feed_id>&resolution=<1s|1m|5m|1h>&start_time=<unix_timestamp>&end_time=<unix_timestamp>",
+        "/api/get_vaa?id=<price_feed_id>&publish_time=<publish_time_in_unix_timestamp>(&encoding=<base64|hex|raw>)",
         "/api/get_vaa_ccip?data=<0x<price_feed_id_32_bytes>+<publish_time_unix_timestamp_be_8_bytes>>",
-    ])
+        "POST /api/verify_update_data {\"data\": <update_data>(, \"encoding\": <base64|hex>)}",
+        "/docs",
+    ];
+
+    Json(
+        routes
+            .into_iter()
+            .map(|route| route.to_string())
+            .chain(v2_routes)
+            .collect::<Vec<_>>(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_framing_frames_blobs() {
+        let blobs = vec![vec![1, 2, 3], vec![4]];
+        assert_eq!(
+            Framing::LengthPrefixed.frame(&blobs),
+            vec![0, 0, 0, 3, 1, 2, 3, 0, 0, 0, 1, 4]
+        );
+        assert_eq!(Framing::Concatenated.frame(&blobs), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    pub fn test_accepts_octet_stream() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_octet_stream(&headers));
+
+        headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+        assert!(!accepts_octet_stream(&headers));
+
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/json, application/octet-stream;q=0.9"),
+        );
+        assert!(accepts_octet_stream(&headers));
+
+        // A quality of 0 means the media type is not acceptable.
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/json, application/octet-stream; q=0"),
+        );
+        assert!(!accepts_octet_stream(&headers));
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/octet-stream;q=0.000"),
+        );
+        assert!(!accepts_octet_stream(&headers));
+    }
+
+    #[test]
+    pub fn test_update_data_encoding() {
+        let headers = HeaderMap::new();
+        assert_eq!(
+            UpdateDataEncoding::Hex.json_encoding(&headers),
+            Some(Encoding::Hex)
+        );
+        assert_eq!(UpdateDataEncoding::Raw.json_encoding(&headers), None);
+
+        #[derive(Debug, serde::Deserialize)]
+        struct Params {
+            encoding: UpdateDataEncoding,
+        }
+        let params: Params = serde_qs::from_str("encoding=raw").unwrap();
+        assert_eq!(params.encoding, UpdateDataEncoding::Raw);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static(OCTET_STREAM));
+        assert_eq!(UpdateDataEncoding::Hex.json_encoding(&headers), None);
+    }
+
+    #[test]
+    pub fn test_check_candles_range() {
+        assert_eq!(check_candles_range(60, 0, 60 * 999), Ok(()));
+        assert_eq!(
+            check_candles_range(60, 0, 60 * 1000),
+            Err(ApiError::InvalidRange)
+        );
+        assert_eq!(check_candles_range(60, 10, 9), Err(ApiError::InvalidRange));
+
+        // The ranges that overflow are rejected rather than wrapped around.
+        assert_eq!(
+            check_candles_range(60, i64::MIN, i64::MAX),
+            Err(ApiError::InvalidRange)
+        );
+        assert_eq!(
+            check_candles_range(3600, i64::MAX - 10, i64::MAX),
+            Err(ApiError::InvalidRange)
+        );
+    }
 }
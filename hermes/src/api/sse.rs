@@ -0,0 +1,170 @@
+use {
+    super::{
+        error::ApiError,
+        extract::Query,
+        metadata::resolve_price_ids,
+        types::{
+            Encoding,
+            PriceIdInput,
+            RpcPriceFeed,
+            RpcPriceFeedExt,
+        },
+    },
+    crate::store::{
+        types::{
+            RequestTime,
+            UpdateEvent,
+        },
+        Store,
+    },
+    axum::{
+        extract::State,
+        response::sse::{
+            Event,
+            KeepAlive,
+            Sse,
+        },
+    },
+    futures::{
+        stream,
+        Stream,
+        StreamExt,
+    },
+    pyth_sdk::PriceIdentifier,
+    std::{
+        convert::Infallible,
+        sync::Arc,
+    },
+    tokio::sync::{
+        broadcast,
+        watch,
+    },
+    utoipa::IntoParams,
+};
+
+/// Capacity of the update notifications channel. A stream lagging behind skips the missed
+/// notifications and sends the latest prices of all its feeds on the next one instead.
+pub const NOTIFICATIONS_CHAN_LEN: usize = 1000;
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamPriceFeedsQueryParams {
+    /// Ids of the price feeds, hex encoded with or without a `0x` prefix, or their symbols.
+    #[param(rename = "ids[]", value_type = Vec<String>)]
+    ids:      Vec<PriceIdInput>,
+    /// Include the metadata of the updates.
+    #[serde(default)]
+    verbose:  bool,
+    /// Include the update data of each price feed.
+    #[serde(default)]
+    binary:   bool,
+    /// Encoding of the update data.
+    #[serde(default)]
+    #[param(inline)]
+    encoding: Encoding,
+}
+
+/// Streams the price feed updates of the requested ids as Server-Sent Events. Each update is sent
+/// as a `price_update` event holding the same JSON as the WebSocket `price_update` messages.
+pub async fn price_feeds_stream(
+    State(state): State<super::State>,
+    Query(params): Query<StreamPriceFeedsQueryParams>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let price_ids = resolve_price_ids(&state.store, params.ids).await?;
+
+    // Fail early on unknown ids instead of opening a stream that would never send anything.
+    state
+        .store
+        .get_price_feeds_with_update_data(price_ids.clone(), RequestTime::Latest)
+        .await?;
+
+    let subscriber = SseSubscriber {
+        store: state.store.clone(),
+        price_ids,
+        verbose: params.verbose,
+        binary: params.binary,
+        encoding: params.encoding,
+        notify_receiver: state.sse_tx.subscribe(),
+        exit_rx: state.exit_rx.clone(),
+    };
+
+    let events = stream::unfold(subscriber, |mut subscriber| async move {
+        let events = subscriber.next_events().await?;
+        Some((stream::iter(events), subscriber))
+    })
+    .flatten()
+    .map(Ok);
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+struct SseSubscriber {
+    store:           Arc<Store>,
+    price_ids:       Vec<PriceIdentifier>,
+    verbose:         bool,
+    binary:          bool,
+    encoding:        Encoding,
+    notify_receiver: broadcast::Receiver<Arc<UpdateEvent>>,
+    exit_rx:         watch::Receiver<bool>,
+}
+
+impl SseSubscriber {
+    /// Waits for the next update notification and returns the events to send for it. Returns
+    /// `None` once the stream should end, either because Hermes is shutting down or because the
+    /// notifications channel is closed.
+    async fn next_events(&mut self) -> Option<Vec<Event>> {
+        let (updated_price_ids, request_time) = tokio::select! {
+            notification = self.notify_receiver.recv() => match notification {
+                Ok(event) => (
+                    self.price_ids
+                        .iter()
+                        .filter(|price_id| event.feed_ids.contains(price_id))
+                        .cloned()
+                        .collect::<Vec<_>>(),
+                    RequestTime::AtSlot(event.slot),
+                ),
+                // The missed notifications are unknown, so the latest update of all the feeds is
+                // sent.
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    (self.price_ids.clone(), RequestTime::Latest)
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            },
+            _ = self.exit_rx.changed() => return None,
+        };
+
+        if updated_price_ids.is_empty() {
+            return Some(vec![]);
+        }
+
+        let price_feeds_with_update_data = match self
+            .store
+            .get_price_feeds_with_update_data(updated_price_ids, request_time)
+            .await
+        {
+            Ok(price_feeds_with_update_data) => price_feeds_with_update_data,
+            Err(e) => {
+                log::debug!("Failed to fetch price feeds for SSE stream: {}", e);
+                return Some(vec![]);
+            }
+        };
+
+        Some(
+            price_feeds_with_update_data
+                .price_feeds
+                .into_iter()
+                .filter_map(|update| {
+                    Event::default()
+                        .event("price_update")
+                        .json_data(RpcPriceFeed::from_price_feed_update(
+                            update,
+                            self.verbose,
+                            self.binary,
+                            self.encoding,
+                        ))
+                        .ok()
+                })
+                .collect(),
+        )
+    }
+}
@@ -1,20 +1,36 @@
+// The types of the API are defined in the client crate so that clients can share them.
+pub use hermes_client::types::{
+    AggregatedUpdate,
+    BinaryUpdate,
+    CandleResolution,
+    ClientMessage,
+    Encoding,
+    ErrorCode,
+    ErrorResponse,
+    GetVaaCcipResponse,
+    GetVaaResponse,
+    PriceIdInput,
+    PriceSchema,
+    RpcCandle,
+    RpcPriceFeed,
+    RpcPriceFeedMetadata,
+    RpcProductMetadata,
+    RpcTwap,
+    RpcTwapWindow,
+    ServerMessage,
+    ServerResponseMessage,
+    TimeWindow,
+};
 use {
-    crate::{
-        impl_deserialize_for_hex_string_wrapper,
-        store::types::{
+    crate::store::{
+        candles::Candle,
+        metadata::ProductMetadata,
+        twap::Twap,
+        types::{
             PriceFeedUpdate,
-            Slot,
-            UnixTimestamp,
+            TwapUpdate,
         },
     },
-    base64::{
-        engine::general_purpose::STANDARD as base64_standard_engine,
-        Engine as _,
-    },
-    derive_more::{
-        Deref,
-        DerefMut,
-    },
     pyth_sdk::{
         Price,
         PriceIdentifier,
@@ -22,50 +38,24 @@ use {
     wormhole_sdk::Chain,
 };
 
-
-/// PriceIdInput is a wrapper around a 32-byte hex string.
-/// that supports a flexible deserialization from a hex string.
-/// It supports both 0x-prefixed and non-prefixed hex strings,
-/// and also supports both lower and upper case characters.
-#[derive(Debug, Clone, Deref, DerefMut)]
-pub struct PriceIdInput([u8; 32]);
-// TODO: Use const generics instead of macro.
-impl_deserialize_for_hex_string_wrapper!(PriceIdInput, 32);
-
-impl From<PriceIdInput> for PriceIdentifier {
-    fn from(id: PriceIdInput) -> Self {
-        Self::new(*id)
-    }
-}
-
-type Base64String = String;
-
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct RpcPriceFeedMetadata {
-    pub slot:                       Slot,
-    pub emitter_chain:              u16,
-    pub price_service_receive_time: UnixTimestamp,
-}
-
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct RpcPriceFeed {
-    pub id:        PriceIdentifier,
-    pub price:     Price,
-    pub ema_price: Price,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub metadata:  Option<RpcPriceFeedMetadata>,
-    /// Vaa binary represented in base64.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub vaa:       Option<Base64String>,
+/// Conversion of the store updates into the API types, which the client crate knows nothing
+/// about.
+pub trait RpcPriceFeedExt {
+    fn from_price_feed_update(
+        price_feed_update: PriceFeedUpdate,
+        verbose: bool,
+        binary: bool,
+        encoding: Encoding,
+    ) -> Self;
 }
 
-impl RpcPriceFeed {
-    // TODO: Use a Encoding type to have None, Base64, and Hex variants instead of binary flag.
+impl RpcPriceFeedExt for RpcPriceFeed {
     // TODO: Use a Verbosity type to define None, or Full instead of verbose flag.
-    pub fn from_price_feed_update(
+    fn from_price_feed_update(
         price_feed_update: PriceFeedUpdate,
         verbose: bool,
         binary: bool,
+        encoding: Encoding,
     ) -> Self {
         let price_feed_message = price_feed_update.price_feed;
 
@@ -88,9 +78,163 @@ impl RpcPriceFeed {
                 price_service_receive_time: price_feed_update.received_at,
                 slot:                       price_feed_update.slot,
             }),
-            vaa:       binary.then_some(
-                base64_standard_engine.encode(price_feed_update.wormhole_merkle_update_data),
-            ),
+            vaa:       binary
+                .then(|| encoding.encode(price_feed_update.wormhole_merkle_update_data)),
+        }
+    }
+}
+
+pub trait RpcTwapExt {
+    fn from_twap_update(
+        twap_update: TwapUpdate,
+        verbose: bool,
+        binary: bool,
+        encoding: Encoding,
+    ) -> Self;
+}
+
+impl RpcTwapExt for RpcTwap {
+    fn from_twap_update(
+        twap_update: TwapUpdate,
+        verbose: bool,
+        binary: bool,
+        encoding: Encoding,
+    ) -> Self {
+        let twap_message = twap_update.twap;
+
+        Self {
+            id:                PriceIdentifier::new(twap_message.feed_id),
+            cumulative_price:  twap_message.cumulative_price.to_string(),
+            cumulative_conf:   twap_message.cumulative_conf.to_string(),
+            num_down_slots:    twap_message.num_down_slots,
+            expo:              twap_message.exponent,
+            publish_time:      twap_message.publish_time,
+            prev_publish_time: twap_message.prev_publish_time,
+            publish_slot:      twap_message.publish_slot,
+            metadata:          verbose.then_some(RpcPriceFeedMetadata {
+                emitter_chain:              Chain::Pythnet.into(),
+                price_service_receive_time: twap_update.received_at,
+                slot:                       twap_update.slot,
+            }),
+            vaa:               binary
+                .then(|| encoding.encode(twap_update.wormhole_merkle_update_data)),
+        }
+    }
+}
+
+pub trait RpcTwapWindowExt {
+    fn from_twap(
+        twap: Twap,
+        start: TwapUpdate,
+        end: TwapUpdate,
+        binary: bool,
+        encoding: Encoding,
+    ) -> Self;
+}
+
+impl RpcTwapWindowExt for RpcTwapWindow {
+    fn from_twap(
+        twap: Twap,
+        start: TwapUpdate,
+        end: TwapUpdate,
+        binary: bool,
+        encoding: Encoding,
+    ) -> Self {
+        Self {
+            id:             PriceIdentifier::new(twap.feed_id),
+            twap:           Price {
+                price:        twap.price,
+                conf:         twap.conf,
+                expo:         twap.exponent,
+                publish_time: twap.end_time,
+            },
+            start_time:     twap.start_time,
+            end_time:       twap.end_time,
+            num_slots:      twap.num_slots,
+            num_down_slots: twap.num_down_slots,
+            vaas:           binary.then(|| {
+                [start, end]
+                    .into_iter()
+                    .map(|update| encoding.encode(update.wormhole_merkle_update_data))
+                    .collect()
+            }),
         }
     }
 }
+
+pub trait RpcCandleExt {
+    fn from_candle(candle: Candle, resolution: CandleResolution) -> Self;
+}
+
+impl RpcCandleExt for RpcCandle {
+    fn from_candle(candle: Candle, resolution: CandleResolution) -> Self {
+        Self {
+            id:          PriceIdentifier::new(candle.feed_id),
+            start_time:  candle.start_time,
+            end_time:    candle.start_time + resolution.seconds(),
+            open:        candle.open,
+            high:        candle.high,
+            low:         candle.low,
+            close:       candle.close,
+            avg_conf:    candle.avg_conf,
+            expo:        candle.exponent,
+            num_updates: candle.num_updates,
+        }
+    }
+}
+
+pub trait RpcProductMetadataExt {
+    fn from_product_metadata(product: ProductMetadata) -> Self;
+}
+
+impl RpcProductMetadataExt for RpcProductMetadata {
+    fn from_product_metadata(product: ProductMetadata) -> Self {
+        Self {
+            id:          product.id,
+            symbol:      product.symbol,
+            asset_class: product.asset_class,
+            base:        product.base,
+            quote:       product.quote,
+            description: product.description,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        pythnet_sdk::messages::PriceFeedMessage,
+    };
+
+    #[test]
+    pub fn test_rpc_price_feed_from_price_feed_update() {
+        let update = || PriceFeedUpdate {
+            price_feed:                  PriceFeedMessage {
+                feed_id:           [1; 32],
+                price:             100,
+                conf:              2,
+                exponent:          -8,
+                publish_time:      10,
+                prev_publish_time: 9,
+                ema_price:         99,
+                ema_conf:          3,
+            },
+            received_at:                 11,
+            slot:                        5,
+            wormhole_merkle_update_data: vec![0xde, 0xad],
+        };
+
+        let price_feed =
+            RpcPriceFeed::from_price_feed_update(update(), false, false, Encoding::Hex);
+        assert_eq!(price_feed.id, PriceIdentifier::new([1; 32]));
+        assert_eq!(price_feed.price.price, 100);
+        assert_eq!(price_feed.ema_price.conf, 3);
+        assert!(price_feed.metadata.is_none());
+        assert!(price_feed.vaa.is_none());
+
+        let price_feed = RpcPriceFeed::from_price_feed_update(update(), true, true, Encoding::Hex);
+        assert_eq!(price_feed.metadata.map(|metadata| metadata.slot), Some(5));
+        assert_eq!(price_feed.vaa, Some("0xdead".to_string()));
+    }
+}
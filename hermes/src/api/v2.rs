@@ -0,0 +1,769 @@
+//! Version 2 of the REST API, documented by the OpenAPI document served at `/docs`.
+//!
+//! The routes are grouped by resource (`price_feeds`, `updates`, `twaps` and `candles`), the
+//! binary data is always returned along with its `Encoding`, and malformed parameters are
+//! rejected with an `ErrorResponse` like the other errors. The price feeds can be given by symbol
+//! instead of id, as listed by `/v2/price_feeds/metadata`. The `/api` routes are kept for
+//! compatibility, the handlers of both share their logic through the `rest`, `sse`, `verify` and
+//! `ws` modules.
+
+use {
+    super::{
+        error::ApiError,
+        extract::{
+            JsonBody,
+            Query,
+        },
+        metadata::resolve_price_ids,
+        rest::{
+            self,
+            GetCandlesQueryParams,
+            GetPriceFeedsRangeQueryParams,
+            GetVaaCcipInput,
+            DEFAULT_MAX_SLOT_LAG,
+        },
+        sse::{
+            self,
+            StreamPriceFeedsQueryParams,
+        },
+        types::{
+            AggregatedUpdate,
+            BinaryUpdate,
+            CandleResolution,
+            Encoding,
+            ErrorCode,
+            ErrorResponse,
+            GetVaaCcipResponse,
+            GetVaaResponse,
+            PriceIdInput,
+            PriceSchema,
+            RpcCandle,
+            RpcPriceFeed,
+            RpcPriceFeedExt,
+            RpcPriceFeedMetadata,
+            RpcProductMetadata,
+            RpcProductMetadataExt,
+            RpcTwap,
+            RpcTwapExt,
+            RpcTwapWindow,
+            TimeWindow,
+        },
+        verify::{
+            self,
+            MerkleRootReport,
+            MerkleUpdateReport,
+            MessageReport,
+            SignatureReport,
+            UpdateDataReport,
+            VaaReport,
+            VerifyUpdateDataRequest,
+        },
+        ws,
+    },
+    crate::store::types::{
+        RequestTime,
+        Slot,
+        UnixTimestamp,
+    },
+    axum::{
+        extract::{
+            ws::WebSocketUpgrade,
+            Extension,
+            State,
+        },
+        response::{
+            sse::{
+                Event,
+                Sse,
+            },
+            IntoResponse,
+            Response,
+        },
+        Json,
+    },
+    futures::Stream,
+    pyth_sdk::PriceIdentifier,
+    std::convert::Infallible,
+    utoipa::{
+        IntoParams,
+        OpenApi,
+    },
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Hermes", description = "Pyth price feeds and their update data"),
+    paths(
+        price_feed_ids,
+        price_feeds_metadata,
+        latest_price_feeds,
+        price_feeds_at_time,
+        price_feeds_range,
+        price_feeds_stream,
+        latest_updates,
+        updates_at_time,
+        aggregated_updates,
+        vaa,
+        vaa_ccip,
+        verify_updates,
+        latest_twaps,
+        twap_window,
+        candles,
+        ws_subscribe,
+    ),
+    components(schemas(
+        AggregatedUpdate,
+        BinaryUpdate,
+        CandleResolution,
+        Encoding,
+        ErrorCode,
+        ErrorResponse,
+        GetVaaCcipResponse,
+        GetVaaResponse,
+        MerkleRootReport,
+        MerkleUpdateReport,
+        MessageReport,
+        PriceSchema,
+        RpcCandle,
+        RpcPriceFeed,
+        RpcPriceFeedMetadata,
+        RpcProductMetadata,
+        RpcTwap,
+        RpcTwapWindow,
+        SignatureReport,
+        TimeWindow,
+        UpdateDataReport,
+        VaaReport,
+        VerifyUpdateDataRequest,
+    )),
+    tags(
+        (name = "price_feeds", description = "Prices of the feeds"),
+        (name = "updates", description = "Update data to submit to the Pyth contracts"),
+        (name = "twaps", description = "Time-weighted average prices"),
+        (name = "candles", description = "OHLC candles"),
+        (name = "ws", description = "WebSocket subscriptions to the price feeds"),
+    )
+)]
+pub struct ApiDoc;
+
+lazy_static::lazy_static! {
+    /// The OpenAPI document of the routes, built once as it never changes.
+    pub static ref OPENAPI: utoipa::openapi::OpenApi = ApiDoc::openapi();
+}
+
+/// Ids of all the price feeds known to Hermes.
+#[utoipa::path(
+    get,
+    path = "/v2/price_feeds/ids",
+    responses((status = 200, description = "Hex encoded price feed ids", body = [String])),
+    tag = "price_feeds"
+)]
+pub async fn price_feed_ids(State(state): State<super::State>) -> Json<Vec<PriceIdentifier>> {
+    Json(state.store.get_price_feed_ids().await.into_iter().collect())
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PriceFeedsMetadataParams {
+    /// Case-insensitive substring of the symbol, base, quote or description of the feeds.
+    query:       Option<String>,
+    /// Case-insensitive asset class of the feeds, e.g. `crypto`.
+    asset_class: Option<String>,
+}
+
+/// Metadata of the price feeds, sorted by symbol. Empty unless Hermes is configured with a source
+/// of metadata.
+#[utoipa::path(
+    get,
+    path = "/v2/price_feeds/metadata",
+    params(PriceFeedsMetadataParams),
+    responses(
+        (status = 200, description = "Metadata of the matching price feeds", body = [RpcProductMetadata]),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+    ),
+    tag = "price_feeds"
+)]
+pub async fn price_feeds_metadata(
+    State(state): State<super::State>,
+    Query(params): Query<PriceFeedsMetadataParams>,
+) -> Result<Json<Vec<RpcProductMetadata>>, ApiError> {
+    Ok(Json(
+        state
+            .store
+            .search_price_feed_metadata(params.query.as_deref(), params.asset_class.as_deref())
+            .await
+            .into_iter()
+            .map(RpcProductMetadata::from_product_metadata)
+            .collect(),
+    ))
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PriceFeedsParams {
+    /// Ids of the price feeds, hex encoded with or without a `0x` prefix, or their symbols.
+    #[param(rename = "ids[]", value_type = Vec<String>)]
+    ids:      Vec<PriceIdInput>,
+    /// Include the metadata of the updates.
+    #[serde(default)]
+    verbose:  bool,
+    /// Include the update data of each price feed.
+    #[serde(default)]
+    binary:   bool,
+    /// Encoding of the update data.
+    #[serde(default)]
+    #[param(inline)]
+    encoding: Encoding,
+}
+
+/// Latest price of the given feeds.
+#[utoipa::path(
+    get,
+    path = "/v2/price_feeds/latest",
+    params(PriceFeedsParams),
+    responses(
+        (status = 200, description = "Latest price feeds", body = [RpcPriceFeed]),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "A price feed is missing", body = ErrorResponse),
+    ),
+    tag = "price_feeds"
+)]
+pub async fn latest_price_feeds(
+    State(state): State<super::State>,
+    Query(params): Query<PriceFeedsParams>,
+) -> Result<Json<Vec<RpcPriceFeed>>, ApiError> {
+    let price_feeds_with_update_data = state
+        .store
+        .get_price_feeds_with_update_data(
+            resolve_price_ids(&state.store, params.ids).await?,
+            RequestTime::Latest,
+        )
+        .await?;
+
+    Ok(Json(
+        price_feeds_with_update_data
+            .price_feeds
+            .into_iter()
+            .map(|price_feed| {
+                RpcPriceFeed::from_price_feed_update(
+                    price_feed,
+                    params.verbose,
+                    params.binary,
+                    params.encoding,
+                )
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PriceFeedsAtTimeParams {
+    /// Ids of the price feeds, hex encoded with or without a `0x` prefix, or their symbols.
+    #[param(rename = "ids[]", value_type = Vec<String>)]
+    ids:          Vec<PriceIdInput>,
+    /// Unix timestamp, in seconds. The first update published at or after it is returned.
+    publish_time: UnixTimestamp,
+    /// Include the metadata of the updates.
+    #[serde(default)]
+    verbose:      bool,
+    /// Include the update data of each price feed.
+    #[serde(default)]
+    binary:       bool,
+    /// Encoding of the update data.
+    #[serde(default)]
+    #[param(inline)]
+    encoding:     Encoding,
+}
+
+/// Price of the given feeds at a point in time.
+#[utoipa::path(
+    get,
+    path = "/v2/price_feeds/at_time",
+    params(PriceFeedsAtTimeParams),
+    responses(
+        (status = 200, description = "First price feeds published at or after the time", body = [RpcPriceFeed]),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "A price feed is unknown or the publish time is out of its retained window", body = ErrorResponse),
+    ),
+    tag = "price_feeds"
+)]
+pub async fn price_feeds_at_time(
+    State(state): State<super::State>,
+    Query(params): Query<PriceFeedsAtTimeParams>,
+) -> Result<Json<Vec<RpcPriceFeed>>, ApiError> {
+    let price_feeds_with_update_data = state
+        .store
+        .get_price_feeds_with_update_data(
+            resolve_price_ids(&state.store, params.ids).await?,
+            RequestTime::FirstAfter(params.publish_time),
+        )
+        .await?;
+
+    Ok(Json(
+        price_feeds_with_update_data
+            .price_feeds
+            .into_iter()
+            .map(|price_feed| {
+                RpcPriceFeed::from_price_feed_update(
+                    price_feed,
+                    params.verbose,
+                    params.binary,
+                    params.encoding,
+                )
+            })
+            .collect(),
+    ))
+}
+
+/// Every update of the given feeds published within a range, ordered by publish time. The start
+/// of the range must be retained. The updates are streamed as newline-delimited JSON, one
+/// `RpcPriceFeed` per line, and the `x-next-cursor` header holds the cursor of the next page if
+/// there is one.
+#[utoipa::path(
+    get,
+    path = "/v2/price_feeds/range",
+    params(GetPriceFeedsRangeQueryParams),
+    responses(
+        (status = 200, description = "Newline-delimited JSON price feeds", body = [RpcPriceFeed], content_type = "application/x-ndjson"),
+        (status = 400, description = "Invalid parameters or range", body = ErrorResponse),
+        (status = 404, description = "A price feed is unknown or the start time is older than its retained window", body = ErrorResponse),
+    ),
+    tag = "price_feeds"
+)]
+pub async fn price_feeds_range(
+    State(state): State<super::State>,
+    Query(params): Query<GetPriceFeedsRangeQueryParams>,
+) -> Result<Response, ApiError> {
+    rest::price_feeds_range(&state, params).await
+}
+
+/// Server-Sent Events stream of the updates of the given feeds. Each update is sent as a
+/// `price_update` event holding an `RpcPriceFeed`.
+#[utoipa::path(
+    get,
+    path = "/v2/price_feeds/stream",
+    params(StreamPriceFeedsQueryParams),
+    responses(
+        (status = 200, description = "Stream of price_update events", body = RpcPriceFeed, content_type = "text/event-stream"),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "A price feed is missing", body = ErrorResponse),
+    ),
+    tag = "price_feeds"
+)]
+pub async fn price_feeds_stream(
+    state: State<super::State>,
+    Query(params): Query<StreamPriceFeedsQueryParams>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    sse::price_feeds_stream(state, Query(params)).await
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UpdatesParams {
+    /// Ids of the price feeds, hex encoded with or without a `0x` prefix, or their symbols.
+    #[param(rename = "ids[]", value_type = Vec<String>)]
+    ids:      Vec<PriceIdInput>,
+    /// Encoding of the update data.
+    #[serde(default)]
+    #[param(inline)]
+    encoding: Encoding,
+}
+
+/// Latest update data of the given feeds.
+#[utoipa::path(
+    get,
+    path = "/v2/updates/latest",
+    params(UpdatesParams),
+    responses(
+        (status = 200, description = "Update data covering all the feeds", body = BinaryUpdate),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "A price feed is missing", body = ErrorResponse),
+    ),
+    tag = "updates"
+)]
+pub async fn latest_updates(
+    State(state): State<super::State>,
+    Query(params): Query<UpdatesParams>,
+) -> Result<Json<BinaryUpdate>, ApiError> {
+    let price_feeds_with_update_data = state
+        .store
+        .get_price_feeds_with_update_data(
+            resolve_price_ids(&state.store, params.ids).await?,
+            RequestTime::Latest,
+        )
+        .await?;
+
+    Ok(Json(BinaryUpdate {
+        encoding: params.encoding,
+        data:     price_feeds_with_update_data
+            .wormhole_merkle_update_data
+            .iter()
+            .map(|data| params.encoding.encode(data))
+            .collect(),
+    }))
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UpdatesAtTimeParams {
+    /// Ids of the price feeds, hex encoded with or without a `0x` prefix, or their symbols.
+    #[param(rename = "ids[]", value_type = Vec<String>)]
+    ids:          Vec<PriceIdInput>,
+    /// Unix timestamp, in seconds. The first updates published at or after it are returned.
+    publish_time: UnixTimestamp,
+    /// Encoding of the update data.
+    #[serde(default)]
+    #[param(inline)]
+    encoding:     Encoding,
+}
+
+/// Update data of the given feeds at a point in time.
+#[utoipa::path(
+    get,
+    path = "/v2/updates/at_time",
+    params(UpdatesAtTimeParams),
+    responses(
+        (status = 200, description = "Update data covering all the feeds", body = BinaryUpdate),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "A price feed is unknown or the publish time is out of its retained window", body = ErrorResponse),
+    ),
+    tag = "updates"
+)]
+pub async fn updates_at_time(
+    State(state): State<super::State>,
+    Query(params): Query<UpdatesAtTimeParams>,
+) -> Result<Json<BinaryUpdate>, ApiError> {
+    let price_feeds_with_update_data = state
+        .store
+        .get_price_feeds_with_update_data(
+            resolve_price_ids(&state.store, params.ids).await?,
+            RequestTime::FirstAfter(params.publish_time),
+        )
+        .await?;
+
+    Ok(Json(BinaryUpdate {
+        encoding: params.encoding,
+        data:     price_feeds_with_update_data
+            .wormhole_merkle_update_data
+            .iter()
+            .map(|data| params.encoding.encode(data))
+            .collect(),
+    }))
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AggregatedUpdatesParams {
+    /// Ids of the price feeds, hex encoded with or without a `0x` prefix, or their symbols.
+    #[param(rename = "ids[]", value_type = Vec<String>)]
+    ids:          Vec<PriceIdInput>,
+    /// How many slots the update can lag behind the latest message of the feeds, 10 by default.
+    max_slot_lag: Option<Slot>,
+    /// Include the metadata of the updates.
+    #[serde(default)]
+    verbose:      bool,
+    /// Encoding of the update data.
+    #[serde(default)]
+    #[param(inline)]
+    encoding:     Encoding,
+}
+
+/// A single update data covering all the given feeds, taken from the latest slot in which every
+/// feed was updated.
+#[utoipa::path(
+    get,
+    path = "/v2/updates/aggregated",
+    params(AggregatedUpdatesParams),
+    responses(
+        (status = 200, description = "Update data and price feeds of the slot", body = AggregatedUpdate),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "A price feed is missing or no recent slot updated all the feeds", body = ErrorResponse),
+    ),
+    tag = "updates"
+)]
+pub async fn aggregated_updates(
+    State(state): State<super::State>,
+    Query(params): Query<AggregatedUpdatesParams>,
+) -> Result<Json<AggregatedUpdate>, ApiError> {
+    let aggregated = state
+        .store
+        .get_price_feeds_with_aggregated_update_data(
+            resolve_price_ids(&state.store, params.ids).await?,
+            params.max_slot_lag.unwrap_or(DEFAULT_MAX_SLOT_LAG),
+        )
+        .await?;
+
+    Ok(Json(AggregatedUpdate {
+        slot:        aggregated.slot,
+        encoding:    params.encoding,
+        data:        params
+            .encoding
+            .encode(&aggregated.wormhole_merkle_update_data),
+        price_feeds: aggregated
+            .price_feeds
+            .into_iter()
+            .map(|price_feed| {
+                RpcPriceFeed::from_price_feed_update(
+                    price_feed,
+                    params.verbose,
+                    false,
+                    params.encoding,
+                )
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VaaParams {
+    /// Id of the price feed, hex encoded with or without a `0x` prefix, or its symbol.
+    #[param(value_type = String)]
+    id:           PriceIdInput,
+    /// Unix timestamp, in seconds. The first update published at or after it is returned.
+    publish_time: UnixTimestamp,
+    /// Encoding of the update data.
+    #[serde(default)]
+    #[param(inline)]
+    encoding:     Encoding,
+}
+
+/// Update data of a single feed at a point in time, along with its actual publish time.
+#[utoipa::path(
+    get,
+    path = "/v2/updates/vaa",
+    params(VaaParams),
+    responses(
+        (status = 200, description = "Update data of the first update published at or after the time", body = GetVaaResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "The price feed is unknown or the publish time is out of its retained window", body = ErrorResponse),
+    ),
+    tag = "updates"
+)]
+pub async fn vaa(
+    State(state): State<super::State>,
+    Query(params): Query<VaaParams>,
+) -> Result<Json<GetVaaResponse>, ApiError> {
+    let (vaa, publish_time) =
+        rest::vaa_at_time(&state.store, params.id, params.publish_time).await?;
+
+    Ok(Json(GetVaaResponse {
+        vaa: params.encoding.encode(vaa),
+        publish_time,
+    }))
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VaaCcipParams {
+    /// Hex encoded price feed id (32 bytes) followed by the big-endian publish time (8 bytes),
+    /// with a `0x` prefix.
+    #[param(value_type = String)]
+    data: GetVaaCcipInput,
+}
+
+/// Update data for the CCIP read gateway. Missing update data is a server error, so that the
+/// gateway retries or tries other endpoints.
+#[utoipa::path(
+    get,
+    path = "/v2/updates/ccip",
+    params(VaaCcipParams),
+    responses(
+        (status = 200, description = "Hex encoded update data", body = GetVaaCcipResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 502, description = "The update data is not available yet", body = ErrorResponse),
+    ),
+    tag = "updates"
+)]
+pub async fn vaa_ccip(
+    State(state): State<super::State>,
+    Query(params): Query<VaaCcipParams>,
+) -> Result<Json<GetVaaCcipResponse>, ApiError> {
+    Ok(Json(rest::vaa_ccip(&state.store, &params.data).await?))
+}
+
+/// Decodes the given update data and checks its signatures and proofs against the guardian sets
+/// known to Hermes. Malformed update data is reported in the response.
+#[utoipa::path(
+    post,
+    path = "/v2/updates/verify",
+    request_body = VerifyUpdateDataRequest,
+    responses(
+        (status = 200, description = "Report of the decoded VAA, merkle root and messages", body = UpdateDataReport),
+        (status = 400, description = "The data cannot be decoded from its encoding", body = ErrorResponse),
+    ),
+    tag = "updates"
+)]
+pub async fn verify_updates(
+    state: State<super::State>,
+    request: JsonBody<VerifyUpdateDataRequest>,
+) -> Result<Json<UpdateDataReport>, ApiError> {
+    verify::verify_update_data(state, request).await
+}
+
+/// Latest TWAP messages of the given feeds.
+#[utoipa::path(
+    get,
+    path = "/v2/twaps/latest",
+    params(PriceFeedsParams),
+    responses(
+        (status = 200, description = "Latest TWAP messages", body = [RpcTwap]),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "A TWAP message is missing", body = ErrorResponse),
+    ),
+    tag = "twaps"
+)]
+pub async fn latest_twaps(
+    State(state): State<super::State>,
+    Query(params): Query<PriceFeedsParams>,
+) -> Result<Json<Vec<RpcTwap>>, ApiError> {
+    let twaps_with_update_data = state
+        .store
+        .get_twaps_with_update_data(
+            resolve_price_ids(&state.store, params.ids).await?,
+            RequestTime::Latest,
+        )
+        .await?;
+
+    Ok(Json(
+        twaps_with_update_data
+            .twaps
+            .into_iter()
+            .map(|twap| {
+                RpcTwap::from_twap_update(twap, params.verbose, params.binary, params.encoding)
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TwapWindowParams {
+    /// Id of the price feed, hex encoded with or without a `0x` prefix, or its symbol.
+    #[param(value_type = String)]
+    id:         PriceIdInput,
+    /// Unix timestamp, in seconds, of the start of the window.
+    start_time: UnixTimestamp,
+    /// Unix timestamp, in seconds, of the end of the window.
+    end_time:   UnixTimestamp,
+    /// Include the update data of the start and end messages.
+    #[serde(default)]
+    binary:     bool,
+    /// Encoding of the update data.
+    #[serde(default)]
+    #[param(inline)]
+    encoding:   Encoding,
+}
+
+/// TWAP of a feed between the first TWAP messages published at or after the start and end of the
+/// window.
+#[utoipa::path(
+    get,
+    path = "/v2/twaps/window",
+    params(TwapWindowParams),
+    responses(
+        (status = 200, description = "TWAP over the actual window", body = RpcTwapWindow),
+        (status = 400, description = "Invalid parameters or window", body = ErrorResponse),
+        (status = 404, description = "A TWAP message is missing", body = ErrorResponse),
+    ),
+    tag = "twaps"
+)]
+pub async fn twap_window(
+    State(state): State<super::State>,
+    Query(params): Query<TwapWindowParams>,
+) -> Result<Json<RpcTwapWindow>, ApiError> {
+    Ok(Json(
+        rest::twap_window(
+            &state.store,
+            params.id,
+            params.start_time,
+            params.end_time,
+            params.binary,
+            params.encoding,
+        )
+        .await?,
+    ))
+}
+
+/// OHLC candles of a feed for the intervals overlapping the range. Intervals without any update
+/// have no candle.
+#[utoipa::path(
+    get,
+    path = "/v2/candles",
+    params(GetCandlesQueryParams),
+    responses(
+        (status = 200, description = "Candles ordered by start time", body = [RpcCandle]),
+        (status = 400, description = "Invalid parameters or range", body = ErrorResponse),
+        (status = 501, description = "The storage backend keeps no history", body = ErrorResponse),
+    ),
+    tag = "candles"
+)]
+pub async fn candles(
+    State(state): State<super::State>,
+    Query(params): Query<GetCandlesQueryParams>,
+) -> Result<Json<Vec<RpcCandle>>, ApiError> {
+    rest::candles(&state, params).await
+}
+
+/// Opens a WebSocket connection to subscribe to the updates of price feeds. The messages are the
+/// JSON `ClientMessage` and `ServerMessage` of the client crate, same as on `/ws`.
+#[utoipa::path(
+    get,
+    path = "/v2/ws",
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol"),
+    ),
+    tag = "ws"
+)]
+pub async fn ws_subscribe(
+    ws: WebSocketUpgrade,
+    state: State<super::State>,
+    client: Option<Extension<super::rate_limit::Client>>,
+) -> impl IntoResponse {
+    ws::ws_route_handler(ws, state, client).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_openapi_document_lists_v2_routes() {
+        let openapi = OPENAPI.clone();
+
+        for path in [
+            "/v2/price_feeds/ids",
+            "/v2/price_feeds/latest",
+            "/v2/price_feeds/at_time",
+            "/v2/price_feeds/range",
+            "/v2/price_feeds/stream",
+            "/v2/updates/latest",
+            "/v2/updates/at_time",
+            "/v2/updates/aggregated",
+            "/v2/updates/vaa",
+            "/v2/updates/ccip",
+            "/v2/updates/verify",
+            "/v2/twaps/latest",
+            "/v2/twaps/window",
+            "/v2/candles",
+            "/v2/ws",
+        ] {
+            assert!(openapi.paths.paths.contains_key(path), "{path} is missing");
+        }
+
+        let schemas = openapi.components.unwrap().schemas;
+        for schema in [
+            "RpcPriceFeed",
+            "Price",
+            "ErrorResponse",
+            "Encoding",
+            "GetVaaResponse",
+            "AggregatedUpdate",
+            "UpdateDataReport",
+            "VaaReport",
+        ] {
+            assert!(schemas.contains_key(schema), "{schema} is missing");
+        }
+    }
+}
@@ -0,0 +1,652 @@
+//! Decoding and verification of update data, to find out why a target chain rejected it.
+//!
+//! The endpoint accepts either `AccumulatorUpdateData` or a legacy batch price attestation VAA and
+//! goes through the same checks as the Pyth contracts: the VAA signatures against the guardian
+//! sets known to Hermes and the merkle proof of each message against the root signed in the VAA.
+//! Instead of stopping at the first failure, every check is reported.
+
+use {
+    super::{
+        extract::JsonBody,
+        types::{
+            Encoding,
+            RpcPriceFeed,
+            RpcPriceFeedExt,
+            RpcTwap,
+            RpcTwapExt,
+        },
+    },
+    crate::store::{
+        types::{
+            PriceFeedUpdate,
+            TwapUpdate,
+        },
+        wormhole::GuardianSet,
+    },
+    anyhow::{
+        anyhow,
+        Result,
+    },
+    axum::{
+        extract::State,
+        Json,
+    },
+    hermes_client::verify::{
+        quorum,
+        recover_signer,
+    },
+    pyth_wormhole_attester_sdk::BatchPriceAttestation,
+    pythnet_sdk::{
+        accumulators::merkle::MerkleRoot,
+        hashers::keccak256_160::Keccak160,
+        messages::Message,
+        wire::{
+            from_slice,
+            v1::{
+                AccumulatorUpdateData,
+                Proof,
+                WormholeMessage,
+                WormholePayload,
+                PYTHNET_ACCUMULATOR_UPDATE_MAGIC,
+            },
+        },
+    },
+    serde_wormhole::RawMessage,
+    std::{
+        collections::BTreeMap,
+        time::{
+            SystemTime,
+            UNIX_EPOCH,
+        },
+    },
+    wormhole_sdk::{
+        vaa::{
+            Body,
+            Header,
+        },
+        Address,
+        Chain,
+        Vaa,
+    },
+};
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+pub struct VerifyUpdateDataRequest {
+    /// The update data, encoded with `encoding`.
+    data:     String,
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UpdateDataReport {
+    AccumulatorUpdate {
+        vaa:         Option<VaaReport>,
+        merkle_root: Option<MerkleRootReport>,
+        updates:     Vec<MerkleUpdateReport>,
+        /// Why the update data would be rejected, if it would be.
+        error:       Option<String>,
+    },
+    BatchAttestation {
+        vaa:                VaaReport,
+        #[schema(value_type = Option<Object>)]
+        price_attestations: Option<BatchPriceAttestation>,
+        error:              Option<String>,
+    },
+}
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct VaaReport {
+    pub version:              u8,
+    pub guardian_set_index:   u32,
+    pub timestamp:            u32,
+    pub nonce:                u32,
+    pub emitter_chain:        u16,
+    pub emitter_address:      String,
+    pub sequence:             u64,
+    pub consistency_level:    u8,
+    pub signatures:           Vec<SignatureReport>,
+    /// Valid signatures needed, if the guardian set is known.
+    pub quorum:               Option<usize>,
+    pub num_valid_signatures: usize,
+    /// Why the VAA would be rejected, if it would be.
+    pub error:                Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct SignatureReport {
+    pub index:           u8,
+    /// Address of the guardian that produced the signature, if it can be recovered.
+    pub signer:          Option<String>,
+    /// Address of the guardian at `index` in the guardian set, if known.
+    pub expected_signer: Option<String>,
+    /// Whether `index` is greater than the index of the previous signature. The signatures must
+    /// be sorted by strictly increasing index, so that a guardian is not counted twice.
+    pub in_order:        bool,
+    /// Whether the signature counts towards the quorum.
+    pub valid:           bool,
+}
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct MerkleRootReport {
+    pub slot:      u64,
+    pub ring_size: u32,
+    pub root:      String,
+}
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct MerkleUpdateReport {
+    pub proof_valid: bool,
+    pub message:     Option<MessageReport>,
+    pub error:       Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MessageReport {
+    PriceFeed(RpcPriceFeed),
+    Twap(RpcTwap),
+}
+
+/// Decodes and verifies the given update data. Malformed data is reported in the response, only
+/// data that cannot be decoded from the requested encoding is rejected.
+pub async fn verify_update_data(
+    State(state): State<super::State>,
+    JsonBody(request): JsonBody<VerifyUpdateDataRequest>,
+) -> Result<Json<UpdateDataReport>, super::error::ApiError> {
+    let data = request
+        .encoding
+        .decode(&request.data)
+        .map_err(|_| super::error::ApiError::InvalidUpdateData)?;
+
+    let guardian_sets = state.store.guardian_set.read().await.clone();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() as u32)
+        .unwrap_or_default();
+
+    Ok(Json(inspect_update_data(&data, &guardian_sets, now)))
+}
+
+pub fn inspect_update_data(
+    data: &[u8],
+    guardian_sets: &BTreeMap<u32, GuardianSet>,
+    now: u32,
+) -> UpdateDataReport {
+    if data.starts_with(PYTHNET_ACCUMULATOR_UPDATE_MAGIC) {
+        inspect_accumulator_update(data, guardian_sets, now)
+    } else {
+        inspect_batch_attestation(data, guardian_sets, now)
+    }
+}
+
+fn inspect_accumulator_update(
+    data: &[u8],
+    guardian_sets: &BTreeMap<u32, GuardianSet>,
+    now: u32,
+) -> UpdateDataReport {
+    let report =
+        |vaa, merkle_root, updates, error: Option<String>| UpdateDataReport::AccumulatorUpdate {
+            vaa,
+            merkle_root,
+            updates,
+            error,
+        };
+
+    let update_data = match AccumulatorUpdateData::try_from_slice(data) {
+        Ok(update_data) => update_data,
+        Err(e) => {
+            return report(
+                None,
+                None,
+                vec![],
+                Some(format!("Invalid update data: {e:?}")),
+            )
+        }
+    };
+
+    let (vaa_bytes, updates) = match update_data.proof {
+        Proof::WormholeMerkle { vaa, updates } => (Vec::<u8>::from(vaa), updates),
+    };
+
+    let vaa = match serde_wormhole::from_slice::<Vaa<&RawMessage>>(&vaa_bytes) {
+        Ok(vaa) => vaa,
+        Err(e) => return report(None, None, vec![], Some(format!("Invalid VAA: {e}"))),
+    };
+
+    let emitted_by_accumulator = vaa.emitter_chain == Chain::Pythnet
+        && vaa.emitter_address == Address(pythnet_sdk::ACCUMULATOR_EMITTER_ADDRESS);
+
+    let (header, body): (Header, Body<&RawMessage>) = vaa.into();
+    let mut vaa_report = inspect_vaa(&header, &body, guardian_sets, now);
+    if !emitted_by_accumulator && vaa_report.error.is_none() {
+        vaa_report.error = Some("VAA is not emitted by the Pythnet accumulator".to_string());
+    }
+
+    let root = match WormholeMessage::try_from_bytes(body.payload) {
+        Ok(message) => match message.payload {
+            WormholePayload::Merkle(root) => root,
+        },
+        Err(e) => {
+            return report(
+                Some(vaa_report),
+                None,
+                vec![],
+                Some(format!("Invalid VAA payload: {e:?}")),
+            )
+        }
+    };
+
+    let merkle_root = MerkleRoot::<Keccak160>::new(root.root);
+    let update_reports: Vec<MerkleUpdateReport> = updates
+        .into_iter()
+        .map(|update| {
+            let message = Vec::<u8>::from(update.message);
+            let proof_valid = merkle_root.check(update.proof, &message);
+            let (message, error) = match decode_message(&message, root.slot) {
+                Ok(message) => (Some(message), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+
+            MerkleUpdateReport {
+                proof_valid,
+                message,
+                error: error.or((!proof_valid).then(|| "Invalid merkle proof".to_string())),
+            }
+        })
+        .collect();
+
+    let error = vaa_report.error.clone().or_else(|| {
+        update_reports
+            .iter()
+            .position(|update| update.error.is_some())
+            .map(|i| format!("Invalid update {i}"))
+    });
+
+    report(
+        Some(vaa_report),
+        Some(MerkleRootReport {
+            slot:      root.slot,
+            ring_size: root.ring_size,
+            root:      hex::encode(root.root),
+        }),
+        update_reports,
+        error,
+    )
+}
+
+fn inspect_batch_attestation(
+    data: &[u8],
+    guardian_sets: &BTreeMap<u32, GuardianSet>,
+    now: u32,
+) -> UpdateDataReport {
+    let vaa = match serde_wormhole::from_slice::<Vaa<&RawMessage>>(data) {
+        Ok(vaa) => vaa,
+        Err(e) => {
+            // Neither format matches, so the failure of the accumulator format is reported too.
+            return inspect_accumulator_update(data, guardian_sets, now)
+                .with_error(format!("Not an accumulator update nor a valid VAA: {e}"));
+        }
+    };
+
+    let (header, body): (Header, Body<&RawMessage>) = vaa.into();
+    let vaa_report = inspect_vaa(&header, &body, guardian_sets, now);
+
+    let (price_attestations, payload_error) =
+        match BatchPriceAttestation::deserialize(payload_bytes(body.payload).as_slice()) {
+            Ok(batch) => (Some(batch), None),
+            Err(e) => (None, Some(format!("Invalid batch attestation: {e}"))),
+        };
+
+    UpdateDataReport::BatchAttestation {
+        error: vaa_report.error.clone().or(payload_error),
+        vaa: vaa_report,
+        price_attestations,
+    }
+}
+
+impl UpdateDataReport {
+    fn with_error(self, error: String) -> Self {
+        match self {
+            UpdateDataReport::AccumulatorUpdate {
+                vaa,
+                merkle_root,
+                updates,
+                ..
+            } => UpdateDataReport::AccumulatorUpdate {
+                vaa,
+                merkle_root,
+                updates,
+                error: Some(error),
+            },
+            report => report,
+        }
+    }
+}
+
+fn payload_bytes(payload: impl AsRef<[u8]>) -> Vec<u8> {
+    payload.as_ref().to_vec()
+}
+
+/// Checks every signature of the VAA against the guardian set it claims to be signed by.
+fn inspect_vaa(
+    header: &Header,
+    body: &Body<&RawMessage>,
+    guardian_sets: &BTreeMap<u32, GuardianSet>,
+    now: u32,
+) -> VaaReport {
+    let guardian_set = guardian_sets.get(&header.guardian_set_index);
+    let digest = body.digest();
+
+    let mut previous_index = None;
+    let signatures: Vec<SignatureReport> = header
+        .signatures
+        .iter()
+        .map(|sig| {
+            let signer = digest
+                .as_ref()
+                .ok()
+                .and_then(|digest| recover_signer(&digest.secp256k_hash, &sig.signature).ok());
+            let expected_signer = guardian_set
+                .and_then(|guardian_set| guardian_set.keys.get(usize::from(sig.index)))
+                .copied();
+            let in_order = previous_index.map_or(true, |previous_index| sig.index > previous_index);
+            previous_index = Some(sig.index);
+
+            SignatureReport {
+                index: sig.index,
+                valid: in_order && signer.is_some() && signer == expected_signer,
+                in_order,
+                signer: signer.map(hex::encode),
+                expected_signer: expected_signer.map(hex::encode),
+            }
+        })
+        .collect();
+
+    let num_valid_signatures = signatures.iter().filter(|sig| sig.valid).count();
+    let quorum = guardian_set.map(|guardian_set| quorum(guardian_set.keys.len()));
+
+    let error = match (guardian_set, quorum) {
+        (None, _) | (_, None) => Some(format!(
+            "Unknown guardian set: {}",
+            header.guardian_set_index
+        )),
+        (Some(guardian_set), _) if guardian_set.is_expired(now) => Some(format!(
+            "Expired guardian set: {}",
+            header.guardian_set_index
+        )),
+        _ if digest.is_err() => Some("Failed to hash the VAA body".to_string()),
+        _ if signatures.iter().any(|sig| !sig.in_order) => {
+            Some("Guardian signatures are repeated or not sorted by increasing index".to_string())
+        }
+        (_, Some(quorum)) if num_valid_signatures < quorum => Some(format!(
+            "Not enough valid signatures. Expected {quorum:?}, received {num_valid_signatures:?}"
+        )),
+        _ => None,
+    };
+
+    VaaReport {
+        version: header.version,
+        guardian_set_index: header.guardian_set_index,
+        timestamp: body.timestamp,
+        nonce: body.nonce,
+        emitter_chain: body.emitter_chain.into(),
+        emitter_address: hex::encode(body.emitter_address.0),
+        sequence: body.sequence,
+        consistency_level: body.consistency_level,
+        signatures,
+        quorum,
+        num_valid_signatures,
+        error,
+    }
+}
+
+/// Decodes a message with the API representation of its type.
+fn decode_message(message: &[u8], slot: u64) -> Result<MessageReport> {
+    let message = from_slice::<byteorder::BE, Message>(message)
+        .map_err(|e| anyhow!("Invalid message: {:?}", e))?;
+
+    // The update data and receive time are not part of the message, they are not reported.
+    Ok(match message {
+        Message::PriceFeedMessage(price_feed) => {
+            MessageReport::PriceFeed(RpcPriceFeed::from_price_feed_update(
+                PriceFeedUpdate {
+                    price_feed,
+                    slot,
+                    received_at: 0,
+                    wormhole_merkle_update_data: vec![],
+                },
+                false,
+                false,
+                Encoding::default(),
+            ))
+        }
+        Message::TwapMessage(twap) => MessageReport::Twap(RpcTwap::from_twap_update(
+            TwapUpdate {
+                twap,
+                slot,
+                received_at: 0,
+                wormhole_merkle_update_data: vec![],
+            },
+            false,
+            false,
+            Encoding::default(),
+        )),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::store::wormhole::test::{
+            create_signed_vaa,
+            guardian_secret_keys,
+            guardian_set,
+        },
+        pythnet_sdk::{
+            accumulators::{
+                merkle::MerkleTree,
+                Accumulator,
+            },
+            messages::PriceFeedMessage,
+            wire::{
+                to_vec,
+                v1::{
+                    MerklePriceUpdate,
+                    WormholeMerkleRoot,
+                },
+            },
+        },
+        secp256k1::SecretKey,
+    };
+
+    fn price_feed_message(feed_id: u8) -> Vec<u8> {
+        to_vec::<_, byteorder::BE>(&Message::PriceFeedMessage(PriceFeedMessage {
+            feed_id:           [feed_id; 32],
+            price:             100,
+            conf:              2,
+            exponent:          -8,
+            publish_time:      10,
+            prev_publish_time: 9,
+            ema_price:         99,
+            ema_conf:          3,
+        }))
+        .unwrap()
+    }
+
+    /// Builds update data for the given messages, with an unsigned VAA from guardian set 0.
+    fn update_data(messages: &[Vec<u8>], tampered: bool) -> Vec<u8> {
+        let tree =
+            MerkleTree::<Keccak160>::from_set(messages.iter().map(|m| m.as_slice())).unwrap();
+        let payload = to_vec::<_, byteorder::BE>(&WormholeMessage::new(WormholePayload::Merkle(
+            WormholeMerkleRoot {
+                slot:      5,
+                ring_size: 10000,
+                root:      tree.root.as_bytes().try_into().unwrap(),
+            },
+        )))
+        .unwrap();
+
+        // An unsigned VAA: header with no signatures, followed by the body.
+        let mut vaa = vec![1];
+        vaa.extend_from_slice(&0u32.to_be_bytes());
+        vaa.push(0);
+        vaa.extend_from_slice(&1u32.to_be_bytes());
+        vaa.extend_from_slice(&2u32.to_be_bytes());
+        vaa.extend_from_slice(&u16::from(Chain::Pythnet).to_be_bytes());
+        vaa.extend_from_slice(&pythnet_sdk::ACCUMULATOR_EMITTER_ADDRESS);
+        vaa.extend_from_slice(&3u64.to_be_bytes());
+        vaa.push(4);
+        vaa.extend_from_slice(&payload);
+
+        to_vec::<_, byteorder::BE>(&AccumulatorUpdateData::new(Proof::WormholeMerkle {
+            vaa:     vaa.into(),
+            updates: messages
+                .iter()
+                .map(|message| {
+                    let mut message = message.clone();
+                    let proof = tree.prove(&message).unwrap();
+                    if tampered {
+                        message[1] ^= 1;
+                    }
+                    MerklePriceUpdate {
+                        message: message.into(),
+                        proof,
+                    }
+                })
+                .collect(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    pub fn test_inspect_accumulator_update() {
+        let messages = vec![price_feed_message(1), price_feed_message(2)];
+        let guardian_sets = BTreeMap::from([(
+            0,
+            GuardianSet {
+                keys:            vec![],
+                expiration_time: 0,
+            },
+        )]);
+
+        match inspect_update_data(&update_data(&messages, false), &guardian_sets, 0) {
+            UpdateDataReport::AccumulatorUpdate {
+                vaa: Some(vaa),
+                merkle_root: Some(merkle_root),
+                updates,
+                error,
+            } => {
+                assert_eq!(vaa.guardian_set_index, 0);
+                assert_eq!(vaa.sequence, 3);
+                assert_eq!(vaa.quorum, Some(0));
+                assert_eq!(merkle_root.slot, 5);
+                assert_eq!(updates.len(), 2);
+                assert!(updates.iter().all(|update| update.proof_valid));
+                match &updates[1].message {
+                    Some(MessageReport::PriceFeed(price_feed)) => {
+                        assert_eq!(price_feed.id, pyth_sdk::PriceIdentifier::new([2; 32]));
+                        assert_eq!(price_feed.price.price, 100);
+                    }
+                    _ => panic!("Unexpected message"),
+                }
+                assert_eq!(error, None);
+            }
+            report => panic!("Unexpected report: {report:?}"),
+        }
+
+        // The unknown guardian set and the tampered messages are reported.
+        match inspect_update_data(&update_data(&messages, true), &BTreeMap::new(), 0) {
+            UpdateDataReport::AccumulatorUpdate {
+                vaa: Some(vaa),
+                updates,
+                error,
+                ..
+            } => {
+                assert_eq!(vaa.error, Some("Unknown guardian set: 0".to_string()));
+                assert!(updates.iter().all(|update| !update.proof_valid));
+                assert_eq!(error, vaa.error);
+            }
+            report => panic!("Unexpected report: {report:?}"),
+        }
+    }
+
+    #[test]
+    pub fn test_inspect_invalid_data() {
+        match inspect_update_data(&[1, 2, 3], &BTreeMap::new(), 0) {
+            UpdateDataReport::AccumulatorUpdate {
+                vaa: None,
+                error: Some(error),
+                ..
+            } => assert!(error.starts_with("Not an accumulator update nor a valid VAA")),
+            report => panic!("Unexpected report: {report:?}"),
+        }
+    }
+
+    /// Inspects a VAA signed by the given guardians of a set of 4, at index 0.
+    fn inspect_signed_vaa(signers: &[(u8, &SecretKey)]) -> VaaReport {
+        let secret_keys = guardian_secret_keys(4);
+        let guardian_sets = BTreeMap::from([(0, guardian_set(&secret_keys))]);
+        let vaa = create_signed_vaa(0, 1, b"payload", signers);
+        let (header, body): (Header, Body<&RawMessage>) =
+            serde_wormhole::from_slice::<Vaa<&RawMessage>>(&vaa)
+                .unwrap()
+                .into();
+        inspect_vaa(&header, &body, &guardian_sets, 0)
+    }
+
+    #[test]
+    pub fn test_inspect_signed_vaa() {
+        let secret_keys = guardian_secret_keys(4);
+
+        let vaa = inspect_signed_vaa(&[
+            (0, &secret_keys[0]),
+            (1, &secret_keys[1]),
+            (3, &secret_keys[3]),
+        ]);
+        assert_eq!(vaa.quorum, Some(3));
+        assert_eq!(vaa.num_valid_signatures, 3);
+        assert!(vaa.signatures.iter().all(|sig| sig.valid && sig.in_order));
+        assert_eq!(vaa.error, None);
+
+        // Below the quorum, with a signature under the index of another guardian.
+        let vaa = inspect_signed_vaa(&[
+            (0, &secret_keys[0]),
+            (1, &secret_keys[1]),
+            (2, &secret_keys[3]),
+        ]);
+        assert_eq!(vaa.num_valid_signatures, 2);
+        assert!(!vaa.signatures[2].valid);
+        assert_ne!(vaa.signatures[2].signer, vaa.signatures[2].expected_signer);
+        assert_eq!(
+            vaa.error,
+            Some("Not enough valid signatures. Expected 3, received 2".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_inspect_signed_vaa_with_repeated_signers() {
+        let secret_keys = guardian_secret_keys(4);
+
+        // The same guardian signing three times only counts once.
+        let vaa = inspect_signed_vaa(&[
+            (0, &secret_keys[0]),
+            (0, &secret_keys[0]),
+            (0, &secret_keys[0]),
+        ]);
+        assert_eq!(vaa.num_valid_signatures, 1);
+        assert_eq!(
+            vaa.signatures
+                .iter()
+                .map(|sig| sig.in_order)
+                .collect::<Vec<_>>(),
+            vec![true, false, false]
+        );
+        assert!(vaa
+            .error
+            .unwrap()
+            .starts_with("Guardian signatures are repeated"));
+    }
+}
@@ -1,11 +1,34 @@
 use {
-    super::types::{
-        PriceIdInput,
-        RpcPriceFeed,
+    super::{
+        error::ApiError,
+        metadata::resolve_price_ids,
+        rate_limit::{
+            Client,
+            RateLimiter,
+        },
+        types::{
+            ClientMessage,
+            Encoding,
+            RpcPriceFeed,
+            RpcPriceFeedExt,
+            RpcTwap,
+            RpcTwapExt,
+            ServerMessage,
+            ServerResponseMessage,
+        },
     },
-    crate::store::{
-        types::RequestTime,
-        Store,
+    crate::{
+        metrics::METRICS,
+        store::{
+            types::{
+                PriceFeedUpdate,
+                RequestTime,
+                Slot,
+                UnixTimestamp,
+                UpdateEvent,
+            },
+            Store,
+        },
     },
     anyhow::{
         anyhow,
@@ -14,6 +37,8 @@ use {
     axum::{
         extract::{
             ws::{
+                close_code,
+                CloseFrame,
                 Message,
                 WebSocket,
                 WebSocketUpgrade,
@@ -21,6 +46,7 @@ use {
             State,
         },
         response::IntoResponse,
+        Extension,
     },
     dashmap::DashMap,
     futures::{
@@ -33,10 +59,6 @@ use {
         StreamExt,
     },
     pyth_sdk::PriceIdentifier,
-    serde::{
-        Deserialize,
-        Serialize,
-    },
     std::{
         collections::HashMap,
         pin::Pin,
@@ -47,33 +69,63 @@ use {
             },
             Arc,
         },
-        time::Duration,
+        time::{
+            Duration,
+            Instant,
+            SystemTime,
+            UNIX_EPOCH,
+        },
+    },
+    tokio::sync::{
+        mpsc,
+        watch,
     },
-    tokio::sync::mpsc,
 };
 
 pub const PING_INTERVAL_DURATION: Duration = Duration::from_secs(30);
 pub const NOTIFICATIONS_CHAN_LEN: usize = 1000;
+/// Oldest update replayed when resuming a subscription.
+pub const MAX_RESUME_AGE: Duration = Duration::from_secs(600);
+/// Most updates of a feed replayed when resuming a subscription. A feed has at most one update
+/// per Pythnet slot of about 400ms, so twice as many updates as slots in `MAX_RESUME_AGE` lets
+/// every subscription within the window resume. A client further behind gets an error and
+/// fetches the missed updates with the REST API instead.
+pub const MAX_MISSED_UPDATES_PER_FEED: usize = (MAX_RESUME_AGE.as_millis() / 200) as usize;
 
 pub async fn ws_route_handler(
     ws: WebSocketUpgrade,
     State(state): State<super::State>,
+    client: Option<Extension<Client>>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| websocket_handler(socket, state))
+    let client = client.map(|Extension(client)| client);
+    ws.on_upgrade(|socket| websocket_handler(socket, state, client))
 }
 
-async fn websocket_handler(stream: WebSocket, state: super::State) {
+/// Handles a WebSocket connection. `client` is set when the API is rate limited, the messages of
+/// the connection then count against the limits of the client.
+async fn websocket_handler(stream: WebSocket, state: super::State, client: Option<Client>) {
     let ws_state = state.ws.clone();
     let id = ws_state.subscriber_counter.fetch_add(1, Ordering::SeqCst);
     log::debug!("New websocket connection, assigning id: {}", id);
 
-    let (notify_sender, notify_receiver) = mpsc::channel::<()>(NOTIFICATIONS_CHAN_LEN);
+    let (notify_sender, notify_receiver) =
+        mpsc::channel::<Arc<UpdateEvent>>(NOTIFICATIONS_CHAN_LEN);
     let (sender, receiver) = stream.split();
-    let mut subscriber =
-        Subscriber::new(id, state.store.clone(), notify_receiver, receiver, sender);
+    let mut subscriber = Subscriber::new(
+        id,
+        state.store.clone(),
+        notify_receiver,
+        receiver,
+        sender,
+        state.rate_limiter.clone().zip(client),
+        state.exit_rx.clone(),
+    );
 
     ws_state.subscribers.insert(id, notify_sender);
+    METRICS.ws_subscribers.inc();
     subscriber.run().await;
+    METRICS.ws_subscribers.dec();
+    ws_state.subscribers.remove(&id);
 }
 
 pub type SubscriberId = usize;
@@ -84,21 +136,33 @@ pub struct Subscriber {
     id:                      SubscriberId,
     closed:                  bool,
     store:                   Arc<Store>,
-    notify_receiver:         mpsc::Receiver<()>,
+    notify_receiver:         mpsc::Receiver<Arc<UpdateEvent>>,
     receiver:                SplitStream<WebSocket>,
     sender:                  SplitSink<WebSocket, Message>,
     price_feeds_with_config: HashMap<PriceIdentifier, PriceFeedClientConfig>,
+    last_sent_prices:        HashMap<PriceIdentifier, SentPrice>,
+    /// Latest update of each feed held back by the `min_interval` of its subscription. It is
+    /// sent once the interval ends, unless a newer update replaces it first.
+    throttled_updates:       HashMap<PriceIdentifier, PriceFeedUpdate>,
+    /// Last slot replayed for each feed of a resumed subscription. The live updates of this slot
+    /// and the ones before are skipped, so the stream hands over from the replay to the live
+    /// updates without going back in time.
+    resumed_until:           HashMap<PriceIdentifier, Slot>,
     ping_interval_future:    Pin<Box<tokio::time::Sleep>>,
     responded_to_ping:       bool,
+    rate_limit:              Option<(Arc<RateLimiter>, Client)>,
+    exit_rx:                 watch::Receiver<bool>,
 }
 
 impl Subscriber {
     pub fn new(
         id: SubscriberId,
         store: Arc<Store>,
-        notify_receiver: mpsc::Receiver<()>,
+        notify_receiver: mpsc::Receiver<Arc<UpdateEvent>>,
         receiver: SplitStream<WebSocket>,
         sender: SplitSink<WebSocket, Message>,
+        rate_limit: Option<(Arc<RateLimiter>, Client)>,
+        exit_rx: watch::Receiver<bool>,
     ) -> Self {
         Self {
             id,
@@ -108,8 +172,13 @@ impl Subscriber {
             receiver,
             sender,
             price_feeds_with_config: HashMap::new(),
+            last_sent_prices: HashMap::new(),
+            throttled_updates: HashMap::new(),
+            resumed_until: HashMap::new(),
             ping_interval_future: Box::pin(tokio::time::sleep(PING_INTERVAL_DURATION)),
             responded_to_ping: true, // We start with true so we don't close the connection immediately
+            rate_limit,
+            exit_rx,
         }
     }
 
@@ -122,13 +191,22 @@ impl Subscriber {
         }
     }
 
+    /// Sends an error response, along with its structured description.
+    async fn send_error(&mut self, error: ApiError) -> Result<()> {
+        self.sender
+            .send(serde_json::to_string(&error.to_server_message())?.into())
+            .await?;
+        Ok(())
+    }
+
     async fn handle_next(&mut self) -> Result<()> {
+        let next_throttled_update_at = self.next_throttled_update_at();
         tokio::select! {
-            maybe_update_feeds = self.notify_receiver.recv() => {
-                if maybe_update_feeds.is_none() {
-                    return Err(anyhow!("Update channel closed. This should never happen. Closing connection."));
-                };
-                self.handle_price_feeds_update().await?;
+            maybe_update_event = self.notify_receiver.recv() => {
+                match maybe_update_event {
+                    Some(event) => self.handle_price_feeds_update(&event).await?,
+                    None => return Err(anyhow!("Update channel closed. This should never happen. Closing connection.")),
+                }
             },
             maybe_message_or_err = self.receiver.next() => {
                 match maybe_message_or_err {
@@ -140,6 +218,9 @@ impl Subscriber {
                     Some(message_or_err) => self.handle_client_message(message_or_err?).await?
                 }
             },
+            _ = sleep_until(next_throttled_update_at) => {
+                self.send_throttled_updates().await?;
+            },
             _  = &mut self.ping_interval_future => {
                 if !self.responded_to_ping {
                     log::debug!("Subscriber {} did not respond to ping. Closing connection.", self.id);
@@ -149,40 +230,248 @@ impl Subscriber {
                 self.responded_to_ping = false;
                 self.sender.send(Message::Ping(vec![])).await?;
                 self.ping_interval_future = Box::pin(tokio::time::sleep(PING_INTERVAL_DURATION));
+            },
+            _ = self.exit_rx.changed() => {
+                log::debug!("Subscriber {}: Hermes is shutting down. Closing connection.", self.id);
+                self.sender
+                    .send(Message::Close(Some(CloseFrame {
+                        code:   close_code::AWAY,
+                        reason: "Hermes is shutting down".into(),
+                    })))
+                    .await?;
+                self.closed = true;
+                return Ok(());
             }
         }
 
         Ok(())
     }
 
-    async fn handle_price_feeds_update(&mut self) -> Result<()> {
-        let price_feed_ids = self.price_feeds_with_config.keys().cloned().collect();
+    /// Sends the updates of the event's slot of the subscribed feeds, unless they are filtered
+    /// out by the throttling and deviation options of their subscription.
+    async fn handle_price_feeds_update(&mut self, event: &UpdateEvent) -> Result<()> {
+        let price_feed_ids: Vec<PriceIdentifier> = self
+            .price_feeds_with_config
+            .keys()
+            .filter(|price_feed_id| event.feed_ids.contains(price_feed_id))
+            .cloned()
+            .collect();
+
+        if price_feed_ids.is_empty() {
+            return Ok(());
+        }
+
+        log::trace!(
+            "Subscriber {}: Sending {} updated feeds for slot {}",
+            self.id,
+            price_feed_ids.len(),
+            event.slot
+        );
+
+        let now = Instant::now();
+        let mut updates = vec![];
+
         for update in self
             .store
-            .get_price_feeds_with_update_data(price_feed_ids, RequestTime::Latest)
+            .get_price_feeds_with_update_data(price_feed_ids, RequestTime::AtSlot(event.slot))
             .await?
             .price_feeds
         {
-            let config = self
-                .price_feeds_with_config
-                .get(&PriceIdentifier::new(update.price_feed.feed_id))
-                .ok_or(anyhow::anyhow!(
-                    "Config missing, price feed list was poisoned during iteration."
-                ))?;
+            let price_feed_id = PriceIdentifier::new(update.price_feed.feed_id);
+            let config =
+                self.price_feeds_with_config
+                    .get(&price_feed_id)
+                    .ok_or(anyhow::anyhow!(
+                        "Config missing, price feed list was poisoned during iteration."
+                    ))?;
+
+            // The update might have been sent already while resuming the subscription.
+            if matches!(
+                self.last_sent_prices.get(&price_feed_id),
+                Some(last_sent) if last_sent.slot >= update.slot
+            ) || matches!(
+                self.resumed_until.get(&price_feed_id),
+                Some(resumed_until) if *resumed_until >= update.slot
+            ) {
+                continue;
+            }
+
+            match config.should_send(
+                self.last_sent_prices.get(&price_feed_id),
+                update.price_feed.price,
+                update.price_feed.conf,
+                now,
+            ) {
+                SendDecision::Send => {
+                    self.throttled_updates.remove(&price_feed_id);
+                    updates.push(update);
+                }
+                SendDecision::Throttle => {
+                    self.throttled_updates.insert(price_feed_id, update);
+                }
+                // The price came back within the filter of the last sent one, so a held back
+                // update would be outdated.
+                SendDecision::Skip => {
+                    self.throttled_updates.remove(&price_feed_id);
+                }
+            }
+        }
+
+        self.send_price_feed_updates(updates, now).await
+    }
+
+    /// When the earliest throttled update can be sent.
+    fn next_throttled_update_at(&self) -> Option<Instant> {
+        self.throttled_updates
+            .keys()
+            .filter_map(|price_feed_id| {
+                let min_interval = self
+                    .price_feeds_with_config
+                    .get(price_feed_id)?
+                    .min_interval?;
+                Some(self.last_sent_prices.get(price_feed_id)?.sent_at + min_interval)
+            })
+            .min()
+    }
+
+    /// Sends the throttled updates whose interval has ended.
+    async fn send_throttled_updates(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let due_price_feed_ids: Vec<PriceIdentifier> = self
+            .throttled_updates
+            .keys()
+            .filter(|price_feed_id| {
+                let config = self.price_feeds_with_config.get(price_feed_id);
+                let last_sent = self.last_sent_prices.get(price_feed_id);
+                match (config.and_then(|config| config.min_interval), last_sent) {
+                    (Some(min_interval), Some(last_sent)) => {
+                        last_sent.sent_at + min_interval <= now
+                    }
+                    _ => true,
+                }
+            })
+            .cloned()
+            .collect();
+
+        let updates = due_price_feed_ids
+            .iter()
+            .filter_map(|price_feed_id| self.throttled_updates.remove(price_feed_id))
+            .filter(|update| {
+                let price_feed_id = PriceIdentifier::new(update.price_feed.feed_id);
+                self.price_feeds_with_config.contains_key(&price_feed_id)
+                    && !matches!(
+                        self.last_sent_prices.get(&price_feed_id),
+                        Some(last_sent) if last_sent.slot >= update.slot
+                    )
+            })
+            .collect();
+
+        self.send_price_feed_updates(updates, now).await
+    }
+
+    /// Sends the updates that passed the filters of their subscription, along with their TWAP if
+    /// requested, and records them as the last sent ones.
+    async fn send_price_feed_updates(
+        &mut self,
+        updates: Vec<PriceFeedUpdate>,
+        now: Instant,
+    ) -> Result<()> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let mut sent_updates = vec![];
+        let mut batched_price_feeds = vec![];
+
+        for update in updates {
+            let price_feed_id = PriceIdentifier::new(update.price_feed.feed_id);
+            let config = match self.price_feeds_with_config.get(&price_feed_id) {
+                Some(config) => config,
+                None => continue,
+            };
+
+            self.last_sent_prices.insert(
+                price_feed_id,
+                SentPrice {
+                    sent_at: now,
+                    slot:    update.slot,
+                    price:   update.price_feed.price,
+                    conf:    update.price_feed.conf,
+                },
+            );
+            sent_updates.push((price_feed_id, update.slot));
+
+            let price_feed = RpcPriceFeed::from_price_feed_update(
+                update,
+                config.verbose,
+                config.binary,
+                config.encoding,
+            );
+
+            if config.batch {
+                batched_price_feeds.push(price_feed);
+                continue;
+            }
 
             self.sender
                 .feed(Message::Text(serde_json::to_string(
-                    &ServerMessage::PriceUpdate {
-                        price_feed: RpcPriceFeed::from_price_feed_update(
-                            update,
-                            config.verbose,
-                            config.binary,
-                        ),
+                    &ServerMessage::PriceUpdate { price_feed },
+                )?))
+                .await?;
+        }
+
+        if !batched_price_feeds.is_empty() {
+            self.sender
+                .feed(Message::Text(serde_json::to_string(
+                    &ServerMessage::PriceUpdates {
+                        price_feeds: batched_price_feeds,
                     },
                 )?))
                 .await?;
         }
 
+        // Not every feed publishes TWAP messages, so they are fetched one by one and missing ones
+        // are skipped instead of failing the whole update. They are only sent along with the
+        // price updates that passed the filters, from the same slot.
+        for (price_feed_id, slot) in sent_updates {
+            let config = match self.price_feeds_with_config.get(&price_feed_id) {
+                Some(config) if config.twap => config,
+                _ => continue,
+            };
+
+            let twap_update = match self
+                .store
+                .get_twaps_with_update_data(vec![price_feed_id], RequestTime::AtSlot(slot))
+                .await
+            {
+                Ok(twaps_with_update_data) => twaps_with_update_data.twaps.into_iter().next(),
+                Err(e) => {
+                    log::debug!(
+                        "Subscriber {}: No TWAP for {:?}: {}",
+                        self.id,
+                        price_feed_id,
+                        e
+                    );
+                    None
+                }
+            };
+
+            if let Some(twap_update) = twap_update {
+                self.sender
+                    .feed(Message::Text(serde_json::to_string(
+                        &ServerMessage::TwapUpdate {
+                            twap: RpcTwap::from_twap_update(
+                                twap_update,
+                                config.verbose,
+                                config.binary,
+                                config.encoding,
+                            ),
+                        },
+                    )?))
+                    .await?;
+            }
+        }
+
         self.sender.flush().await?;
         Ok(())
     }
@@ -210,17 +499,17 @@ impl Subscriber {
             }
         };
 
+        if let Some((rate_limiter, client)) = self.rate_limit.as_ref() {
+            if let Err(retry_after) = rate_limiter.check_ws_message(client, Instant::now()) {
+                self.send_error(ApiError::RateLimited { retry_after })
+                    .await?;
+                return Ok(());
+            }
+        }
+
         match maybe_client_message {
             Err(e) => {
-                self.sender
-                    .send(
-                        serde_json::to_string(&ServerMessage::Response(
-                            ServerResponseMessage::Err {
-                                error: e.to_string(),
-                            },
-                        ))?
-                        .into(),
-                    )
+                self.send_error(ApiError::InvalidParams(e.to_string()))
                     .await?;
                 return Ok(());
             }
@@ -228,17 +517,79 @@ impl Subscriber {
                 ids,
                 verbose,
                 binary,
+                twap,
+                encoding,
+                min_interval_ms,
+                min_price_change_bps,
+                batch,
+                from_slot,
+                from_publish_time,
             }) => {
-                for id in ids {
-                    let price_id: PriceIdentifier = id.into();
-                    self.price_feeds_with_config
-                        .insert(price_id, PriceFeedClientConfig { verbose, binary });
+                let price_ids = match resolve_price_ids(&self.store, ids).await {
+                    Ok(price_ids) => price_ids,
+                    Err(e) => {
+                        self.send_error(e).await?;
+                        return Ok(());
+                    }
+                };
+
+                // The missed updates are fetched before subscribing so that a resume point that
+                // is no longer stored fails the whole subscription.
+                let (missed_updates, resumed_until) = match self
+                    .fetch_missed_updates(price_ids.clone(), from_slot, from_publish_time)
+                    .await
+                {
+                    Ok(missed_updates) => missed_updates,
+                    Err(e) => {
+                        self.send_error(e).await?;
+                        return Ok(());
+                    }
+                };
+
+                for price_id in price_ids {
+                    self.price_feeds_with_config.insert(
+                        price_id,
+                        PriceFeedClientConfig {
+                            verbose,
+                            binary,
+                            twap,
+                            encoding,
+                            min_interval: min_interval_ms.map(Duration::from_millis),
+                            min_price_change_bps,
+                            batch,
+                        },
+                    );
+                    // A new subscription starts with a fresh update.
+                    self.last_sent_prices.remove(&price_id);
+                    self.throttled_updates.remove(&price_id);
+                    match resumed_until {
+                        Some(resumed_until) => self.resumed_until.insert(price_id, resumed_until),
+                        None => self.resumed_until.remove(&price_id),
+                    };
                 }
+
+                self.sender
+                    .send(
+                        serde_json::to_string(&ServerMessage::Response(ServerResponseMessage::Ok))?
+                            .into(),
+                    )
+                    .await?;
+
+                return self.send_missed_updates(missed_updates).await;
             }
             Ok(ClientMessage::Unsubscribe { ids }) => {
-                for id in ids {
-                    let price_id: PriceIdentifier = id.into();
+                let price_ids = match resolve_price_ids(&self.store, ids).await {
+                    Ok(price_ids) => price_ids,
+                    Err(e) => {
+                        self.send_error(e).await?;
+                        return Ok(());
+                    }
+                };
+                for price_id in price_ids {
                     self.price_feeds_with_config.remove(&price_id);
+                    self.last_sent_prices.remove(&price_id);
+                    self.throttled_updates.remove(&price_id);
+                    self.resumed_until.remove(&price_id);
                 }
             }
         }
@@ -251,24 +602,159 @@ impl Subscriber {
 
         Ok(())
     }
+
+    /// Fetches the stored price feed updates after the resume point of a subscription, ordered
+    /// by slot, along with the last slot they cover. Returns no updates if the subscription does
+    /// not resume.
+    ///
+    /// The replay is bounded: it fails if more than `MAX_MISSED_UPDATES_PER_FEED` updates of a
+    /// feed were missed or if they go back more than `MAX_RESUME_AGE`, rather than leave a gap in
+    /// the stream. The limit is applied by the storage lookups, which fetch one more update per
+    /// feed than replayed to tell if some would be left out.
+    async fn fetch_missed_updates(
+        &self,
+        price_ids: Vec<PriceIdentifier>,
+        from_slot: Option<Slot>,
+        from_publish_time: Option<UnixTimestamp>,
+    ) -> Result<(Vec<PriceFeedUpdate>, Option<Slot>), ApiError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| ApiError::Internal)?;
+        let oldest_publish_time = now.saturating_sub(MAX_RESUME_AGE).as_secs() as UnixTimestamp;
+        let too_old = || {
+            ApiError::InvalidParams(format!(
+                "Cannot resume from more than {} seconds ago",
+                MAX_RESUME_AGE.as_secs()
+            ))
+        };
+
+        let mut updates = match (from_slot, from_publish_time) {
+            (None, None) => return Ok((vec![], None)),
+            (Some(_), Some(_)) => {
+                return Err(ApiError::InvalidParams(
+                    "Only one of from_slot and from_publish_time can be set".to_string(),
+                ))
+            }
+            (Some(from_slot), None) => {
+                self.store
+                    .get_price_feed_updates_after_slot(
+                        price_ids,
+                        from_slot,
+                        MAX_MISSED_UPDATES_PER_FEED + 1,
+                    )
+                    .await?
+            }
+            (None, Some(from_publish_time)) => {
+                if from_publish_time < oldest_publish_time {
+                    return Err(too_old());
+                }
+                self.store
+                    .get_price_feed_updates_since(
+                        price_ids,
+                        from_publish_time,
+                        MAX_MISSED_UPDATES_PER_FEED + 1,
+                    )
+                    .await?
+            }
+        };
+
+        let mut missed_updates_per_feed: HashMap<PriceIdentifier, usize> = HashMap::new();
+        for update in updates.iter() {
+            *missed_updates_per_feed
+                .entry(PriceIdentifier::new(update.price_feed.feed_id))
+                .or_default() += 1;
+        }
+        if missed_updates_per_feed
+            .values()
+            .any(|count| *count > MAX_MISSED_UPDATES_PER_FEED)
+        {
+            return Err(ApiError::InvalidParams(format!(
+                "Cannot resume with more than {MAX_MISSED_UPDATES_PER_FEED} missed updates of a feed"
+            )));
+        }
+        if updates
+            .iter()
+            .any(|update| update.price_feed.publish_time < oldest_publish_time)
+        {
+            return Err(too_old());
+        }
+
+        updates.sort_by_key(|update| update.slot);
+        let resumed_until = updates.last().map(|update| update.slot).max(from_slot);
+        Ok((updates, resumed_until))
+    }
+
+    /// Sends the missed updates of a resumed subscription, before any live update. They are sent
+    /// as individual `price_update` messages regardless of the throttling options so that the
+    /// stream has no gap.
+    async fn send_missed_updates(&mut self, updates: Vec<PriceFeedUpdate>) -> Result<()> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        log::debug!(
+            "Subscriber {}: Resuming with {} missed updates",
+            self.id,
+            updates.len()
+        );
+
+        let now = Instant::now();
+        if let Some((rate_limiter, client)) = self.rate_limit.as_ref() {
+            rate_limiter.charge_ws_messages(client, updates.len(), now);
+        }
+
+        for update in updates {
+            let price_feed_id = PriceIdentifier::new(update.price_feed.feed_id);
+            let config = match self.price_feeds_with_config.get(&price_feed_id) {
+                Some(config) => config,
+                None => continue,
+            };
+
+            self.throttled_updates.remove(&price_feed_id);
+            self.last_sent_prices.insert(
+                price_feed_id,
+                SentPrice {
+                    sent_at: now,
+                    slot:    update.slot,
+                    price:   update.price_feed.price,
+                    conf:    update.price_feed.conf,
+                },
+            );
+
+            self.sender
+                .feed(Message::Text(serde_json::to_string(
+                    &ServerMessage::PriceUpdate {
+                        price_feed: RpcPriceFeed::from_price_feed_update(
+                            update,
+                            config.verbose,
+                            config.binary,
+                            config.encoding,
+                        ),
+                    },
+                )?))
+                .await?;
+        }
+
+        self.sender.flush().await?;
+        Ok(())
+    }
 }
 
-pub async fn notify_updates(ws_state: Arc<WsState>) {
-    let closed_subscribers: Vec<Option<SubscriberId>> = join_all(
-        ws_state
-            .subscribers
-            .iter_mut()
-            .map(|subscriber| async move {
-                match subscriber.send(()).await {
+pub async fn notify_updates(ws_state: Arc<WsState>, event: Arc<UpdateEvent>) {
+    let closed_subscribers: Vec<Option<SubscriberId>> =
+        join_all(ws_state.subscribers.iter_mut().map(|subscriber| {
+            let event = event.clone();
+            async move {
+                match subscriber.send(event).await {
                     Ok(_) => None,
                     Err(e) => {
                         log::debug!("Error sending update to subscriber: {}", e);
                         Some(*subscriber.key())
                     }
                 }
-            }),
-    )
-    .await;
+            }
+        }))
+        .await;
 
     // Remove closed_subscribers from ws_state
     closed_subscribers.into_iter().for_each(|id| {
@@ -280,13 +766,88 @@ pub async fn notify_updates(ws_state: Arc<WsState>) {
 
 #[derive(Clone)]
 pub struct PriceFeedClientConfig {
-    verbose: bool,
-    binary:  bool,
+    verbose:              bool,
+    binary:               bool,
+    twap:                 bool,
+    encoding:             Encoding,
+    /// Minimum time between two updates of the feed.
+    min_interval:         Option<Duration>,
+    /// Only send an update if the price moved by at least this many basis points since the last
+    /// sent update, or if the confidence changed.
+    min_price_change_bps: Option<u64>,
+    /// Send the updates of the feed together with the other batched feeds in a single
+    /// `price_updates` message.
+    batch:                bool,
+}
+
+/// What to do with an update of a feed, given the filters of its subscription.
+#[derive(Debug, PartialEq, Eq)]
+enum SendDecision {
+    Send,
+    /// The update passes the price filter but comes before the end of the `min_interval`. It is
+    /// held back and sent at the end of the interval, unless a newer update replaces it.
+    Throttle,
+    Skip,
+}
+
+impl PriceFeedClientConfig {
+    /// What to do with an update with the given price and confidence, given the last update sent
+    /// for the feed.
+    fn should_send(
+        &self,
+        last_sent: Option<&SentPrice>,
+        price: i64,
+        conf: u64,
+        now: Instant,
+    ) -> SendDecision {
+        let last_sent = match last_sent {
+            Some(last_sent) => last_sent,
+            None => return SendDecision::Send,
+        };
+
+        if let Some(min_price_change_bps) = self.min_price_change_bps {
+            // Compare |price - last_price| / |last_price| >= bps / 10000 without divisions.
+            let change = (price as i128 - last_sent.price as i128).unsigned_abs();
+            let price_changed = change > 0
+                && change * 10_000
+                    >= min_price_change_bps as u128 * last_sent.price.unsigned_abs() as u128;
+
+            // A confidence change is always sent.
+            if !price_changed && conf == last_sent.conf {
+                return SendDecision::Skip;
+            }
+        }
+
+        if let Some(min_interval) = self.min_interval {
+            if now.saturating_duration_since(last_sent.sent_at) < min_interval {
+                return SendDecision::Throttle;
+            }
+        }
+
+        SendDecision::Send
+    }
+}
+
+/// Waits until the deadline, or forever if there is none.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The last price sent to a subscriber for a feed, used to filter the next updates.
+#[derive(Clone, Debug)]
+struct SentPrice {
+    sent_at: Instant,
+    slot:    Slot,
+    price:   i64,
+    conf:    u64,
 }
 
 pub struct WsState {
     pub subscriber_counter: AtomicUsize,
-    pub subscribers:        DashMap<SubscriberId, mpsc::Sender<()>>,
+    pub subscribers:        DashMap<SubscriberId, mpsc::Sender<Arc<UpdateEvent>>>,
 }
 
 impl WsState {
@@ -298,37 +859,155 @@ impl WsState {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(tag = "type")]
-enum ClientMessage {
-    #[serde(rename = "subscribe")]
-    Subscribe {
-        ids:     Vec<PriceIdInput>,
-        #[serde(default)]
-        verbose: bool,
-        #[serde(default)]
-        binary:  bool,
-    },
-    #[serde(rename = "unsubscribe")]
-    Unsubscribe { ids: Vec<PriceIdInput> },
-}
+    fn config(
+        min_interval: Option<Duration>,
+        min_price_change_bps: Option<u64>,
+    ) -> PriceFeedClientConfig {
+        PriceFeedClientConfig {
+            verbose: false,
+            binary: false,
+            twap: false,
+            encoding: Encoding::default(),
+            min_interval,
+            min_price_change_bps,
+            batch: false,
+        }
+    }
 
+    #[test]
+    pub fn test_should_send_without_filters() {
+        let now = Instant::now();
+        let last_sent = SentPrice {
+            sent_at: now,
+            slot:    1,
+            price:   100,
+            conf:    1,
+        };
 
-#[derive(Serialize, Debug, Clone)]
-#[serde(tag = "type")]
-enum ServerMessage {
-    #[serde(rename = "response")]
-    Response(ServerResponseMessage),
-    #[serde(rename = "price_update")]
-    PriceUpdate { price_feed: RpcPriceFeed },
-}
+        let config = config(None, None);
+        assert_eq!(config.should_send(None, 100, 1, now), SendDecision::Send);
+        assert_eq!(
+            config.should_send(Some(&last_sent), 100, 1, now),
+            SendDecision::Send
+        );
+    }
+
+    #[test]
+    pub fn test_should_send_respects_min_interval() {
+        let now = Instant::now();
+        let last_sent = SentPrice {
+            sent_at: now,
+            slot:    1,
+            price:   100,
+            conf:    1,
+        };
 
-#[derive(Serialize, Debug, Clone)]
-#[serde(tag = "status")]
-enum ServerResponseMessage {
-    #[serde(rename = "ok")]
-    Ok,
-    #[serde(rename = "error")]
-    Err { error: String },
+        let config = config(Some(Duration::from_secs(1)), None);
+        assert_eq!(config.should_send(None, 100, 1, now), SendDecision::Send);
+        // Updates within the interval are held back to be sent when it ends.
+        assert_eq!(
+            config.should_send(Some(&last_sent), 200, 1, now + Duration::from_millis(999)),
+            SendDecision::Throttle
+        );
+        assert_eq!(
+            config.should_send(Some(&last_sent), 200, 1, now + Duration::from_secs(1)),
+            SendDecision::Send
+        );
+    }
+
+    #[test]
+    pub fn test_should_send_respects_min_price_change() {
+        let now = Instant::now();
+        let last_sent = SentPrice {
+            sent_at: now,
+            slot:    1,
+            price:   -10_000,
+            conf:    1,
+        };
+
+        // 10 bps of 10000 is 10.
+        let config = config(None, Some(10));
+        assert_eq!(
+            config.should_send(Some(&last_sent), -10_000, 1, now),
+            SendDecision::Skip
+        );
+        assert_eq!(
+            config.should_send(Some(&last_sent), -9_991, 1, now),
+            SendDecision::Skip
+        );
+        assert_eq!(
+            config.should_send(Some(&last_sent), -9_990, 1, now),
+            SendDecision::Send
+        );
+        assert_eq!(
+            config.should_send(Some(&last_sent), -10_010, 1, now),
+            SendDecision::Send
+        );
+        // A confidence change is always sent.
+        assert_eq!(
+            config.should_send(Some(&last_sent), -10_000, 2, now),
+            SendDecision::Send
+        );
+
+        // Any move away from a zero price is sent.
+        let last_sent = SentPrice {
+            sent_at: now,
+            slot:    1,
+            price:   0,
+            conf:    1,
+        };
+        assert_eq!(
+            config.should_send(Some(&last_sent), 0, 1, now),
+            SendDecision::Skip
+        );
+        assert_eq!(
+            config.should_send(Some(&last_sent), 1, 1, now),
+            SendDecision::Send
+        );
+    }
+
+    #[test]
+    pub fn test_should_send_filters_price_before_throttling() {
+        let now = Instant::now();
+        let last_sent = SentPrice {
+            sent_at: now,
+            slot:    1,
+            price:   10_000,
+            conf:    1,
+        };
+
+        let config = config(Some(Duration::from_secs(1)), Some(10));
+        let within_interval = now + Duration::from_millis(500);
+        // A move big enough is held back until the end of the interval, a small one is dropped.
+        assert_eq!(
+            config.should_send(Some(&last_sent), 10_010, 1, within_interval),
+            SendDecision::Throttle
+        );
+        assert_eq!(
+            config.should_send(Some(&last_sent), 10_001, 1, within_interval),
+            SendDecision::Skip
+        );
+        assert_eq!(
+            config.should_send(Some(&last_sent), 10_001, 1, now + Duration::from_secs(2)),
+            SendDecision::Skip
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_sleep_until_the_throttled_update_is_due() {
+        let deadline = Instant::now() + Duration::from_millis(10);
+        sleep_until(Some(deadline)).await;
+        assert!(Instant::now() >= deadline);
+
+        // Without a throttled update the branch never fires.
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), sleep_until(None))
+                .await
+                .is_err()
+        );
+    }
 }
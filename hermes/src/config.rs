@@ -1,10 +1,52 @@
 use {
+    crate::store::{
+        storage::{
+            local_storage::LocalStorageConfig,
+            sled_storage::SledStorageConfig,
+        },
+        wormhole::{
+            load_guardian_sets,
+            StaticGuardianSet,
+        },
+    },
+    anyhow::{
+        anyhow,
+        Context,
+        Result,
+    },
+    axum::http::HeaderValue,
     libp2p::Multiaddr,
+    reqwest::Url,
     solana_sdk::pubkey::Pubkey,
-    std::net::SocketAddr,
+    std::{
+        fmt::Display,
+        net::SocketAddr,
+        path::PathBuf,
+        str::FromStr,
+        time::Duration,
+    },
     structopt::StructOpt,
 };
 
+pub mod profile;
+
+use self::profile::{
+    ConfigFile,
+    ProfileConfig,
+    RateLimitConfig,
+    DEFAULT_PROFILE,
+};
+
+/// Backends available to store the message and accumulator states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::EnumString, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum StorageBackend {
+    /// In-memory cache of the most recent states, lost on restart.
+    Local,
+    /// On-disk database that persists the states across restarts.
+    Sled,
+}
+
 /// StructOpt definitions that provides the following arguments and commands:
 ///
 /// Some of these arguments are not currently used, but are included for future use to guide the
@@ -12,45 +54,582 @@ use {
 #[derive(StructOpt, Debug)]
 #[structopt(name = "hermes", about = "Hermes")]
 pub enum Options {
-    Run {
-        #[structopt(long, env = "PYTHNET_WS_ENDPOINT")]
-        pythnet_ws_endpoint: String,
-
-        #[structopt(long, env = "PYTHNET_HTTP_ENDPOINT")]
-        pythnet_http_endpoint: String,
-
-        /// Network ID for Wormhole
-        #[structopt(
-            long,
-            default_value = "/wormhole/mainnet/2",
-            env = "WORMHOLE_NETWORK_ID"
-        )]
-        wh_network_id: String,
-
-        /// Multiaddresses for Wormhole bootstrap peers (separated by comma).
-        #[structopt(
-            long,
-            use_delimiter = true,
-            default_value = "/dns4/wormhole-mainnet-v2-bootstrap.certus.one/udp/8999/quic/p2p/12D3KooWQp644DK27fd3d4Km3jr7gHiuJJ5ZGmy8hH4py7fP4FP7",
-            env = "WORMHOLE_BOOTSTRAP_ADDRS"
-        )]
-        wh_bootstrap_addrs: Vec<Multiaddr>,
-
-        /// Multiaddresses to bind Wormhole P2P to (separated by comma)
-        #[structopt(
-            long,
-            use_delimiter = true,
-            default_value = "/ip4/0.0.0.0/udp/30910/quic,/ip6/::/udp/30910/quic",
-            env = "WORMHOLE_LISTEN_ADDRS"
-        )]
-        wh_listen_addrs: Vec<Multiaddr>,
+    Run(RunOptions),
+
+    /// Validate the configuration and print the effective settings of the selected profile, in
+    /// the configuration file format.
+    CheckConfig(RunOptions),
+
+    /// Replay a recording made with `run --record-path` and serve the resulting state through the
+    /// API, without connecting to Pythnet or the Wormhole network.
+    Replay {
+        /// Path of the recording to replay.
+        #[structopt(long)]
+        input: PathBuf,
+
+        /// Replay speed relative to the recorded timing (e.g. `10` replays ten times faster).
+        /// `0` replays the updates as fast as possible.
+        #[structopt(long, default_value = "1")]
+        speed: f64,
 
         /// The address to bind the API server to.
         #[structopt(long, default_value = "127.0.0.1:33999")]
         api_addr: SocketAddr,
-
-        /// Address of the Wormhole contract on the target PythNet cluster.
-        #[structopt(long, default_value = "H3fxXJ86ADW2PNuDDmZJg6mzTtPxkYCpNuQUTgmJ7AjU")]
-        wh_contract_addr: Pubkey,
     },
 }
+
+/// Arguments of the `run` and `check-config` commands. The settings given here override the ones
+/// of the selected profile.
+#[derive(StructOpt, Debug)]
+pub struct RunOptions {
+    /// TOML configuration file defining settings profiles.
+    #[structopt(long, env = "HERMES_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Settings profile to use: `mainnet`, `testnet`, `devnet` or one defined in the
+    /// configuration file. Defaults to the `profile` of the configuration file, or `mainnet`.
+    #[structopt(long, env = "HERMES_PROFILE")]
+    profile: Option<String>,
+
+    /// Pythnet websocket RPC endpoints to listen for accumulator updates on (separated by comma).
+    /// All of them are subscribed to in parallel and their updates are deduplicated.
+    #[structopt(long, use_delimiter = true, env = "PYTHNET_WS_ENDPOINT")]
+    pythnet_ws_endpoint: Vec<String>,
+
+    /// Pythnet RPC endpoints used to fetch the Wormhole guardian sets (separated by comma), tried
+    /// in order until one succeeds. Can be omitted when the guardian sets are supplied with
+    /// `--guardian-sets-path` or `--guardian-set`.
+    #[structopt(long, use_delimiter = true, env = "PYTHNET_HTTP_ENDPOINT")]
+    pythnet_http_endpoint: Vec<String>,
+
+    /// JSON file with the guardian sets to verify VAAs with, in addition to the ones fetched from
+    /// Pythnet.
+    #[structopt(long, env = "GUARDIAN_SETS_PATH")]
+    guardian_sets_path: Option<PathBuf>,
+
+    /// A guardian set to verify VAAs with, as `<index>:<key>,<key>,...` with hex encoded keys. Can
+    /// be repeated. These sets never expire.
+    #[structopt(long, number_of_values = 1)]
+    guardian_set: Vec<String>,
+
+    /// Network ID for Wormhole
+    #[structopt(long, env = "WORMHOLE_NETWORK_ID")]
+    wh_network_id: Option<String>,
+
+    /// Multiaddresses for Wormhole bootstrap peers (separated by comma).
+    #[structopt(long, use_delimiter = true, env = "WORMHOLE_BOOTSTRAP_ADDRS")]
+    wh_bootstrap_addrs: Vec<Multiaddr>,
+
+    /// Multiaddresses to bind Wormhole P2P to (separated by comma)
+    #[structopt(long, use_delimiter = true, env = "WORMHOLE_LISTEN_ADDRS")]
+    wh_listen_addrs: Vec<Multiaddr>,
+
+    /// The address to bind the API server to.
+    #[structopt(long)]
+    api_addr: Option<SocketAddr>,
+
+    /// Origins allowed to call the API from a browser (separated by comma), `*` allows all of
+    /// them.
+    #[structopt(long, use_delimiter = true, env = "CORS_ORIGINS")]
+    cors_origins: Vec<String>,
+
+    /// Address of the Wormhole contract on the target PythNet cluster.
+    #[structopt(long)]
+    wh_contract_addr: Option<Pubkey>,
+
+    /// Storage backend for message and accumulator states (`local` or `sled`).
+    #[structopt(long, env = "STORAGE_BACKEND")]
+    storage_backend: Option<StorageBackend>,
+
+    /// Directory of the on-disk database used by the `sled` storage backend.
+    #[structopt(long, env = "STORAGE_PATH")]
+    storage_path: Option<PathBuf>,
+
+    /// How long message states are kept by the `sled` storage backend (e.g. `7days`).
+    #[structopt(long, env = "STORAGE_RETENTION")]
+    storage_retention: Option<humantime::Duration>,
+
+    /// Maximum number of message states kept per feed by the `local` storage backend.
+    #[structopt(long, env = "LOCAL_STORAGE_MESSAGES_PER_FEED")]
+    local_storage_messages_per_feed: Option<usize>,
+
+    /// Maximum age of the message states kept by the `local` storage backend, relative to the
+    /// latest message of their feed (e.g. `10min`).
+    #[structopt(long, env = "LOCAL_STORAGE_MESSAGE_AGE")]
+    local_storage_message_age: Option<humantime::Duration>,
+
+    /// Approximate memory budget, in bytes, of the message states of the `local` storage backend.
+    #[structopt(long, env = "LOCAL_STORAGE_MAX_BYTES")]
+    local_storage_max_bytes: Option<usize>,
+
+    /// Maximum number of accumulator states, one per slot, kept by the `local` storage backend.
+    #[structopt(long, env = "LOCAL_STORAGE_ACCUMULATOR_STATES")]
+    local_storage_accumulator_states: Option<usize>,
+
+    /// How long the `local` storage backend keeps an accumulator state waiting for its VAA or its
+    /// messages (e.g. `1min`).
+    #[structopt(long, env = "LOCAL_STORAGE_INCOMPLETE_STATE_TTL")]
+    local_storage_incomplete_state_ttl: Option<humantime::Duration>,
+
+    /// Number of slots behind the newest accumulator state that are kept by the `sled` storage
+    /// backend.
+    #[structopt(long, env = "SLED_STORAGE_ACCUMULATOR_SLOTS")]
+    sled_storage_accumulator_slots: Option<u64>,
+
+    /// How long the `sled` storage backend keeps an accumulator state waiting for its VAA or its
+    /// messages (e.g. `1min`).
+    #[structopt(long, env = "SLED_STORAGE_INCOMPLETE_STATE_TTL")]
+    sled_storage_incomplete_state_ttl: Option<humantime::Duration>,
+
+    /// Record the received updates to this file, to be replayed with `hermes replay`.
+    #[structopt(long, env = "RECORD_PATH")]
+    record_path: Option<PathBuf>,
+
+    /// JSON file with the metadata of the price feeds (symbol, asset class, ...), which allows
+    /// requesting them by symbol.
+    #[structopt(long, env = "PRICE_FEED_METADATA_PATH")]
+    price_feed_metadata_path: Option<PathBuf>,
+
+    /// Address of the Pyth oracle program on Pythnet. When set, the metadata of the price feeds
+    /// is read from its product accounts and refreshed periodically. Requires Pythnet HTTP
+    /// endpoints and cannot be combined with `--price-feed-metadata-path`.
+    #[structopt(long, env = "ORACLE_PROGRAM_ADDR")]
+    oracle_program_addr: Option<Pubkey>,
+}
+
+impl RunOptions {
+    /// Returns the name and the effective settings of the selected profile. The command line
+    /// arguments override the profile, which overrides the network independent defaults.
+    pub fn effective_profile(self) -> Result<(String, ProfileConfig)> {
+        let file = match self.config.as_ref() {
+            Some(path) => ConfigFile::load(path)?,
+            None => ConfigFile::default(),
+        };
+
+        let name = self
+            .profile
+            .clone()
+            .or_else(|| file.profile.clone())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+        let profile = file.resolve_profile(&name)?;
+
+        Ok((
+            name,
+            self.overrides().or(profile).or(ProfileConfig::defaults()),
+        ))
+    }
+
+    /// Returns the validated settings of the selected profile.
+    pub fn settings(self) -> Result<Settings> {
+        let (name, profile) = self.effective_profile()?;
+        Settings::try_from(profile).with_context(|| format!("Invalid profile {name}"))
+    }
+
+    fn overrides(self) -> ProfileConfig {
+        fn non_empty<T: ToString>(values: Vec<T>) -> Option<Vec<String>> {
+            (!values.is_empty()).then(|| values.iter().map(ToString::to_string).collect())
+        }
+
+        ProfileConfig {
+            extends:                            None,
+            pythnet_ws_endpoints:               non_empty(self.pythnet_ws_endpoint),
+            pythnet_http_endpoints:             non_empty(self.pythnet_http_endpoint),
+            guardian_sets_path:                 self.guardian_sets_path,
+            guardian_sets:                      non_empty(self.guardian_set),
+            wh_network_id:                      self.wh_network_id,
+            wh_bootstrap_addrs:                 non_empty(self.wh_bootstrap_addrs),
+            wh_listen_addrs:                    non_empty(self.wh_listen_addrs),
+            wh_contract_addr:                   self.wh_contract_addr.map(|addr| addr.to_string()),
+            api_addr:                           self.api_addr.map(|addr| addr.to_string()),
+            cors_origins:                       non_empty(self.cors_origins),
+            storage_backend:                    self.storage_backend.map(|b| b.to_string()),
+            storage_path:                       self.storage_path,
+            storage_retention:                  self.storage_retention.map(|d| d.to_string()),
+            local_storage_messages_per_feed:    self.local_storage_messages_per_feed,
+            local_storage_message_age:          self
+                .local_storage_message_age
+                .map(|d| d.to_string()),
+            local_storage_max_bytes:            self.local_storage_max_bytes,
+            local_storage_accumulator_states:   self.local_storage_accumulator_states,
+            local_storage_incomplete_state_ttl: self
+                .local_storage_incomplete_state_ttl
+                .map(|d| d.to_string()),
+            sled_storage_accumulator_slots:     self.sled_storage_accumulator_slots,
+            sled_storage_incomplete_state_ttl:  self
+                .sled_storage_incomplete_state_ttl
+                .map(|d| d.to_string()),
+            record_path:                        self.record_path,
+            price_feed_metadata_path:           self.price_feed_metadata_path,
+            oracle_program_addr:                self
+                .oracle_program_addr
+                .map(|addr| addr.to_string()),
+            rate_limit:                         None,
+        }
+    }
+}
+
+/// Where the metadata of the price feeds is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataSource {
+    /// A JSON file, read once on startup.
+    File(PathBuf),
+    /// The product accounts of the Pyth oracle program at this address, polled periodically.
+    Pythnet(Pubkey),
+}
+
+/// Validated settings of a profile.
+#[derive(Debug)]
+pub struct Settings {
+    pub pythnet_ws_endpoints:   Vec<String>,
+    pub pythnet_http_endpoints: Vec<String>,
+    /// The guardian sets given inline and the ones read from the guardian sets file.
+    pub static_guardian_sets:   Vec<StaticGuardianSet>,
+    pub wh_network_id:          String,
+    pub wh_bootstrap_addrs:     Vec<Multiaddr>,
+    pub wh_listen_addrs:        Vec<Multiaddr>,
+    pub wh_contract_addr:       Pubkey,
+    pub api_addr:               SocketAddr,
+    /// Origins allowed by CORS, `None` allows all of them.
+    pub cors_origins:           Option<Vec<HeaderValue>>,
+    pub storage_backend:        StorageBackend,
+    pub storage_path:           PathBuf,
+    pub local_storage:          LocalStorageConfig,
+    pub sled_storage:           SledStorageConfig,
+    pub record_path:            Option<PathBuf>,
+    /// Source of the price feed metadata, `None` if the feeds cannot be looked up by symbol.
+    pub price_feed_metadata:    Option<MetadataSource>,
+    pub rate_limit:             Option<RateLimitConfig>,
+}
+
+impl TryFrom<ProfileConfig> for Settings {
+    type Error = anyhow::Error;
+
+    fn try_from(profile: ProfileConfig) -> Result<Self> {
+        let pythnet_ws_endpoints = required(profile.pythnet_ws_endpoints, "pythnet_ws_endpoints")?;
+        if pythnet_ws_endpoints.is_empty() {
+            return Err(anyhow!(
+                "At least one Pythnet websocket endpoint is required"
+            ));
+        }
+        check_endpoints(&pythnet_ws_endpoints, &["ws", "wss"])?;
+
+        let pythnet_http_endpoints = profile.pythnet_http_endpoints.unwrap_or_default();
+        check_endpoints(&pythnet_http_endpoints, &["http", "https"])?;
+
+        let mut static_guardian_sets =
+            parse_all(&profile.guardian_sets.unwrap_or_default(), "guardian set")?;
+        if let Some(guardian_sets_path) = profile.guardian_sets_path {
+            static_guardian_sets.extend(load_guardian_sets(&guardian_sets_path)?);
+        }
+
+        if pythnet_http_endpoints.is_empty() && static_guardian_sets.is_empty() {
+            return Err(anyhow!(
+                "Guardian sets are required to verify VAAs, set pythnet_http_endpoints, \
+                 guardian_sets_path or guardian_sets"
+            ));
+        }
+
+        let cors_origins = required(profile.cors_origins, "cors_origins")?;
+        let cors_origins = if cors_origins.iter().any(|origin| origin == "*") {
+            if cors_origins.len() > 1 {
+                return Err(anyhow!(
+                    "The `*` CORS origin cannot be combined with others"
+                ));
+            }
+            None
+        } else {
+            Some(parse_all(&cors_origins, "CORS origin")?)
+        };
+
+        let local_storage = LocalStorageConfig {
+            max_messages_per_key:       required(
+                profile.local_storage_messages_per_feed,
+                "local_storage_messages_per_feed",
+            )?,
+            max_message_age:            profile
+                .local_storage_message_age
+                .map(|age| parse_duration(&age))
+                .transpose()?,
+            max_message_bytes:          profile.local_storage_max_bytes,
+            max_accumulator_states:     required(
+                profile.local_storage_accumulator_states,
+                "local_storage_accumulator_states",
+            )?,
+            incomplete_accumulator_ttl: parse_duration(&required(
+                profile.local_storage_incomplete_state_ttl,
+                "local_storage_incomplete_state_ttl",
+            )?)?,
+        };
+        if local_storage.max_messages_per_key == 0 || local_storage.max_accumulator_states == 0 {
+            return Err(anyhow!("The local storage limits must be positive"));
+        }
+
+        let sled_storage = SledStorageConfig {
+            retention:                  parse_duration(&required(
+                profile.storage_retention,
+                "storage_retention",
+            )?)?,
+            max_accumulator_slots:      required(
+                profile.sled_storage_accumulator_slots,
+                "sled_storage_accumulator_slots",
+            )?,
+            incomplete_accumulator_ttl: parse_duration(&required(
+                profile.sled_storage_incomplete_state_ttl,
+                "sled_storage_incomplete_state_ttl",
+            )?)?,
+        };
+        if sled_storage.max_accumulator_slots == 0 {
+            return Err(anyhow!("The sled storage limits must be positive"));
+        }
+
+        let price_feed_metadata = match (
+            profile.price_feed_metadata_path,
+            profile.oracle_program_addr,
+        ) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow!(
+                    "Set either price_feed_metadata_path or oracle_program_addr, not both"
+                ));
+            }
+            (Some(path), None) => Some(MetadataSource::File(path)),
+            (None, Some(addr)) => {
+                if pythnet_http_endpoints.is_empty() {
+                    return Err(anyhow!(
+                        "Pythnet HTTP endpoints are required to read the price feed metadata \
+                         from the oracle program"
+                    ));
+                }
+                Some(MetadataSource::Pythnet(parse(
+                    &addr,
+                    "oracle program address",
+                )?))
+            }
+            (None, None) => None,
+        };
+
+        if let Some(rate_limit) = profile.rate_limit.as_ref() {
+            rate_limit.validate()?;
+        }
+
+        Ok(Self {
+            pythnet_ws_endpoints,
+            pythnet_http_endpoints,
+            static_guardian_sets,
+            wh_network_id: required(profile.wh_network_id, "wh_network_id")?,
+            wh_bootstrap_addrs: parse_all(
+                &required(profile.wh_bootstrap_addrs, "wh_bootstrap_addrs")?,
+                "Wormhole bootstrap address",
+            )?,
+            wh_listen_addrs: parse_all(
+                &required(profile.wh_listen_addrs, "wh_listen_addrs")?,
+                "Wormhole listen address",
+            )?,
+            wh_contract_addr: parse(
+                &required(profile.wh_contract_addr, "wh_contract_addr")?,
+                "Wormhole contract address",
+            )?,
+            api_addr: parse(&required(profile.api_addr, "api_addr")?, "API address")?,
+            cors_origins,
+            storage_backend: parse(
+                &required(profile.storage_backend, "storage_backend")?,
+                "storage backend",
+            )?,
+            storage_path: required(profile.storage_path, "storage_path")?,
+            local_storage,
+            sled_storage,
+            record_path: profile.record_path,
+            price_feed_metadata,
+            rate_limit: profile.rate_limit,
+        })
+    }
+}
+
+fn required<T>(value: Option<T>, name: &str) -> Result<T> {
+    value.ok_or_else(|| anyhow!("Missing setting {}", name))
+}
+
+fn parse<T>(value: &str, what: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|e| anyhow!("Invalid {} {}: {}", what, value, e))
+}
+
+fn parse_all<T>(values: &[String], what: &str) -> Result<Vec<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    values.iter().map(|value| parse(value, what)).collect()
+}
+
+fn parse_duration(value: &str) -> Result<Duration> {
+    Ok(parse::<humantime::Duration>(value, "duration")?.into())
+}
+
+fn check_endpoints(endpoints: &[String], schemes: &[&str]) -> Result<()> {
+    for endpoint in endpoints {
+        let url =
+            Url::parse(endpoint).map_err(|e| anyhow!("Invalid endpoint {}: {}", endpoint, e))?;
+        if !schemes.contains(&url.scheme()) {
+            return Err(anyhow!(
+                "Invalid endpoint {}: expected a {} URL",
+                endpoint,
+                schemes.join(" or ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        profile::builtin_profile,
+    };
+
+    fn devnet_profile() -> ProfileConfig {
+        builtin_profile("devnet")
+            .unwrap()
+            .or(ProfileConfig::defaults())
+    }
+
+    #[test]
+    pub fn test_settings_from_builtin_profile() {
+        let settings = Settings::try_from(devnet_profile()).unwrap();
+
+        assert_eq!(settings.pythnet_ws_endpoints, vec!["ws://127.0.0.1:8900"]);
+        assert_eq!(settings.api_addr, "127.0.0.1:33999".parse().unwrap());
+        assert_eq!(settings.cors_origins, None);
+        assert_eq!(settings.storage_backend, StorageBackend::Local);
+        assert_eq!(
+            settings.sled_storage.retention,
+            Duration::from_secs(7 * 24 * 60 * 60)
+        );
+        assert_eq!(
+            settings.local_storage.incomplete_accumulator_ttl,
+            Duration::from_secs(60)
+        );
+        assert_eq!(settings.wh_listen_addrs.len(), 2);
+
+        // The mainnet profile needs Pythnet endpoints to be given.
+        assert!(Settings::try_from(
+            builtin_profile("mainnet")
+                .unwrap()
+                .or(ProfileConfig::defaults())
+        )
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_settings_validation() {
+        let with = |profile: ProfileConfig| Settings::try_from(profile.or(devnet_profile()));
+
+        assert!(with(ProfileConfig {
+            pythnet_ws_endpoints: Some(vec![]),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(with(ProfileConfig {
+            pythnet_ws_endpoints: Some(vec!["http://127.0.0.1:8899".to_string()]),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(with(ProfileConfig {
+            pythnet_http_endpoints: Some(vec![]),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(with(ProfileConfig {
+            cors_origins: Some(vec!["*".to_string(), "https://a.com".to_string()]),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(with(ProfileConfig {
+            storage_retention: Some("a week".to_string()),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(with(ProfileConfig {
+            local_storage_messages_per_feed: Some(0),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(with(ProfileConfig {
+            sled_storage_accumulator_slots: Some(0),
+            ..Default::default()
+        })
+        .is_err());
+
+        let settings = with(ProfileConfig {
+            pythnet_http_endpoints: Some(vec![]),
+            guardian_sets: Some(vec![
+                "0:58cc3ae5c097b213ce3c81979e1b9f9570746aa5".to_string()
+            ]),
+            cors_origins: Some(vec!["https://a.com".to_string()]),
+            storage_backend: Some("sled".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(settings.static_guardian_sets.len(), 1);
+        assert_eq!(
+            settings.cors_origins,
+            Some(vec![HeaderValue::from_static("https://a.com")])
+        );
+        assert_eq!(settings.storage_backend, StorageBackend::Sled);
+    }
+
+    #[test]
+    pub fn test_price_feed_metadata_source() {
+        let with = |profile: ProfileConfig| Settings::try_from(profile.or(devnet_profile()));
+        let oracle_program_addr = "FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH";
+
+        assert_eq!(with(Default::default()).unwrap().price_feed_metadata, None);
+        assert_eq!(
+            with(ProfileConfig {
+                price_feed_metadata_path: Some(PathBuf::from("metadata.json")),
+                ..Default::default()
+            })
+            .unwrap()
+            .price_feed_metadata,
+            Some(MetadataSource::File(PathBuf::from("metadata.json")))
+        );
+        assert_eq!(
+            with(ProfileConfig {
+                oracle_program_addr: Some(oracle_program_addr.to_string()),
+                ..Default::default()
+            })
+            .unwrap()
+            .price_feed_metadata,
+            Some(MetadataSource::Pythnet(
+                oracle_program_addr.parse().unwrap()
+            ))
+        );
+
+        assert!(with(ProfileConfig {
+            price_feed_metadata_path: Some(PathBuf::from("metadata.json")),
+            oracle_program_addr: Some(oracle_program_addr.to_string()),
+            ..Default::default()
+        })
+        .is_err());
+        // The product accounts are read through the HTTP endpoints.
+        assert!(with(ProfileConfig {
+            pythnet_http_endpoints: Some(vec![]),
+            guardian_sets: Some(vec![
+                "0:58cc3ae5c097b213ce3c81979e1b9f9570746aa5".to_string()
+            ]),
+            oracle_program_addr: Some(oracle_program_addr.to_string()),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(with(ProfileConfig {
+            oracle_program_addr: Some("not-an-address".to_string()),
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -2,62 +2,175 @@
 #![feature(slice_group_by)]
 
 use {
-    crate::store::Store,
-    anyhow::Result,
+    crate::{
+        config::{
+            MetadataSource,
+            StorageBackend,
+        },
+        replay::Recorder,
+        store::{
+            storage::{
+                local_storage::LocalStorage,
+                sled_storage::SledStorage,
+            },
+            Store,
+        },
+    },
+    anyhow::{
+        Context,
+        Result,
+    },
     structopt::StructOpt,
+    tokio::sync::watch,
 };
 
 mod api;
 mod config;
-mod macros;
+mod metrics;
 mod network;
+mod replay;
 mod store;
 
 /// Initialize the Application. This can be invoked either by real main, or by the Geyser plugin.
-async fn init() -> Result<()> {
+///
+/// All the components stop once `exit_rx` signals a shutdown and `init` returns after the RPC
+/// server has drained and the storage has been flushed.
+async fn init(exit_rx: watch::Receiver<bool>) -> Result<()> {
     log::info!("Initializing Hermes...");
 
     // Parse the command line arguments with StructOpt, will exit automatically on `--help` or
     // with invalid arguments.
     match config::Options::from_args() {
-        config::Options::Run {
-            pythnet_ws_endpoint,
-            pythnet_http_endpoint,
-            wh_network_id,
-            wh_bootstrap_addrs,
-            wh_listen_addrs,
-            wh_contract_addr,
-            api_addr,
-        } => {
+        config::Options::Run(options) => {
+            let settings = options.settings()?;
+
             // A channel to emit state updates to api
             let (update_tx, update_rx) = tokio::sync::mpsc::channel(1000);
 
+            let storage = match settings.storage_backend {
+                StorageBackend::Local => LocalStorage::with_config(settings.local_storage),
+                StorageBackend::Sled => {
+                    log::info!(
+                        "Opening sled storage at {}",
+                        settings.storage_path.display()
+                    );
+                    SledStorage::new_instance(&settings.storage_path, settings.sled_storage)?
+                }
+            };
+
+            let recorder = match settings.record_path {
+                Some(record_path) => {
+                    log::info!("Recording updates to {}", record_path.display());
+                    Some(Recorder::new(&record_path)?)
+                }
+                None => None,
+            };
+
             log::info!("Running Hermes...");
-            let store = Store::new_with_local_cache(update_tx, 1000);
+            let store = Store::new(update_tx, storage, recorder);
+
+            for static_guardian_set in settings.static_guardian_sets {
+                let guardian_set = static_guardian_set.guardian_set();
+                log::info!(
+                    "Using static GuardianSet ({}): {}",
+                    static_guardian_set.index,
+                    guardian_set
+                );
+                store
+                    .update_guardian_set(static_guardian_set.index, guardian_set)
+                    .await;
+            }
+
+            let oracle_program_addr = match settings.price_feed_metadata {
+                Some(MetadataSource::File(path)) => {
+                    log::info!("Loading price feed metadata from {}", path.display());
+                    let products = store::metadata::load_file(&path)?;
+                    log::info!("Loaded the metadata of {} price feeds", products.len());
+                    store.update_price_feed_metadata(products).await;
+                    None
+                }
+                Some(MetadataSource::Pythnet(oracle_program_addr)) => Some(oracle_program_addr),
+                None => None,
+            };
+
+            store::spawn_janitor(store.clone(), exit_rx.clone());
 
             // Spawn the P2P layer.
-            log::info!("Starting P2P server on {:?}", wh_listen_addrs);
+            log::info!("Starting P2P server on {:?}", settings.wh_listen_addrs);
             network::p2p::spawn(
                 store.clone(),
-                wh_network_id.to_string(),
-                wh_bootstrap_addrs,
-                wh_listen_addrs,
+                settings.wh_network_id,
+                settings.wh_bootstrap_addrs,
+                settings.wh_listen_addrs,
+                exit_rx.clone(),
             )
             .await?;
 
             // Spawn the Pythnet listener
-            log::info!("Starting Pythnet listener using {}", pythnet_ws_endpoint);
+            log::info!(
+                "Starting Pythnet listeners using {} endpoint(s)",
+                settings.pythnet_ws_endpoints.len()
+            );
             network::pythnet::spawn(
                 store.clone(),
-                pythnet_ws_endpoint,
-                pythnet_http_endpoint,
-                wh_contract_addr,
+                settings.pythnet_ws_endpoints,
+                settings.pythnet_http_endpoints,
+                settings.wh_contract_addr,
+                oracle_program_addr,
+                exit_rx.clone(),
             )
             .await?;
 
             // Run the RPC server and wait for it to shutdown gracefully.
+            log::info!("Starting RPC server on {}", settings.api_addr);
+            api::run(
+                store.clone(),
+                update_rx,
+                settings.api_addr.to_string(),
+                settings.cors_origins,
+                settings.rate_limit,
+                exit_rx,
+            )
+            .await?;
+
+            log::info!("Flushing storage...");
+            store.flush().await?;
+        }
+        config::Options::CheckConfig(options) => {
+            let (name, profile) = options.effective_profile()?;
+            config::Settings::try_from(profile.clone())
+                .with_context(|| format!("Invalid profile {name}"))?;
+
+            println!("# Effective settings of the {name} profile, without credentials");
+            print!("{}", toml::to_string(&profile.redacted())?);
+        }
+        config::Options::Replay {
+            input,
+            speed,
+            api_addr,
+        } => {
+            let (update_tx, update_rx) = tokio::sync::mpsc::channel(1000);
+
+            log::info!("Replaying recording from {}", input.display());
+            let records = replay::RecordReader::open(&input).await?;
+
+            let store = Store::new(update_tx, LocalStorage::new_instance(1000), None);
+
+            // Replay in the background so the API can be queried while the updates come in.
+            log::info!("Replaying at {}x speed", speed);
+            {
+                let store = store.clone();
+                let exit_rx = exit_rx.clone();
+                tokio::spawn(async move {
+                    match replay::replay(store, records, speed, exit_rx).await {
+                        Ok(()) => log::info!("Replay finished"),
+                        Err(e) => log::error!("Replay failed: {:?}", e),
+                    }
+                });
+            }
+
             log::info!("Starting RPC server on {}", api_addr);
-            api::run(store.clone(), update_rx, api_addr.to_string()).await?;
+            api::run(store, update_rx, api_addr.to_string(), None, None, exit_rx).await?;
         }
     }
 
@@ -65,23 +178,34 @@ async fn init() -> Result<()> {
 }
 
 #[tokio::main]
-async fn main() -> Result<!> {
+async fn main() -> Result<()> {
     env_logger::init();
 
+    // A shutdown signal shared by all the components. It is flipped to `true` on Ctrl-C.
+    let (exit_tx, exit_rx) = watch::channel(false);
+
     tokio::spawn(async move {
-        // Launch the application. If it fails, print the full backtrace and exit. RUST_BACKTRACE
-        // should be set to 1 for this otherwise it will only print the top-level error.
-        if let Err(result) = init().await {
-            eprintln!("{}", result.backtrace());
-            for cause in result.chain() {
-                eprintln!("{cause}");
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => {
+                log::info!("Received Ctrl-C, shutting down Hermes...");
+                let _ = exit_tx.send(true);
             }
-            std::process::exit(1);
+            Err(e) => log::error!("Failed to listen for Ctrl-C: {:?}", e),
         }
+        // Keep the sender alive, the components treat a closed channel as a shutdown signal.
+        std::future::pending::<()>().await;
     });
 
-    // TODO: Setup a Ctrl-C handler that waits. We use process::exit(0) for now but we should have
-    // a graceful shutdown with an AtomicBool or similar before production.
-    tokio::signal::ctrl_c().await?;
-    std::process::exit(0);
+    // Launch the application. If it fails, print the full backtrace and exit. RUST_BACKTRACE
+    // should be set to 1 for this otherwise it will only print the top-level error.
+    if let Err(result) = init(exit_rx).await {
+        eprintln!("{}", result.backtrace());
+        for cause in result.chain() {
+            eprintln!("{cause}");
+        }
+        std::process::exit(1);
+    }
+
+    log::info!("Hermes shut down gracefully");
+    Ok(())
 }
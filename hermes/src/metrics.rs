@@ -0,0 +1,305 @@
+//! Prometheus metrics exported by Hermes.
+//!
+//! The metrics live in a process-wide registry so that every component (store, storage backends
+//! and API) can record them without threading a handle through all the constructors. The
+//! registry is served in the OpenMetrics text format by the `/metrics` API route.
+
+use prometheus_client::{
+    encoding::{
+        text::encode,
+        EncodeLabelSet,
+        EncodeLabelValue,
+    },
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{
+            exponential_buckets,
+            Histogram,
+        },
+    },
+    registry::Registry,
+};
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum VaaRejectionReason {
+    /// The VAA bytes could not be parsed.
+    Malformed,
+    /// The VAA is signed by a guardian set that is not known.
+    UnknownGuardianSet,
+    /// The VAA is signed by a previous guardian set that has expired.
+    ExpiredGuardianSet,
+    /// The VAA signatures are not sorted by strictly increasing guardian index.
+    UnsortedSignatures,
+    /// A VAA signature is malformed, its signer cannot be recovered.
+    InvalidSignature,
+    /// Fewer guardians than the quorum of the set signed the VAA.
+    NoQuorum,
+    /// The VAA payload is not a valid accumulator message.
+    InvalidPayload,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct VaaRejectionLabels {
+    pub reason: VaaRejectionReason,
+}
+
+/// Why a valid VAA was skipped without being verified. Unlike the rejections, these are expected
+/// during normal operation.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum VaaIgnoreReason {
+    /// The VAA was emitted by another emitter than the Pythnet accumulator.
+    IgnoredEmitter,
+    /// The VAA sequence was already processed.
+    Duplicate,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct VaaIgnoreLabels {
+    pub reason: VaaIgnoreReason,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum AccumulatorStateOutcome {
+    /// Both the accumulator messages and the VAA of the slot were received.
+    Completed,
+    /// The state was evicted from the storage before receiving both its parts.
+    EvictedIncomplete,
+    /// The state was dropped by the storage janitor after waiting too long for its other part.
+    ExpiredIncomplete,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct AccumulatorStateLabels {
+    pub outcome: AccumulatorStateOutcome,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum EvictionReason {
+    /// The maximum number of message states of the feed was reached.
+    Count,
+    /// The message state is older than the maximum age.
+    Age,
+    /// The memory budget of the storage was exceeded.
+    Memory,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct EvictionLabels {
+    pub reason: EvictionReason,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum RateLimitScope {
+    /// An API request.
+    Request,
+    /// A message sent by a client over a WebSocket connection.
+    WsMessage,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct RateLimitLabels {
+    pub scope: RateLimitScope,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct EndpointLabels {
+    /// Index of the endpoint in the configuration and its host, see `ws_endpoint_label`.
+    pub endpoint: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct FeedLabels {
+    pub feed_id: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct RequestLabels {
+    pub route:  String,
+    pub status: u16,
+}
+
+pub struct Metrics {
+    registry:               Registry,
+    pub vaas_received:      Counter,
+    pub vaas_accepted:      Counter,
+    pub vaas_rejected:      Family<VaaRejectionLabels, Counter>,
+    pub vaas_ignored:       Family<VaaIgnoreLabels, Counter>,
+    pub accumulator_states: Family<AccumulatorStateLabels, Counter>,
+    pub message_evictions:  Family<EvictionLabels, Counter>,
+    pub storage_bytes:      Gauge,
+    pub pythnet_slot:       Family<EndpointLabels, Gauge>,
+    pub pythnet_lag:        Family<EndpointLabels, Gauge>,
+    pub update_latency:     Family<FeedLabels, Histogram>,
+    pub ws_subscribers:     Gauge,
+    pub request_latency:    Family<RequestLabels, Histogram>,
+    pub rate_limited:       Family<RateLimitLabels, Counter>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let mut registry = Registry::with_prefix("hermes");
+
+        let vaas_received = Counter::default();
+        registry.register(
+            "vaas_received",
+            "VAAs received from the Wormhole network",
+            vaas_received.clone(),
+        );
+
+        let vaas_accepted = Counter::default();
+        registry.register(
+            "vaas_accepted",
+            "VAAs verified and stored",
+            vaas_accepted.clone(),
+        );
+
+        let vaas_rejected = Family::<VaaRejectionLabels, Counter>::default();
+        registry.register(
+            "vaas_rejected",
+            "VAAs rejected, by reason",
+            vaas_rejected.clone(),
+        );
+
+        let vaas_ignored = Family::<VaaIgnoreLabels, Counter>::default();
+        registry.register(
+            "vaas_ignored",
+            "VAAs skipped without verification, by reason",
+            vaas_ignored.clone(),
+        );
+
+        let accumulator_states = Family::<AccumulatorStateLabels, Counter>::default();
+        registry.register(
+            "accumulator_states",
+            "Accumulator states completed or evicted while incomplete",
+            accumulator_states.clone(),
+        );
+
+        let message_evictions = Family::<EvictionLabels, Counter>::default();
+        registry.register(
+            "message_evictions",
+            "Message states evicted from the in-memory storage, by reason",
+            message_evictions.clone(),
+        );
+
+        let storage_bytes = Gauge::default();
+        registry.register(
+            "storage_bytes",
+            "Approximate size of the message states held by the in-memory storage",
+            storage_bytes.clone(),
+        );
+
+        let pythnet_slot = Family::<EndpointLabels, Gauge>::default();
+        registry.register(
+            "pythnet_slot",
+            "Latest slot of the accumulator messages received from each Pythnet endpoint",
+            pythnet_slot.clone(),
+        );
+
+        let pythnet_lag = Family::<EndpointLabels, Gauge>::default();
+        registry.register(
+            "pythnet_lag_slots",
+            "Slots behind the most advanced Pythnet endpoint, by endpoint",
+            pythnet_lag.clone(),
+        );
+
+        // Buckets from 0.25s to ~2min.
+        let update_latency = Family::<FeedLabels, Histogram>::new_with_constructor(|| {
+            Histogram::new(exponential_buckets(0.25, 2.0, 10))
+        });
+        registry.register(
+            "update_latency_seconds",
+            "Delay between the publish time of a price feed update and its reception",
+            update_latency.clone(),
+        );
+
+        let ws_subscribers = Gauge::default();
+        registry.register(
+            "ws_subscribers",
+            "Number of connected WebSocket subscribers",
+            ws_subscribers.clone(),
+        );
+
+        // Buckets from 1ms to ~4s.
+        let request_latency = Family::<RequestLabels, Histogram>::new_with_constructor(|| {
+            Histogram::new(exponential_buckets(0.001, 2.0, 13))
+        });
+        registry.register(
+            "request_latency_seconds",
+            "Latency of the REST requests, by route and status",
+            request_latency.clone(),
+        );
+
+        let rate_limited = Family::<RateLimitLabels, Counter>::default();
+        registry.register(
+            "rate_limited",
+            "Requests and WebSocket messages rejected by the rate limiter",
+            rate_limited.clone(),
+        );
+
+        Self {
+            registry,
+            vaas_received,
+            vaas_accepted,
+            vaas_rejected,
+            vaas_ignored,
+            accumulator_states,
+            message_evictions,
+            storage_bytes,
+            pythnet_slot,
+            pythnet_lag,
+            update_latency,
+            ws_subscribers,
+            request_latency,
+            rate_limited,
+        }
+    }
+
+    pub fn reject_vaa(&self, reason: VaaRejectionReason) {
+        self.vaas_rejected
+            .get_or_create(&VaaRejectionLabels { reason })
+            .inc();
+    }
+
+    pub fn ignore_vaa(&self, reason: VaaIgnoreReason) {
+        self.vaas_ignored
+            .get_or_create(&VaaIgnoreLabels { reason })
+            .inc();
+    }
+
+    pub fn record_accumulator_state(&self, outcome: AccumulatorStateOutcome) {
+        self.accumulator_states
+            .get_or_create(&AccumulatorStateLabels { outcome })
+            .inc();
+    }
+
+    pub fn record_eviction(&self, reason: EvictionReason) {
+        self.message_evictions
+            .get_or_create(&EvictionLabels { reason })
+            .inc();
+    }
+
+    pub fn record_rate_limited(&self, scope: RateLimitScope) {
+        self.rate_limited
+            .get_or_create(&RateLimitLabels { scope })
+            .inc();
+    }
+
+    pub fn record_pythnet_slot(&self, endpoint: String, slot: u64, lag: u64) {
+        let labels = EndpointLabels { endpoint };
+        self.pythnet_slot.get_or_create(&labels).set(slot as i64);
+        self.pythnet_lag.get_or_create(&labels).set(lag as i64);
+    }
+
+    /// Encodes all the metrics in the OpenMetrics text format.
+    pub fn encode(&self) -> Result<String, std::fmt::Error> {
+        let mut buffer = String::new();
+        encode(&mut buffer, &self.registry)?;
+        Ok(buffer)
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::new();
+}
@@ -1,2 +1,8 @@
+#[cfg(feature = "native-p2p")]
+pub mod native_p2p;
+#[cfg(not(feature = "native-p2p"))]
 pub mod p2p;
 pub mod pythnet;
+
+#[cfg(feature = "native-p2p")]
+pub use native_p2p as p2p;
@@ -0,0 +1,297 @@
+//! Pure Rust implementation of the Wormhole P2P connection, enabled by the `native-p2p` feature.
+//!
+//! This is a replacement for the Go library used by `p2p.rs`. It joins the Wormhole gossip network
+//! over QUIC, discovers peers through the Kademlia DHT of the network and forwards the VAAs with
+//! quorum received on the broadcast topic to the store. It does not need a Go toolchain and keeps
+//! all its state in the spawned task instead of a global channel.
+
+use {
+    crate::store::{
+        types::Update,
+        Store,
+    },
+    anyhow::{
+        anyhow,
+        Result,
+    },
+    futures::StreamExt,
+    libp2p::Multiaddr,
+    libp2p_native::{
+        core::muxing::StreamMuxerBox,
+        gossipsub,
+        identify,
+        identity,
+        kad,
+        multiaddr::Protocol,
+        swarm::{
+            NetworkBehaviour,
+            SwarmBuilder,
+            SwarmEvent,
+        },
+        PeerId,
+        Transport,
+    },
+    libp2p_quic as quic,
+    prost::Message as _,
+    std::{
+        borrow::Cow,
+        sync::Arc,
+        time::Duration,
+    },
+    tokio::{
+        sync::watch,
+        time::Instant,
+    },
+};
+
+/// The subset of the Wormhole `gossip.proto` definitions needed to extract VAAs. Prost skips the
+/// fields and oneof variants that are not declared here.
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GossipMessage {
+        #[prost(oneof = "gossip_message::Message", tags = "4")]
+        pub message: Option<gossip_message::Message>,
+    }
+
+    pub mod gossip_message {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Message {
+            #[prost(message, tag = "4")]
+            SignedVaaWithQuorum(super::SignedVaaWithQuorum),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SignedVaaWithQuorum {
+        #[prost(bytes = "vec", tag = "1")]
+        pub vaa: Vec<u8>,
+    }
+}
+
+#[derive(NetworkBehaviour)]
+#[behaviour(prelude = "libp2p_native::swarm::derive_prelude")]
+struct Behaviour {
+    gossipsub: gossipsub::Behaviour,
+    kademlia:  kad::Kademlia<kad::store::MemoryStore>,
+    identify:  identify::Behaviour,
+}
+
+/// Extracts the VAA from a gossip message, if it holds a VAA with quorum.
+fn extract_vaa(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    Ok(match proto::GossipMessage::decode(data)?.message {
+        Some(proto::gossip_message::Message::SignedVaaWithQuorum(signed_vaa)) => {
+            Some(signed_vaa.vaa)
+        }
+        None => None,
+    })
+}
+
+/// The config uses the Multiaddr of the libp2p version shared with Solana, which is converted
+/// through its string representation.
+fn convert_multiaddr(addr: &Multiaddr) -> Result<libp2p_native::Multiaddr> {
+    addr.to_string()
+        .parse()
+        .map_err(|e| anyhow!("Invalid multiaddr {}: {}", addr, e))
+}
+
+async fn run(
+    store: Arc<Store>,
+    network_id: &str,
+    wh_bootstrap_addrs: &[Multiaddr],
+    wh_listen_addrs: &[Multiaddr],
+    exit_rx: &mut watch::Receiver<bool>,
+) -> Result<()> {
+    let keypair = identity::Keypair::generate_ed25519();
+    let local_peer_id = PeerId::from(keypair.public());
+
+    // The Wormhole network still uses QUIC draft-29 (`/quic` multiaddrs).
+    let mut quic_config = quic::Config::new(&keypair);
+    quic_config.support_draft_29 = true;
+    let transport = quic::tokio::Transport::new(quic_config)
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+        .boxed();
+
+    let gossipsub = gossipsub::Behaviour::new(
+        gossipsub::MessageAuthenticity::Signed(keypair.clone()),
+        gossipsub::Config::default(),
+    )
+    .map_err(|e| anyhow!("Failed to create gossipsub: {}", e))?;
+
+    // Same protocol name as the Go DHT, which prefixes the network id with another `/`.
+    let mut kademlia_config = kad::KademliaConfig::default();
+    kademlia_config.set_protocol_names(vec![Cow::Owned(
+        format!("/{network_id}/kad/1.0.0").into_bytes(),
+    )]);
+    let kademlia = kad::Kademlia::with_config(
+        local_peer_id,
+        kad::store::MemoryStore::new(local_peer_id),
+        kademlia_config,
+    );
+
+    let identify = identify::Behaviour::new(identify::Config::new(
+        "ipfs/0.1.0".to_string(),
+        keypair.public(),
+    ));
+
+    let mut swarm = SwarmBuilder::with_tokio_executor(
+        transport,
+        Behaviour {
+            gossipsub,
+            kademlia,
+            identify,
+        },
+        local_peer_id,
+    )
+    .build();
+
+    for addr in wh_listen_addrs {
+        swarm.listen_on(convert_multiaddr(addr)?)?;
+    }
+
+    for addr in wh_bootstrap_addrs {
+        let addr = convert_multiaddr(addr)?;
+        let peer_id = match addr.iter().last() {
+            Some(Protocol::P2p(hash)) => match PeerId::from_multihash(hash) {
+                Ok(peer_id) => peer_id,
+                Err(_) => {
+                    log::warn!(
+                        "Bootstrap address {} has an invalid peer id, skipping",
+                        addr
+                    );
+                    continue;
+                }
+            },
+            _ => {
+                log::warn!("Bootstrap address {} has no peer id, skipping", addr);
+                continue;
+            }
+        };
+
+        swarm
+            .behaviour_mut()
+            .kademlia
+            .add_address(&peer_id, addr.clone());
+        if let Err(e) = swarm.dial(addr.clone()) {
+            log::warn!("Failed to dial bootstrap peer {}: {}", addr, e);
+        }
+    }
+
+    if let Err(e) = swarm.behaviour_mut().kademlia.bootstrap() {
+        log::warn!("Failed to bootstrap the DHT: {:?}", e);
+    }
+
+    let topic = gossipsub::IdentTopic::new(format!("{network_id}/broadcast"));
+    swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
+
+    loop {
+        tokio::select! {
+            event = swarm.select_next_some() => match event {
+                SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                    message,
+                    ..
+                })) => match extract_vaa(&message.data) {
+                    Ok(Some(vaa_bytes)) => {
+                        let store = store.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = store.store_update(Update::Vaa(vaa_bytes)).await {
+                                log::error!("Failed to process VAA: {:?}", e);
+                            }
+                        });
+                    }
+                    Ok(None) => {}
+                    Err(e) => log::debug!("Failed to decode gossip message: {:?}", e),
+                },
+                SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received {
+                    peer_id,
+                    info,
+                })) => {
+                    for addr in info.listen_addrs {
+                        swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
+                    }
+                }
+                SwarmEvent::NewListenAddr { address, .. } => {
+                    log::info!("P2P listening on {}", address);
+                }
+                _ => {}
+            },
+            _ = exit_rx.changed() => return Ok(()),
+        }
+    }
+}
+
+// Spawn's the P2P layer as a tokio task that runs until `exit_rx` signals a shutdown. Like the Go
+// implementation, the node is restarted if it fails.
+pub async fn spawn(
+    store: Arc<Store>,
+    network_id: String,
+    wh_bootstrap_addrs: Vec<Multiaddr>,
+    wh_listen_addrs: Vec<Multiaddr>,
+    mut exit_rx: watch::Receiver<bool>,
+) -> Result<()> {
+    tokio::spawn(async move {
+        while !*exit_rx.borrow() {
+            let current_time = Instant::now();
+
+            if let Err(ref e) = run(
+                store.clone(),
+                &network_id,
+                &wh_bootstrap_addrs,
+                &wh_listen_addrs,
+                &mut exit_rx,
+            )
+            .await
+            {
+                log::error!("Error in P2P network listener: {:?}", e);
+            }
+
+            if current_time.elapsed() < Duration::from_secs(30) && !*exit_rx.borrow() {
+                log::error!("P2P network listener is restarting too quickly. Sleeping for 1s");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+
+        log::info!("Shutting down P2P network listener...");
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_extract_vaa_from_gossip_message() {
+        // GossipMessage { signed_vaa_with_quorum (4): SignedVaaWithQuorum { vaa (1): [1, 2, 3] } }
+        let data = [0x22, 0x05, 0x0a, 0x03, 1, 2, 3];
+        assert_eq!(extract_vaa(&data).unwrap(), Some(vec![1, 2, 3]));
+
+        let message = proto::GossipMessage {
+            message: Some(proto::gossip_message::Message::SignedVaaWithQuorum(
+                proto::SignedVaaWithQuorum { vaa: vec![4, 5] },
+            )),
+        };
+        assert_eq!(
+            extract_vaa(&message.encode_to_vec()).unwrap(),
+            Some(vec![4, 5])
+        );
+    }
+
+    #[test]
+    pub fn test_extract_vaa_ignores_other_messages() {
+        // GossipMessage { signed_observation (2): SignedObservation { addr (1): [1] } }
+        let data = [0x12, 0x03, 0x0a, 0x01, 1];
+        assert_eq!(extract_vaa(&data).unwrap(), None);
+
+        assert!(extract_vaa(&[0xff]).is_err());
+    }
+
+    #[test]
+    pub fn test_convert_multiaddr() {
+        let addr: Multiaddr = "/ip4/127.0.0.1/udp/8999/quic".parse().unwrap();
+        assert_eq!(
+            convert_multiaddr(&addr).unwrap().to_string(),
+            "/ip4/127.0.0.1/udp/8999/quic"
+        );
+    }
+}
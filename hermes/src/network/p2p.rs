@@ -8,6 +8,9 @@
 //! in Rust but it should absolutely be removed once QUIC+TLS is supported in Rust. The change to
 //! the program structure should be minimal, and users of the service won't need to change any of
 //! their infrastructure.
+//!
+//! A pure Rust implementation with the same interface lives in `native_p2p.rs` and is used instead
+//! of this module when building with the `native-p2p` feature.
 
 use {
     crate::store::{
@@ -24,12 +27,15 @@ use {
         sync::{
             mpsc::{
                 Receiver,
+                RecvTimeoutError,
                 Sender,
             },
             Arc,
             Mutex,
         },
+        time::Duration,
     },
+    tokio::sync::watch,
 };
 
 extern "C" {
@@ -121,19 +127,27 @@ pub fn bootstrap(
     Ok(())
 }
 
-// Spawn's the P2P layer as a separate thread via Go.
+/// How long to block on the observation channel before checking for shutdown again.
+const OBSERVATION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Spawn's the P2P layer as a separate thread via Go and forwards its observations to the store
+// until `exit_rx` signals a shutdown.
+//
+// The Go thread has no way to be stopped, it is left running and is terminated along with the
+// process.
 pub async fn spawn(
     store: Arc<Store>,
     network_id: String,
     wh_bootstrap_addrs: Vec<Multiaddr>,
     wh_listen_addrs: Vec<Multiaddr>,
+    exit_rx: watch::Receiver<bool>,
 ) -> Result<()> {
     std::thread::spawn(|| bootstrap(network_id, wh_bootstrap_addrs, wh_listen_addrs).unwrap());
 
     tokio::spawn(async move {
         // Listen in the background for new VAA's from the p2p layer
         // and update the state accordingly.
-        loop {
+        while !*exit_rx.borrow() {
             let vaa_bytes = tokio::task::spawn_blocking(|| {
                 let observation = OBSERVATIONS.1.lock();
                 let observation = match observation {
@@ -145,8 +159,11 @@ pub async fn spawn(
                     }
                 };
 
-                match observation.recv() {
-                    Ok(vaa_bytes) => vaa_bytes,
+                // A timeout is used instead of a blocking `recv` so the loop gets a chance to
+                // notice a shutdown even when no observations are coming in.
+                match observation.recv_timeout(OBSERVATION_POLL_INTERVAL) {
+                    Ok(vaa_bytes) => Some(vaa_bytes),
+                    Err(RecvTimeoutError::Timeout) => None,
                     Err(e) => {
                         // This should never happen, but if it does, we want to panic and crash
                         // as it is not recoverable.
@@ -157,6 +174,10 @@ pub async fn spawn(
             .await
             .unwrap();
 
+            let Some(vaa_bytes) = vaa_bytes else {
+                continue;
+            };
+
             let store = store.clone();
             tokio::spawn(async move {
                 if let Err(e) = store.store_update(Update::Vaa(vaa_bytes)).await {
@@ -164,6 +185,8 @@ pub async fn spawn(
                 }
             });
         }
+
+        log::info!("Shutting down P2P observation listener...");
     });
 
     Ok(())
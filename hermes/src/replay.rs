@@ -0,0 +1,498 @@
+//! Recording and replaying of the updates received by Hermes.
+//!
+//! A recording is a file with one JSON `Record` per line. Each record holds an event that went
+//! through the store (a VAA, accumulator messages, or a guardian set) along with the time it was
+//! received. Replaying a recording feeds the events back to a `Store` in the same order and with
+//! the same spacing (optionally accelerated), which makes it possible to run Hermes and reproduce
+//! incidents without a Pythnet node or the Wormhole network.
+
+use {
+    crate::store::{
+        types::{
+            AccumulatorMessages,
+            Update,
+        },
+        wormhole::GuardianSet,
+        Store,
+    },
+    anyhow::{
+        anyhow,
+        Result,
+    },
+    base64::{
+        engine::general_purpose::STANDARD as base64_standard_engine,
+        Engine as _,
+    },
+    borsh::{
+        BorshDeserialize,
+        BorshSerialize,
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    std::{
+        fs::File,
+        io::{
+            BufWriter,
+            Write,
+        },
+        path::Path,
+        sync::Arc,
+        time::{
+            Duration,
+            SystemTime,
+            UNIX_EPOCH,
+        },
+    },
+    tokio::{
+        io::{
+            AsyncBufReadExt,
+            BufReader,
+            Lines,
+        },
+        sync::{
+            mpsc,
+            oneshot,
+            watch,
+        },
+    },
+};
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Time the event was received, in milliseconds since the unix epoch.
+    pub received_at_ms: u64,
+    #[serde(flatten)]
+    pub event:          Event,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A VAA observed on the Wormhole network, encoded in base64.
+    Vaa { data: String },
+    /// Accumulator messages read from Pythnet, Borsh serialized and encoded in base64.
+    AccumulatorMessages { data: String },
+    /// A guardian set, with the keys encoded in hex.
+    GuardianSet {
+        index:           u32,
+        keys:            Vec<String>,
+        #[serde(default)]
+        expiration_time: u32,
+    },
+}
+
+impl Event {
+    pub fn from_vaa(vaa_bytes: &[u8]) -> Self {
+        Event::Vaa {
+            data: base64_standard_engine.encode(vaa_bytes),
+        }
+    }
+
+    pub fn from_accumulator_messages(accumulator_messages: &AccumulatorMessages) -> Result<Self> {
+        Ok(Event::AccumulatorMessages {
+            data: base64_standard_engine.encode(accumulator_messages.try_to_vec()?),
+        })
+    }
+
+    pub fn from_guardian_set(index: u32, guardian_set: &GuardianSet) -> Self {
+        Event::GuardianSet {
+            index,
+            keys: guardian_set.keys.iter().map(hex::encode).collect(),
+            expiration_time: guardian_set.expiration_time,
+        }
+    }
+}
+
+enum RecorderCommand {
+    Record(Record),
+    Flush(oneshot::Sender<Result<()>>),
+}
+
+/// Writes the events received by the store to a recording file.
+///
+/// The file is written by a blocking task, the events are handed to it through a channel so that
+/// recording never blocks the runtime.
+pub struct Recorder {
+    commands: mpsc::UnboundedSender<RecorderCommand>,
+}
+
+impl Recorder {
+    /// Creates a recorder writing to `path`. The file is truncated if it already exists. Must be
+    /// called from within the Tokio runtime.
+    pub fn new(path: &Path) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let (commands, mut commands_rx) = mpsc::unbounded_channel();
+
+        tokio::task::spawn_blocking(move || {
+            while let Some(command) = commands_rx.blocking_recv() {
+                match command {
+                    RecorderCommand::Record(record) => {
+                        if let Err(e) = write_record(&mut writer, &record) {
+                            log::error!("Failed to write record: {:?}", e);
+                        }
+                    }
+                    RecorderCommand::Flush(done) => {
+                        let _ = done.send(writer.flush().map_err(Into::into));
+                    }
+                }
+            }
+
+            // All the senders are gone, the store is being dropped.
+            if let Err(e) = writer.flush() {
+                log::error!("Failed to flush the recording: {:?}", e);
+            }
+        });
+
+        Ok(Self { commands })
+    }
+
+    /// Queues the event to be written, timestamped with the current time.
+    pub fn record(&self, event: Event) -> Result<()> {
+        let record = Record {
+            received_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)?
+                .as_millis()
+                .try_into()?,
+            event,
+        };
+
+        self.commands
+            .send(RecorderCommand::Record(record))
+            .map_err(|_| anyhow!("Recorder stopped"))
+    }
+
+    /// Waits for the queued events to be written and flushes the file.
+    pub async fn flush(&self) -> Result<()> {
+        let (done_tx, done_rx) = oneshot::channel();
+        self.commands
+            .send(RecorderCommand::Flush(done_tx))
+            .map_err(|_| anyhow!("Recorder stopped"))?;
+        done_rx.await?
+    }
+}
+
+fn write_record(writer: &mut impl Write, record: &Record) -> Result<()> {
+    serde_json::to_writer(&mut *writer, record)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Reads the records of a recording file one line at a time, so that recordings of any size can
+/// be replayed.
+pub struct RecordReader {
+    lines:       Lines<BufReader<tokio::fs::File>>,
+    line_number: usize,
+}
+
+impl RecordReader {
+    pub async fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            lines:       BufReader::new(tokio::fs::File::open(path).await?).lines(),
+            line_number: 0,
+        })
+    }
+
+    /// Returns the next record, or `None` at the end of the file. Empty lines are skipped.
+    pub async fn next(&mut self) -> Result<Option<Record>> {
+        while let Some(line) = self.lines.next_line().await? {
+            self.line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            return serde_json::from_str(&line)
+                .map(Some)
+                .map_err(|e| anyhow!("Invalid record on line {}: {}", self.line_number, e));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Feeds the records to the store, in order. The delay between two records is the delay between
+/// their original reception divided by `speed`, a `speed` of 0 replays them without any delay.
+///
+/// Returns early if `exit_rx` signals a shutdown.
+pub async fn replay(
+    store: Arc<Store>,
+    mut records: RecordReader,
+    speed: f64,
+    mut exit_rx: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut previous_received_at_ms = None;
+
+    while let Some(record) = records.next().await? {
+        if *exit_rx.borrow() {
+            break;
+        }
+
+        if let Some(previous_received_at_ms) = previous_received_at_ms {
+            let delay = replay_delay(previous_received_at_ms, record.received_at_ms, speed);
+            if !delay.is_zero() {
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = exit_rx.changed() => break,
+                }
+            }
+        }
+        previous_received_at_ms = Some(record.received_at_ms);
+
+        match record.event {
+            Event::GuardianSet {
+                index,
+                keys,
+                expiration_time,
+            } => {
+                let keys = keys
+                    .iter()
+                    .map(|key| {
+                        <[u8; 20]>::try_from(hex::decode(key)?)
+                            .map_err(|_| anyhow!("Invalid guardian key: {}", key))
+                    })
+                    .collect::<Result<_>>()?;
+                store
+                    .update_guardian_set(
+                        index,
+                        GuardianSet {
+                            keys,
+                            expiration_time,
+                        },
+                    )
+                    .await;
+            }
+            Event::Vaa { data } => {
+                let update = Update::Vaa(base64_standard_engine.decode(data)?);
+                // Rejected updates are expected in a recording (e.g. VAAs signed by an unknown
+                // guardian set), so they are logged and the replay goes on like the live listeners
+                // would.
+                if let Err(e) = store.store_update(update).await {
+                    log::warn!("Failed to replay VAA: {:?}", e);
+                }
+            }
+            Event::AccumulatorMessages { data } => {
+                let accumulator_messages =
+                    AccumulatorMessages::try_from_slice(&base64_standard_engine.decode(data)?)?;
+                if let Err(e) = store
+                    .store_update(Update::AccumulatorMessages(accumulator_messages))
+                    .await
+                {
+                    log::warn!("Failed to replay accumulator messages: {:?}", e);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn replay_delay(previous_received_at_ms: u64, received_at_ms: u64, speed: f64) -> Duration {
+    if speed <= 0.0 {
+        return Duration::ZERO;
+    }
+
+    Duration::from_millis(received_at_ms.saturating_sub(previous_received_at_ms)).div_f64(speed)
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::store::{
+            storage::local_storage::LocalStorage,
+            types::RequestTime,
+            wormhole::test::{
+                create_signed_vaa,
+                guardian_secret_keys,
+                guardian_set,
+            },
+        },
+        pyth_sdk::PriceIdentifier,
+        pythnet_sdk::{
+            accumulators::{
+                merkle::MerkleTree,
+                Accumulator,
+            },
+            hashers::keccak256_160::Keccak160,
+            messages::{
+                Message,
+                PriceFeedMessage,
+            },
+            wire::{
+                to_vec,
+                v1::{
+                    WormholeMerkleRoot,
+                    WormholeMessage,
+                    WormholePayload,
+                },
+            },
+        },
+    };
+
+    fn temporary_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("hermes-replay-test-{}", rand::random::<u64>()))
+    }
+
+    async fn read_all_records(path: &Path) -> Vec<Record> {
+        let mut reader = RecordReader::open(path).await.unwrap();
+        let mut records = vec![];
+        while let Some(record) = reader.next().await.unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    #[tokio::test]
+    pub async fn test_recorded_events_are_read_back() {
+        let accumulator_messages = AccumulatorMessages {
+            magic:        *b"PAS1",
+            slot:         10,
+            ring_size:    10000,
+            raw_messages: vec![vec![1, 2, 3], vec![4, 5]],
+        };
+
+        let events = vec![
+            Event::from_guardian_set(
+                3,
+                &GuardianSet {
+                    keys:            vec![[7; 20]],
+                    expiration_time: 0,
+                },
+            ),
+            Event::from_vaa(&[1, 2, 3]),
+            Event::from_accumulator_messages(&accumulator_messages).unwrap(),
+        ];
+
+        let path = temporary_path();
+        let recorder = Recorder::new(&path).unwrap();
+        for event in events.iter() {
+            recorder.record(event.clone()).unwrap();
+        }
+        recorder.flush().await.unwrap();
+
+        let records = read_all_records(&path).await;
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.event.clone())
+                .collect::<Vec<_>>(),
+            events
+        );
+        assert!(records
+            .windows(2)
+            .all(|pair| pair[0].received_at_ms <= pair[1].received_at_ms));
+
+        // The accumulator messages are recovered byte for byte.
+        match &records[2].event {
+            Event::AccumulatorMessages { data } => assert_eq!(
+                AccumulatorMessages::try_from_slice(&base64_standard_engine.decode(data).unwrap())
+                    .unwrap(),
+                accumulator_messages
+            ),
+            _ => panic!("Unexpected event"),
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn test_replayed_recording_rebuilds_the_store() {
+        let raw_messages = (1..=2)
+            .map(|feed_id| {
+                to_vec::<_, byteorder::BE>(&Message::PriceFeedMessage(PriceFeedMessage {
+                    feed_id:           [feed_id; 32],
+                    price:             100 * feed_id as i64,
+                    conf:              2,
+                    exponent:          -8,
+                    publish_time:      10,
+                    prev_publish_time: 9,
+                    ema_price:         99,
+                    ema_conf:          3,
+                }))
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let tree =
+            MerkleTree::<Keccak160>::from_set(raw_messages.iter().map(|m| m.as_slice())).unwrap();
+        let payload = to_vec::<_, byteorder::BE>(&WormholeMessage::new(WormholePayload::Merkle(
+            WormholeMerkleRoot {
+                slot:      5,
+                ring_size: 10000,
+                root:      tree.root.as_bytes().try_into().unwrap(),
+            },
+        )))
+        .unwrap();
+
+        let secret_keys = guardian_secret_keys(1);
+        let vaa = create_signed_vaa(0, 1, &payload, &[(0, &secret_keys[0])]);
+        let accumulator_messages = AccumulatorMessages {
+            magic: *b"PAS1",
+            slot: 5,
+            ring_size: 10000,
+            raw_messages,
+        };
+
+        // Record the updates going through a store. The duplicate VAA is filtered out before
+        // being recorded.
+        let path = temporary_path();
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(10);
+        let store = Store::new(
+            update_tx,
+            LocalStorage::new_instance(10),
+            Some(Recorder::new(&path).unwrap()),
+        );
+        store
+            .update_guardian_set(0, guardian_set(&secret_keys))
+            .await;
+        store
+            .store_update(Update::AccumulatorMessages(accumulator_messages))
+            .await
+            .unwrap();
+        store.store_update(Update::Vaa(vaa.clone())).await.unwrap();
+        store.store_update(Update::Vaa(vaa)).await.unwrap();
+        store.flush().await.unwrap();
+        assert_eq!(read_all_records(&path).await.len(), 3);
+
+        // Replaying the recording in a fresh store leads to the same prices.
+        let (update_tx, mut update_rx) = tokio::sync::mpsc::channel(10);
+        let replayed_store = Store::new(update_tx, LocalStorage::new_instance(10), None);
+        let (_exit_tx, exit_rx) = watch::channel(false);
+        replay(
+            replayed_store.clone(),
+            RecordReader::open(&path).await.unwrap(),
+            0.0,
+            exit_rx,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(update_rx.recv().await.unwrap().slot, 5);
+        let price_feeds = replayed_store
+            .get_price_feeds_with_update_data(
+                vec![PriceIdentifier::new([1; 32]), PriceIdentifier::new([2; 32])],
+                RequestTime::Latest,
+            )
+            .await
+            .unwrap()
+            .price_feeds;
+        assert_eq!(
+            price_feeds
+                .iter()
+                .map(|update| (update.slot, update.price_feed.price))
+                .collect::<Vec<_>>(),
+            vec![(5, 100), (5, 200)]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    pub fn test_replay_delay_scales_with_speed() {
+        assert_eq!(replay_delay(1000, 3000, 1.0), Duration::from_secs(2));
+        assert_eq!(replay_delay(1000, 3000, 4.0), Duration::from_millis(500));
+        assert_eq!(replay_delay(1000, 3000, 0.0), Duration::ZERO);
+        // Out of order timestamps don't make the replay wait.
+        assert_eq!(replay_delay(3000, 1000, 1.0), Duration::ZERO);
+    }
+}
@@ -1,5 +1,14 @@
 use {
     self::{
+        candles::{
+            candles_range,
+            Candle,
+            CandleBuilder,
+        },
+        metadata::{
+            MetadataIndex,
+            ProductMetadata,
+        },
         proof::wormhole_merkle::construct_update_data,
         storage::{
             MessageState,
@@ -7,24 +16,48 @@ use {
             StorageInstance,
         },
         types::{
+            AggregatedPriceFeedsWithUpdateData,
+            LookupError,
             PriceFeedUpdate,
+            PriceFeedUpdatesPage,
             PriceFeedsWithUpdateData,
+            RangePosition,
             RequestTime,
+            Slot,
+            TwapUpdate,
+            TwapsWithUpdateData,
             Update,
+            UpdateEvent,
         },
         wormhole::GuardianSet,
     },
-    crate::store::{
-        proof::wormhole_merkle::{
-            construct_message_states_proofs,
-            store_wormhole_merkle_verified_message,
+    crate::{
+        metrics::{
+            AccumulatorStateOutcome,
+            FeedLabels,
+            VaaIgnoreReason,
+            VaaRejectionReason,
+            METRICS,
         },
-        storage::CompletedAccumulatorState,
-        types::{
-            ProofSet,
-            UnixTimestamp,
+        replay::{
+            Event,
+            Recorder,
+        },
+        store::{
+            proof::wormhole_merkle::{
+                construct_message_states_proofs,
+                store_wormhole_merkle_verified_message,
+            },
+            storage::CompletedAccumulatorState,
+            types::{
+                ProofSet,
+                UnixTimestamp,
+            },
+            wormhole::{
+                vaa_rejection_reason,
+                verify_vaa,
+            },
         },
-        wormhole::verify_vaa,
     },
     anyhow::{
         anyhow,
@@ -49,6 +82,7 @@ use {
         collections::{
             BTreeMap,
             BTreeSet,
+            HashMap,
             HashSet,
         },
         sync::Arc,
@@ -60,6 +94,7 @@ use {
     tokio::{
         sync::{
             mpsc::Sender,
+            watch,
             RwLock,
         },
         time::{
@@ -74,29 +109,49 @@ use {
     },
 };
 
+pub mod candles;
+pub mod metadata;
 pub mod proof;
 pub mod storage;
+pub mod twap;
 pub mod types;
 pub mod wormhole;
 
 const OBSERVED_CACHE_SIZE: usize = 1000;
 
+/// Interval between two runs of the storage janitor.
+const JANITOR_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of messages read from the storage at once when building candles.
+const CANDLE_MESSAGES_PAGE_SIZE: usize = 1000;
+
 pub struct Store {
     pub storage:                  StorageInstance,
     pub observed_vaa_seqs:        RwLock<BTreeSet<u64>>,
     pub guardian_set:             RwLock<BTreeMap<u32, GuardianSet>>,
-    pub update_tx:                Sender<()>,
+    pub update_tx:                Sender<UpdateEvent>,
     pub last_completed_update_at: RwLock<Option<Instant>>,
+    /// Records the updates and guardian sets going through the store, when enabled. VAAs of
+    /// other emitters and duplicate VAAs are not recorded.
+    pub recorder:                 Option<Recorder>,
+    /// Empty unless a source of price feed metadata is configured.
+    pub price_feed_metadata:      RwLock<MetadataIndex>,
 }
 
 impl Store {
-    pub fn new_with_local_cache(update_tx: Sender<()>, cache_size: u64) -> Arc<Self> {
+    pub fn new(
+        update_tx: Sender<UpdateEvent>,
+        storage: StorageInstance,
+        recorder: Option<Recorder>,
+    ) -> Arc<Self> {
         Arc::new(Self {
-            storage: storage::local_storage::LocalStorage::new_instance(cache_size),
+            storage,
             observed_vaa_seqs: RwLock::new(Default::default()),
             guardian_set: RwLock::new(Default::default()),
             update_tx,
             last_completed_update_at: RwLock::new(None),
+            recorder,
+            price_feed_metadata: RwLock::new(Default::default()),
         })
     }
 
@@ -104,25 +159,38 @@ impl Store {
     pub async fn store_update(&self, update: Update) -> Result<()> {
         let slot = match update {
             Update::Vaa(vaa_bytes) => {
+                METRICS.vaas_received.inc();
+
                 let vaa =
-                    serde_wormhole::from_slice::<Vaa<&serde_wormhole::RawMessage>>(&vaa_bytes)?;
+                    serde_wormhole::from_slice::<Vaa<&serde_wormhole::RawMessage>>(&vaa_bytes)
+                        .map_err(|e| {
+                            METRICS.reject_vaa(VaaRejectionReason::Malformed);
+                            e
+                        })?;
 
                 if vaa.emitter_chain != Chain::Pythnet
                     || vaa.emitter_address != Address(pythnet_sdk::ACCUMULATOR_EMITTER_ADDRESS)
                 {
+                    METRICS.ignore_vaa(VaaIgnoreReason::IgnoredEmitter);
                     return Ok(()); // Ignore VAA from other emitters
                 }
 
                 if self.observed_vaa_seqs.read().await.contains(&vaa.sequence) {
+                    METRICS.ignore_vaa(VaaIgnoreReason::Duplicate);
                     return Ok(()); // Ignore VAA if we have already seen it
                 }
 
+                // VAAs are recorded before being verified so that a replay goes through the same
+                // steps, including the rejection of the invalid ones.
+                self.record(|| Ok(Event::from_vaa(&vaa_bytes)));
+
                 let vaa = verify_vaa(self, vaa).await;
 
                 let vaa = match vaa {
                     Ok(vaa) => vaa,
                     Err(err) => {
                         log::info!("Ignoring invalid VAA: {:?}", err);
+                        METRICS.reject_vaa(vaa_rejection_reason(&err));
                         return Ok(());
                     }
                 };
@@ -135,16 +203,24 @@ impl Store {
                     }
                 }
 
-                match WormholeMessage::try_from_bytes(vaa.payload)?.payload {
+                let message = WormholeMessage::try_from_bytes(vaa.payload).map_err(|e| {
+                    METRICS.reject_vaa(VaaRejectionReason::InvalidPayload);
+                    e
+                })?;
+
+                match message.payload {
                     WormholePayload::Merkle(proof) => {
                         log::info!("Storing merkle proof for slot {:?}", proof.slot,);
                         store_wormhole_merkle_verified_message(self, proof.clone(), vaa_bytes)
                             .await?;
+                        METRICS.vaas_accepted.inc();
                         proof.slot
                     }
                 }
             }
             Update::AccumulatorMessages(accumulator_messages) => {
+                self.record(|| Event::from_accumulator_messages(&accumulator_messages));
+
                 let slot = accumulator_messages.slot;
                 log::info!("Storing accumulator messages for slot {:?}.", slot,);
                 self.storage
@@ -175,9 +251,10 @@ impl Store {
 
         // Once the accumulator reaches a complete state for a specific slot
         // we can build the message states
-        self.build_message_states(completed_state).await?;
+        let feed_ids = self.build_message_states(completed_state).await?;
+        METRICS.record_accumulator_state(AccumulatorStateOutcome::Completed);
 
-        self.update_tx.send(()).await?;
+        self.update_tx.send(UpdateEvent { slot, feed_ids }).await?;
 
         self.last_completed_update_at
             .write()
@@ -187,12 +264,17 @@ impl Store {
         Ok(())
     }
 
-    async fn build_message_states(&self, completed_state: CompletedAccumulatorState) -> Result<()> {
+    /// Builds and stores the message states of a completed accumulator state. Returns the ids of
+    /// the feeds that got a new message state.
+    async fn build_message_states(
+        &self,
+        completed_state: CompletedAccumulatorState,
+    ) -> Result<HashSet<PriceIdentifier>> {
         let wormhole_merkle_message_states_proofs =
             construct_message_states_proofs(&completed_state)?;
 
-        let current_time: UnixTimestamp =
-            SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as _;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let current_time: UnixTimestamp = now.as_secs() as _;
 
         let message_states = completed_state
             .accumulator_messages
@@ -218,13 +300,45 @@ impl Store {
 
         log::info!("Message states len: {:?}", message_states.len());
 
+        for message_state in message_states.iter() {
+            if let Message::PriceFeedMessage(price_feed) = message_state.message {
+                METRICS
+                    .update_latency
+                    .get_or_create(&FeedLabels {
+                        feed_id: hex::encode(price_feed.feed_id),
+                    })
+                    .observe(now.as_secs_f64() - price_feed.publish_time as f64);
+            }
+        }
+
+        let feed_ids = message_states
+            .iter()
+            .map(|message_state| PriceIdentifier::new(message_state.message.feed_id()))
+            .collect();
+
         self.storage.store_message_states(message_states).await?;
 
-        Ok(())
+        Ok(feed_ids)
+    }
+
+    /// Records the event if recording is enabled. Failing to record is logged, it never fails
+    /// the update being recorded.
+    fn record(&self, event: impl FnOnce() -> Result<Event>) {
+        if let Some(recorder) = &self.recorder {
+            if let Err(e) = event().and_then(|event| recorder.record(event)) {
+                log::error!("Failed to record event: {:?}", e);
+            }
+        }
     }
 
     pub async fn update_guardian_set(&self, id: u32, guardian_set: GuardianSet) {
         let mut guardian_sets = self.guardian_set.write().await;
+
+        // Guardian sets are polled periodically, only the changes are worth recording.
+        if guardian_sets.get(&id) != Some(&guardian_set) {
+            self.record(|| Ok(Event::from_guardian_set(id, &guardian_set)));
+        }
+
         guardian_sets.insert(id, guardian_set);
     }
 
@@ -247,18 +361,7 @@ impl Store {
 
         let price_feeds = messages
             .iter()
-            .map(|message_state| match message_state.message {
-                Message::PriceFeedMessage(price_feed) => Ok(PriceFeedUpdate {
-                    price_feed,
-                    received_at: message_state.received_at,
-                    slot: message_state.slot,
-                    wormhole_merkle_update_data: construct_update_data(vec![message_state])?
-                        .into_iter()
-                        .next()
-                        .ok_or(anyhow!("Missing update data for message"))?,
-                }),
-                _ => Err(anyhow!("Invalid message state type")),
-            })
+            .map(price_feed_update_from_message_state)
             .collect::<Result<Vec<_>>>()?;
 
         let update_data = construct_update_data(messages.iter().collect())?;
@@ -269,6 +372,319 @@ impl Store {
         })
     }
 
+    /// Returns the price feeds of the given ids at the latest slot in which all of them have a
+    /// message, so that a single update data (and VAA) covers them all. The slot is at most
+    /// `max_slot_lag` slots older than the latest message of the feeds.
+    pub async fn get_price_feeds_with_aggregated_update_data(
+        &self,
+        price_ids: Vec<PriceIdentifier>,
+        max_slot_lag: Slot,
+    ) -> Result<AggregatedPriceFeedsWithUpdateData> {
+        let feed_ids: HashSet<_> = price_ids
+            .iter()
+            .map(|price_id| price_id.to_bytes())
+            .collect();
+
+        let latest_slot = self
+            .storage
+            .fetch_message_states(
+                feed_ids.iter().copied().collect(),
+                RequestTime::Latest,
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+            .await?
+            .iter()
+            .map(|message_state| message_state.slot)
+            .max()
+            .ok_or(anyhow!("No price feeds requested"))?;
+
+        let message_states = self
+            .storage
+            .fetch_message_states(
+                feed_ids.iter().copied().collect(),
+                RequestTime::SinceSlot(latest_slot.saturating_sub(max_slot_lag)),
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+            .await?;
+
+        let mut message_states_by_slot: BTreeMap<Slot, HashMap<_, &MessageState>> = BTreeMap::new();
+        for message_state in message_states.iter() {
+            message_states_by_slot
+                .entry(message_state.slot)
+                .or_default()
+                .insert(message_state.message.feed_id(), message_state);
+        }
+
+        let (slot, message_states) = message_states_by_slot
+            .into_iter()
+            .rev()
+            .find(|(_, message_states)| message_states.len() == feed_ids.len())
+            .ok_or(LookupError::NoCommonSlot {
+                latest_slot,
+                max_slot_lag,
+            })?;
+
+        // Keep the order of the requested ids, without the duplicates.
+        let mut seen_feed_ids = HashSet::new();
+        let message_states: Vec<&MessageState> = price_ids
+            .iter()
+            .map(|price_id| price_id.to_bytes())
+            .filter(|feed_id| seen_feed_ids.insert(*feed_id))
+            .filter_map(|feed_id| message_states.get(&feed_id).copied())
+            .collect();
+
+        let price_feeds = message_states
+            .iter()
+            .copied()
+            .map(price_feed_update_from_message_state)
+            .collect::<Result<Vec<_>>>()?;
+
+        // The messages of a slot are all proven against the same VAA, so there is a single
+        // update data.
+        let wormhole_merkle_update_data = construct_update_data(message_states)?
+            .into_iter()
+            .next()
+            .ok_or(anyhow!("Missing update data for slot {}", slot))?;
+
+        Ok(AggregatedPriceFeedsWithUpdateData {
+            slot,
+            price_feeds,
+            wormhole_merkle_update_data,
+        })
+    }
+
+    pub async fn get_twaps_with_update_data(
+        &self,
+        price_ids: Vec<PriceIdentifier>,
+        request_time: RequestTime,
+    ) -> Result<TwapsWithUpdateData> {
+        let messages = self
+            .storage
+            .fetch_message_states(
+                price_ids
+                    .iter()
+                    .map(|price_id| price_id.to_bytes())
+                    .collect(),
+                request_time,
+                MessageStateFilter::Only(MessageType::TwapMessage),
+            )
+            .await?;
+
+        let twaps = messages
+            .iter()
+            .map(twap_update_from_message_state)
+            .collect::<Result<Vec<_>>>()?;
+
+        let update_data = construct_update_data(messages.iter().collect())?;
+
+        Ok(TwapsWithUpdateData {
+            twaps,
+            wormhole_merkle_update_data: update_data,
+        })
+    }
+
+    /// Returns the first `limit` price feed updates of the given price ids published within the
+    /// inclusive `[start_time, end_time]` range and strictly after the `after` position, each one
+    /// with its own update data. Fails with `LookupError::PublishTimeTooOld` if the start of the
+    /// range is older than the retained updates of a feed.
+    ///
+    /// Each feed is only read up to the size of the page, and the update data is only built for
+    /// the updates of the page.
+    pub async fn get_price_feed_updates_in_range(
+        &self,
+        price_ids: Vec<PriceIdentifier>,
+        start_time: UnixTimestamp,
+        end_time: UnixTimestamp,
+        after: Option<RangePosition>,
+        limit: usize,
+    ) -> Result<PriceFeedUpdatesPage> {
+        let (start_time, start_slot) = match after {
+            Some(after) if after.publish_time >= start_time => (after.publish_time, after.slot),
+            _ => (start_time, 0),
+        };
+
+        // Every feed has at most one update at the position of `after`, which is skipped if the
+        // feed comes before it. One more update is needed to tell whether another page follows.
+        let mut message_states = self
+            .storage
+            .fetch_message_states(
+                price_ids
+                    .iter()
+                    .map(|price_id| price_id.to_bytes())
+                    .collect(),
+                RequestTime::Page {
+                    start_time,
+                    start_slot,
+                    end_time,
+                    limit: limit.saturating_add(2),
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+            .await?
+            .into_iter()
+            .map(|message_state| (range_position(&message_state), message_state))
+            .filter(|(position, _)| after.map_or(true, |after| *position > after))
+            .collect::<Vec<_>>();
+
+        message_states.sort_by_key(|(position, _)| *position);
+
+        let next = match message_states.len() > limit {
+            true => {
+                message_states.truncate(limit);
+                message_states.last().map(|(position, _)| *position)
+            }
+            false => None,
+        };
+
+        Ok(PriceFeedUpdatesPage {
+            updates: Box::new(
+                message_states
+                    .into_iter()
+                    .map(|(_, message_state)| price_feed_update_from_message_state(&message_state)),
+            ),
+            next,
+        })
+    }
+
+    /// Returns the price feed updates of the given price ids stored for the slots after the given
+    /// one, each one with its own update data. At most `limit` updates are returned per feed, the
+    /// latest ones.
+    pub async fn get_price_feed_updates_after_slot(
+        &self,
+        price_ids: Vec<PriceIdentifier>,
+        slot: Slot,
+        limit: usize,
+    ) -> Result<Vec<PriceFeedUpdate>> {
+        self.storage
+            .fetch_message_states(
+                price_ids
+                    .iter()
+                    .map(|price_id| price_id.to_bytes())
+                    .collect(),
+                RequestTime::AfterSlot { slot, limit },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+            .await?
+            .iter()
+            .map(price_feed_update_from_message_state)
+            .collect()
+    }
+
+    /// Returns the first `limit` price feed updates of each of the given price ids published at
+    /// or after the given time, each one with its own update data. Unlike the paginated range, it
+    /// fails if the time is older than the retained updates of a feed.
+    pub async fn get_price_feed_updates_since(
+        &self,
+        price_ids: Vec<PriceIdentifier>,
+        publish_time: UnixTimestamp,
+        limit: usize,
+    ) -> Result<Vec<PriceFeedUpdate>> {
+        self.storage
+            .fetch_message_states(
+                price_ids
+                    .iter()
+                    .map(|price_id| price_id.to_bytes())
+                    .collect(),
+                RequestTime::Range {
+                    start: publish_time,
+                    end: UnixTimestamp::MAX,
+                    limit,
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+            .await?
+            .iter()
+            .map(price_feed_update_from_message_state)
+            .collect()
+    }
+
+    /// Returns the candles of a feed at the given resolution, in seconds, for the intervals
+    /// overlapping the inclusive `[start_time, end_time]` range. Only persistent storages keep
+    /// the messages long enough to build them. The range is clamped to the retained messages, so
+    /// the first candle only covers the retained part of its interval if it starts before them.
+    ///
+    /// The messages are read and aggregated a page at a time, only the candles are kept.
+    pub async fn get_candles(
+        &self,
+        price_id: PriceIdentifier,
+        resolution: i64,
+        start_time: UnixTimestamp,
+        end_time: UnixTimestamp,
+    ) -> Result<Vec<Candle>> {
+        if !self.storage.is_persistent() {
+            return Err(anyhow!("Candles require a persistent storage"));
+        }
+
+        // Widen the range to whole intervals so that the first and last candles are complete.
+        let (start_time, end_time) = candles_range(start_time, end_time, resolution)
+            .ok_or_else(|| anyhow!("Invalid candle range or resolution: {}", resolution))?;
+
+        let fetch_page = |start_time, start_slot| {
+            self.storage.fetch_message_states(
+                vec![price_id.to_bytes()],
+                RequestTime::Page {
+                    start_time,
+                    start_slot,
+                    end_time,
+                    limit: CANDLE_MESSAGES_PAGE_SIZE,
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+        };
+
+        let mut builder = CandleBuilder::new(resolution)?;
+        let (mut start_time, mut start_slot) = (start_time, 0);
+        loop {
+            // Clamp the start to the oldest retained message, which may also have been evicted
+            // since the previous page.
+            let message_states = match fetch_page(start_time, start_slot).await {
+                Err(e) => match e.downcast_ref::<LookupError>() {
+                    Some(LookupError::PublishTimeTooOld {
+                        retained_window: Some(window),
+                        ..
+                    }) => fetch_page(window.start, 0).await?,
+                    _ => return Err(e),
+                },
+                message_states => message_states?,
+            };
+
+            for message_state in message_states.iter() {
+                match message_state.message {
+                    Message::PriceFeedMessage(price_feed) => builder.push(&price_feed)?,
+                    _ => return Err(anyhow!("Invalid message state type")),
+                }
+            }
+
+            match message_states.last() {
+                Some(last) if message_states.len() == CANDLE_MESSAGES_PAGE_SIZE => {
+                    start_time = last.time().publish_time;
+                    start_slot = last.slot + 1;
+                }
+                _ => return Ok(builder.finish()),
+            }
+        }
+    }
+
+    pub async fn update_price_feed_metadata(&self, products: Vec<ProductMetadata>) {
+        *self.price_feed_metadata.write().await = MetadataIndex::new(products);
+    }
+
+    /// Returns the id of the price feed with the given symbol, see `MetadataIndex::resolve`.
+    pub async fn resolve_symbol(&self, symbol: &str) -> Result<PriceIdentifier> {
+        Ok(self.price_feed_metadata.read().await.resolve(symbol)?)
+    }
+
+    pub async fn search_price_feed_metadata(
+        &self,
+        query: Option<&str>,
+        asset_class: Option<&str>,
+    ) -> Vec<ProductMetadata> {
+        self.price_feed_metadata
+            .read()
+            .await
+            .search(query, asset_class)
+    }
+
     pub async fn get_price_feed_ids(&self) -> HashSet<PriceIdentifier> {
         self.storage
             .message_state_keys()
@@ -278,6 +694,14 @@ impl Store {
             .collect()
     }
 
+    /// Flush the storage so the stored updates survive a restart. Called on shutdown.
+    pub async fn flush(&self) -> Result<()> {
+        if let Some(recorder) = &self.recorder {
+            recorder.flush().await?;
+        }
+        self.storage.flush().await
+    }
+
     pub async fn is_ready(&self) -> bool {
         const STALENESS_THRESHOLD: Duration = Duration::from_secs(30);
 
@@ -290,3 +714,254 @@ impl Store {
         }
     }
 }
+
+/// Spawns the storage janitor, which periodically evicts the stale states of the storage until
+/// `exit_rx` signals a shutdown.
+pub fn spawn_janitor(store: Arc<Store>, mut exit_rx: watch::Receiver<bool>) {
+    tokio::spawn(async move {
+        while !*exit_rx.borrow() {
+            tokio::select! {
+                _ = tokio::time::sleep(JANITOR_INTERVAL) => {}
+                _ = exit_rx.changed() => break,
+            }
+
+            if let Err(e) = store.storage.evict_stale_states().await {
+                log::error!("Failed to evict stale storage states: {:?}", e);
+            }
+        }
+
+        log::info!("Shutting down storage janitor...");
+    });
+}
+
+fn price_feed_update_from_message_state(message_state: &MessageState) -> Result<PriceFeedUpdate> {
+    match message_state.message {
+        Message::PriceFeedMessage(price_feed) => Ok(PriceFeedUpdate {
+            price_feed,
+            received_at: message_state.received_at,
+            slot: message_state.slot,
+            wormhole_merkle_update_data: construct_update_data(vec![message_state])?
+                .into_iter()
+                .next()
+                .ok_or(anyhow!("Missing update data for message"))?,
+        }),
+        _ => Err(anyhow!("Invalid message state type")),
+    }
+}
+
+fn range_position(message_state: &MessageState) -> RangePosition {
+    RangePosition {
+        publish_time: message_state.message.publish_time(),
+        slot:         message_state.slot,
+        feed_id:      message_state.message.feed_id(),
+    }
+}
+
+fn twap_update_from_message_state(message_state: &MessageState) -> Result<TwapUpdate> {
+    match message_state.message {
+        Message::TwapMessage(twap) => Ok(TwapUpdate {
+            twap,
+            received_at: message_state.received_at,
+            slot: message_state.slot,
+            wormhole_merkle_update_data: construct_update_data(vec![message_state])?
+                .into_iter()
+                .next()
+                .ok_or(anyhow!("Missing update data for message"))?,
+        }),
+        _ => Err(anyhow!("Invalid message state type")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::store::{
+            proof::wormhole_merkle::WormholeMerkleMessageProof,
+            storage::{
+                local_storage::LocalStorage,
+                sled_storage::{
+                    SledStorage,
+                    SledStorageConfig,
+                },
+            },
+            types::TimeWindow,
+        },
+        pythnet_sdk::{
+            accumulators::merkle::MerklePath,
+            hashers::keccak256_160::Keccak160,
+            messages::PriceFeedMessage,
+        },
+    };
+
+    fn create_dummy_price_feed_message_state(
+        feed_id: [u8; 32],
+        publish_time: UnixTimestamp,
+        slot: Slot,
+    ) -> MessageState {
+        MessageState {
+            slot,
+            raw_message: vec![],
+            message: Message::PriceFeedMessage(PriceFeedMessage {
+                feed_id,
+                publish_time,
+                price: 1,
+                conf: 2,
+                exponent: 3,
+                ema_price: 4,
+                ema_conf: 5,
+                prev_publish_time: 6,
+            }),
+            received_at: publish_time,
+            proof_set: ProofSet {
+                wormhole_merkle_proof: WormholeMerkleMessageProof {
+                    vaa:   vec![],
+                    proof: MerklePath::<Keccak160>::new(vec![]),
+                },
+            },
+        }
+    }
+
+    async fn fetch_range_page(
+        store: &Store,
+        after: Option<RangePosition>,
+    ) -> (Vec<RangePosition>, Option<RangePosition>) {
+        let page = store
+            .get_price_feed_updates_in_range(
+                vec![PriceIdentifier::new([1; 32]), PriceIdentifier::new([2; 32])],
+                10,
+                100,
+                after,
+                2,
+            )
+            .await
+            .unwrap();
+        let positions = page
+            .updates
+            .map(|update| {
+                let update = update.unwrap();
+                RangePosition {
+                    publish_time: update.price_feed.publish_time,
+                    slot:         update.slot,
+                    feed_id:      update.price_feed.feed_id,
+                }
+            })
+            .collect();
+        (positions, page.next)
+    }
+
+    #[tokio::test]
+    pub async fn test_price_feed_updates_in_range_are_paginated() {
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(10);
+        let store = Store::new(update_tx, LocalStorage::new_instance(100), None);
+        store
+            .storage
+            .store_message_states(vec![
+                create_dummy_price_feed_message_state([1; 32], 10, 1),
+                create_dummy_price_feed_message_state([1; 32], 11, 2),
+                create_dummy_price_feed_message_state([1; 32], 12, 3),
+                create_dummy_price_feed_message_state([2; 32], 10, 1),
+                create_dummy_price_feed_message_state([2; 32], 12, 3),
+            ])
+            .await
+            .unwrap();
+
+        let position = |publish_time, slot, feed_id| RangePosition {
+            publish_time,
+            slot,
+            feed_id,
+        };
+
+        let (positions, next) = fetch_range_page(&store, None).await;
+        assert_eq!(
+            positions,
+            vec![position(10, 1, [1; 32]), position(10, 1, [2; 32])]
+        );
+        assert_eq!(next, Some(position(10, 1, [2; 32])));
+
+        let (positions, next) = fetch_range_page(&store, next).await;
+        assert_eq!(
+            positions,
+            vec![position(11, 2, [1; 32]), position(12, 3, [1; 32])]
+        );
+        assert_eq!(next, Some(position(12, 3, [1; 32])));
+
+        let (positions, next) = fetch_range_page(&store, next).await;
+        assert_eq!(positions, vec![position(12, 3, [2; 32])]);
+        assert_eq!(next, None);
+    }
+
+    #[tokio::test]
+    pub async fn test_candles_are_built_across_storage_pages() {
+        let path =
+            std::env::temp_dir().join(format!("hermes-candles-test-{}", rand::random::<u64>()));
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(10);
+        let storage = SledStorage::new_instance(&path, SledStorageConfig::default()).unwrap();
+        let store = Store::new(update_tx, storage, None);
+
+        // More messages than fit in a page, one per second.
+        let num_messages = 2 * CANDLE_MESSAGES_PAGE_SIZE + 500;
+        store
+            .storage
+            .store_message_states(
+                (0..num_messages)
+                    .map(|i| create_dummy_price_feed_message_state([1; 32], i as i64, i as u64))
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        // The range starts before the retained messages, so it is clamped to them.
+        let candles = store
+            .get_candles(
+                PriceIdentifier::new([1; 32]),
+                1000,
+                -5000,
+                num_messages as i64,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            candles
+                .iter()
+                .map(|candle| (candle.start_time, candle.num_updates))
+                .collect::<Vec<_>>(),
+            vec![(0, 1000), (1000, 1000), (2000, 500)]
+        );
+
+        drop(store);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn test_price_feed_updates_in_range_fail_before_the_retained_updates() {
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(10);
+        let store = Store::new(update_tx, LocalStorage::new_instance(100), None);
+        store
+            .storage
+            .store_message_states(vec![
+                create_dummy_price_feed_message_state([1; 32], 10, 1),
+                create_dummy_price_feed_message_state([1; 32], 12, 3),
+            ])
+            .await
+            .unwrap();
+
+        let error = match store
+            .get_price_feed_updates_in_range(vec![PriceIdentifier::new([1; 32])], 9, 100, None, 2)
+            .await
+        {
+            Ok(_) => panic!("The range starts before the retained updates"),
+            Err(error) => error,
+        };
+        assert_eq!(
+            error.downcast_ref::<LookupError>(),
+            Some(&LookupError::PublishTimeTooOld {
+                feed_ids:        vec![PriceIdentifier::new([1; 32])],
+                retained_window: Some(TimeWindow {
+                    start: 10,
+                    end:   12,
+                }),
+            })
+        );
+    }
+}
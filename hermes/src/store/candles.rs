@@ -0,0 +1,229 @@
+//! OHLC candles built from the `PriceFeedMessage`s kept by a persistent storage.
+//!
+//! Messages are bucketed by publish time into fixed-size intervals aligned on the Unix epoch.
+//! Intervals without any message have no candle.
+
+use {
+    super::types::UnixTimestamp,
+    anyhow::{
+        anyhow,
+        Result,
+    },
+    pythnet_sdk::messages::{
+        FeedId,
+        PriceFeedMessage,
+    },
+};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Candle {
+    pub feed_id:     FeedId,
+    /// Start of the interval, inclusive. The interval ends at `start_time + resolution`.
+    pub start_time:  UnixTimestamp,
+    pub open:        i64,
+    pub high:        i64,
+    pub low:         i64,
+    pub close:       i64,
+    /// Average of the confidence intervals of the messages in the interval.
+    pub avg_conf:    u64,
+    pub exponent:    i32,
+    pub num_updates: u64,
+}
+
+/// Widens the inclusive `[start_time, end_time]` range to the whole intervals of the given
+/// resolution, in seconds, overlapping it. Returns `None` if the resolution is invalid or if the
+/// widened range does not fit in a timestamp.
+pub fn candles_range(
+    start_time: UnixTimestamp,
+    end_time: UnixTimestamp,
+    resolution: i64,
+) -> Option<(UnixTimestamp, UnixTimestamp)> {
+    if resolution <= 0 {
+        return None;
+    }
+
+    let start_time = start_time.div_euclid(resolution).checked_mul(resolution)?;
+    let end_time = end_time
+        .div_euclid(resolution)
+        .checked_mul(resolution)?
+        .checked_add(resolution - 1)?;
+    Some((start_time, end_time))
+}
+
+/// Builds the candles of a feed at the given resolution, in seconds, one message at a time so
+/// that the messages can be read from the storage page by page. The messages must belong to the
+/// same feed and be pushed in publish time order.
+pub struct CandleBuilder {
+    resolution: i64,
+    candles:    Vec<Candle>,
+    /// Sum of the confidence intervals of the messages of the last candle.
+    conf_sum:   u128,
+}
+
+impl CandleBuilder {
+    pub fn new(resolution: i64) -> Result<Self> {
+        if resolution <= 0 {
+            return Err(anyhow!("Invalid candle resolution: {}", resolution));
+        }
+
+        Ok(Self {
+            resolution,
+            candles: vec![],
+            conf_sum: 0,
+        })
+    }
+
+    pub fn push(&mut self, message: &PriceFeedMessage) -> Result<()> {
+        if let Some(first) = self.candles.first() {
+            if message.feed_id != first.feed_id || message.exponent != first.exponent {
+                return Err(anyhow!("Candle messages belong to different feeds"));
+            }
+        }
+
+        let start_time = message.publish_time.div_euclid(self.resolution) * self.resolution;
+
+        match self.candles.last_mut() {
+            Some(candle) if candle.start_time == start_time => {
+                candle.high = candle.high.max(message.price);
+                candle.low = candle.low.min(message.price);
+                candle.close = message.price;
+                candle.num_updates += 1;
+                self.conf_sum += message.conf as u128;
+                candle.avg_conf = (self.conf_sum / candle.num_updates as u128) as u64;
+            }
+            Some(candle) if candle.start_time > start_time => {
+                return Err(anyhow!("Candle messages are not sorted by publish time"));
+            }
+            _ => {
+                self.conf_sum = message.conf as u128;
+                self.candles.push(Candle {
+                    feed_id: message.feed_id,
+                    start_time,
+                    open: message.price,
+                    high: message.price,
+                    low: message.price,
+                    close: message.price,
+                    avg_conf: message.conf,
+                    exponent: message.exponent,
+                    num_updates: 1,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Vec<Candle> {
+        self.candles
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_candles(messages: &[PriceFeedMessage], resolution: i64) -> Result<Vec<Candle>> {
+        let mut builder = CandleBuilder::new(resolution)?;
+        for message in messages {
+            builder.push(message)?;
+        }
+        Ok(builder.finish())
+    }
+
+    fn create_dummy_price_feed_message(
+        price: i64,
+        conf: u64,
+        publish_time: i64,
+    ) -> PriceFeedMessage {
+        PriceFeedMessage {
+            feed_id: [1; 32],
+            price,
+            conf,
+            exponent: -8,
+            publish_time,
+            prev_publish_time: publish_time - 1,
+            ema_price: price,
+            ema_conf: conf,
+        }
+    }
+
+    #[test]
+    pub fn test_build_candles_works() {
+        let messages = vec![
+            create_dummy_price_feed_message(100, 10, 60),
+            create_dummy_price_feed_message(120, 20, 75),
+            create_dummy_price_feed_message(90, 30, 80),
+            create_dummy_price_feed_message(110, 40, 119),
+            // No message in [120, 180).
+            create_dummy_price_feed_message(-5, 5, 180),
+        ];
+
+        assert_eq!(
+            build_candles(&messages, 60).unwrap(),
+            vec![
+                Candle {
+                    feed_id:     [1; 32],
+                    start_time:  60,
+                    open:        100,
+                    high:        120,
+                    low:         90,
+                    close:       110,
+                    avg_conf:    25,
+                    exponent:    -8,
+                    num_updates: 4,
+                },
+                Candle {
+                    feed_id:     [1; 32],
+                    start_time:  180,
+                    open:        -5,
+                    high:        -5,
+                    low:         -5,
+                    close:       -5,
+                    avg_conf:    5,
+                    exponent:    -8,
+                    num_updates: 1,
+                },
+            ]
+        );
+
+        // Intervals are aligned on the epoch, including before it.
+        let candles = build_candles(&[create_dummy_price_feed_message(1, 1, -1)], 60).unwrap();
+        assert_eq!(candles[0].start_time, -60);
+        assert_eq!(build_candles(&[], 60).unwrap(), vec![]);
+    }
+
+    #[test]
+    pub fn test_candles_range() {
+        assert_eq!(candles_range(61, 119, 60), Some((60, 119)));
+        assert_eq!(candles_range(60, 120, 60), Some((60, 179)));
+        assert_eq!(candles_range(-1, -1, 60), Some((-60, -1)));
+
+        // The widened range must fit in a timestamp.
+        assert_eq!(candles_range(0, i64::MAX, 60), None);
+        assert_eq!(candles_range(i64::MIN, 0, 60), None);
+        assert_eq!(candles_range(0, 0, 0), None);
+    }
+
+    #[test]
+    pub fn test_build_candles_fails_for_invalid_input() {
+        let messages = vec![
+            create_dummy_price_feed_message(100, 10, 80),
+            create_dummy_price_feed_message(120, 20, 30),
+        ];
+        assert!(build_candles(&messages, 60).is_err());
+        assert!(build_candles(&messages, 0).is_err());
+
+        let other_feed_message = PriceFeedMessage {
+            feed_id: [2; 32],
+            ..create_dummy_price_feed_message(100, 10, 150)
+        };
+        assert!(build_candles(
+            &[
+                create_dummy_price_feed_message(100, 10, 80),
+                other_feed_message
+            ],
+            60
+        )
+        .is_err());
+    }
+}
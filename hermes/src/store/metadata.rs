@@ -0,0 +1,369 @@
+//! Metadata of the price feeds (symbol, asset class, ...), used to look the feeds up by symbol.
+//!
+//! The metadata is taken from the product accounts of the Pyth oracle program on Pythnet, or from
+//! a JSON file with the same content as the `/api/price_feeds/metadata` responses. Each product
+//! account holds a header followed by the attributes of the product as length-prefixed key/value
+//! strings, and the address of its price account, which is the id of the price feed.
+
+use {
+    super::types::LookupError,
+    anyhow::{
+        anyhow,
+        Result,
+    },
+    pyth_sdk::PriceIdentifier,
+    serde::{
+        Deserialize,
+        Deserializer,
+    },
+    std::{
+        collections::HashMap,
+        path::Path,
+    },
+};
+
+const PRODUCT_ACCOUNT_MAGIC: u32 = 0xa1b2c3d4;
+const PRODUCT_ACCOUNT_TYPE: u32 = 2;
+/// Size of the product account header: magic, version, type and size, then the price account.
+const PRODUCT_ACCOUNT_HEADER_SIZE: usize = 48;
+
+/// Filters selecting the product accounts among the accounts of the oracle program, as
+/// `(offset, bytes)` pairs.
+pub const PRODUCT_ACCOUNT_FILTERS: [(usize, [u8; 4]); 2] = [
+    (0, PRODUCT_ACCOUNT_MAGIC.to_le_bytes()),
+    (8, PRODUCT_ACCOUNT_TYPE.to_le_bytes()),
+];
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize)]
+pub struct ProductMetadata {
+    #[serde(deserialize_with = "deserialize_feed_id")]
+    pub id:          PriceIdentifier,
+    pub symbol:      String,
+    pub asset_class: String,
+    #[serde(default)]
+    pub base:        Option<String>,
+    #[serde(default)]
+    pub quote:       Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl ProductMetadata {
+    /// Parses the data of a product account. Returns `None` for the accounts which are not
+    /// product accounts, have no price account yet, or lack a symbol or an asset class.
+    pub fn from_product_account(data: &[u8]) -> Option<Self> {
+        let read_u32 = |offset: usize| {
+            data.get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        if read_u32(0)? != PRODUCT_ACCOUNT_MAGIC || read_u32(8)? != PRODUCT_ACCOUNT_TYPE {
+            return None;
+        }
+        let size = (read_u32(12)? as usize).min(data.len());
+
+        let id: [u8; 32] = data.get(16..PRODUCT_ACCOUNT_HEADER_SIZE)?.try_into().ok()?;
+        if id == [0; 32] {
+            return None;
+        }
+
+        let mut attributes = HashMap::new();
+        let mut offset = PRODUCT_ACCOUNT_HEADER_SIZE;
+        while offset < size {
+            let key = read_string(&data[..size], &mut offset)?;
+            let value = read_string(&data[..size], &mut offset)?;
+            attributes.insert(key, value);
+        }
+        let mut attribute = |key: &str| attributes.remove(key).filter(|value| !value.is_empty());
+
+        Some(Self {
+            id:          PriceIdentifier::new(id),
+            symbol:      attribute("symbol")?,
+            asset_class: attribute("asset_type")?,
+            base:        attribute("base"),
+            quote:       attribute("quote_currency"),
+            description: attribute("description"),
+        })
+    }
+
+    /// The symbol without its asset class, e.g. `BTC/USD` for `Crypto.BTC/USD`.
+    pub fn short_symbol(&self) -> &str {
+        self.symbol
+            .rsplit_once('.')
+            .map_or(self.symbol.as_str(), |(_, symbol)| symbol)
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        [
+            Some(&self.symbol),
+            self.base.as_ref(),
+            self.quote.as_ref(),
+            self.description.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|field| field.to_lowercase().contains(query))
+    }
+}
+
+/// Reads a string prefixed by its length on a single byte.
+fn read_string(data: &[u8], offset: &mut usize) -> Option<String> {
+    let len = *data.get(*offset)? as usize;
+    let bytes = data.get(*offset + 1..*offset + 1 + len)?;
+    *offset += 1 + len;
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn deserialize_feed_id<'de, D>(deserializer: D) -> Result<PriceIdentifier, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let id = String::deserialize(deserializer)?;
+    let bytes = hex::decode(id.trim_start_matches("0x")).map_err(serde::de::Error::custom)?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| serde::de::Error::custom(format!("Invalid price feed id {id}")))?;
+    Ok(PriceIdentifier::new(bytes))
+}
+
+/// Loads the metadata of the price feeds from a JSON array of products.
+pub fn load_file(path: &Path) -> Result<Vec<ProductMetadata>> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        anyhow!(
+            "Failed to read price feed metadata file {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    serde_json::from_str(&content)
+        .map_err(|e| anyhow!("Invalid price feed metadata file {}: {}", path.display(), e))
+}
+
+/// The metadata of the price feeds, indexed by their full and short symbols. Symbols are matched
+/// case-insensitively.
+#[derive(Default, Debug)]
+pub struct MetadataIndex {
+    /// Sorted by symbol.
+    products:        Vec<ProductMetadata>,
+    by_symbol:       HashMap<String, usize>,
+    by_short_symbol: HashMap<String, Vec<usize>>,
+}
+
+impl MetadataIndex {
+    pub fn new(mut products: Vec<ProductMetadata>) -> Self {
+        products.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+        let mut by_symbol = HashMap::new();
+        let mut by_short_symbol: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, product) in products.iter().enumerate() {
+            by_symbol
+                .entry(product.symbol.to_lowercase())
+                .or_insert(index);
+            by_short_symbol
+                .entry(product.short_symbol().to_lowercase())
+                .or_default()
+                .push(index);
+        }
+
+        Self {
+            products,
+            by_symbol,
+            by_short_symbol,
+        }
+    }
+
+    /// Returns the price feed with the given full symbol or, failing that, the only one with the
+    /// given short symbol.
+    pub fn resolve(&self, symbol: &str) -> Result<PriceIdentifier, LookupError> {
+        let key = symbol.to_lowercase();
+        if let Some(index) = self.by_symbol.get(&key) {
+            return Ok(self.products[*index].id);
+        }
+
+        match self.by_short_symbol.get(&key).map(Vec::as_slice) {
+            Some([index]) => Ok(self.products[*index].id),
+            Some(indexes) => Err(LookupError::AmbiguousSymbol {
+                symbol:  symbol.to_string(),
+                matches: indexes
+                    .iter()
+                    .map(|index| self.products[*index].symbol.clone())
+                    .collect(),
+            }),
+            None => Err(LookupError::UnknownSymbol(symbol.to_string())),
+        }
+    }
+
+    /// Returns the products whose symbol, base, quote or description contains `query`, in the
+    /// given asset class. Both filters are case-insensitive.
+    pub fn search(&self, query: Option<&str>, asset_class: Option<&str>) -> Vec<ProductMetadata> {
+        let query = query.map(str::to_lowercase);
+        self.products
+            .iter()
+            .filter(|product| {
+                asset_class.map_or(true, |asset_class| {
+                    product.asset_class.eq_ignore_ascii_case(asset_class)
+                })
+            })
+            .filter(|product| query.as_ref().map_or(true, |query| product.matches(query)))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn product(id: u8, symbol: &str, asset_class: &str) -> ProductMetadata {
+        ProductMetadata {
+            id:          PriceIdentifier::new([id; 32]),
+            symbol:      symbol.to_string(),
+            asset_class: asset_class.to_string(),
+            base:        None,
+            quote:       None,
+            description: None,
+        }
+    }
+
+    fn product_account(price_account: [u8; 32], attributes: &[(&str, &str)]) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&PRODUCT_ACCOUNT_MAGIC.to_le_bytes());
+        data.extend_from_slice(&2_u32.to_le_bytes());
+        data.extend_from_slice(&PRODUCT_ACCOUNT_TYPE.to_le_bytes());
+        data.extend_from_slice(&0_u32.to_le_bytes());
+        data.extend_from_slice(&price_account);
+        for (key, value) in attributes {
+            for string in [key, value] {
+                data.push(string.len() as u8);
+                data.extend_from_slice(string.as_bytes());
+            }
+        }
+        let size = data.len() as u32;
+        data[12..16].copy_from_slice(&size.to_le_bytes());
+        // Accounts are allocated with a fixed size, the unused space is zeroed.
+        data.resize(512, 0);
+        data
+    }
+
+    #[test]
+    pub fn test_parse_product_account() {
+        let data = product_account(
+            [1; 32],
+            &[
+                ("symbol", "Crypto.BTC/USD"),
+                ("asset_type", "Crypto"),
+                ("base", "BTC"),
+                ("quote_currency", "USD"),
+                ("description", ""),
+                ("generic_symbol", "BTCUSD"),
+            ],
+        );
+
+        assert_eq!(
+            ProductMetadata::from_product_account(&data),
+            Some(ProductMetadata {
+                id:          PriceIdentifier::new([1; 32]),
+                symbol:      "Crypto.BTC/USD".to_string(),
+                asset_class: "Crypto".to_string(),
+                base:        Some("BTC".to_string()),
+                quote:       Some("USD".to_string()),
+                description: None,
+            })
+        );
+    }
+
+    #[test]
+    pub fn test_parse_product_account_rejects_other_accounts() {
+        let attributes = [("symbol", "Crypto.BTC/USD"), ("asset_type", "Crypto")];
+
+        // No price account yet.
+        let data = product_account([0; 32], &attributes);
+        assert_eq!(ProductMetadata::from_product_account(&data), None);
+
+        // A price account.
+        let mut data = product_account([1; 32], &attributes);
+        data[8..12].copy_from_slice(&3_u32.to_le_bytes());
+        assert_eq!(ProductMetadata::from_product_account(&data), None);
+
+        // Truncated attributes.
+        let mut data = product_account([1; 32], &attributes);
+        data[12..16].copy_from_slice(&600_u32.to_le_bytes());
+        data.truncate(60);
+        assert_eq!(ProductMetadata::from_product_account(&data), None);
+    }
+
+    #[test]
+    pub fn test_load_metadata_from_json() {
+        let products: Vec<ProductMetadata> = serde_json::from_str(&format!(
+            r#"[{{"id": "0x{}", "symbol": "FX.EUR/USD", "asset_class": "FX", "quote": "USD"}}]"#,
+            "02".repeat(32)
+        ))
+        .unwrap();
+
+        assert_eq!(products[0].id, PriceIdentifier::new([2; 32]));
+        assert_eq!(products[0].short_symbol(), "EUR/USD");
+        assert_eq!(products[0].quote, Some("USD".to_string()));
+        assert_eq!(products[0].base, None);
+    }
+
+    #[test]
+    pub fn test_resolve_symbols() {
+        let index = MetadataIndex::new(vec![
+            product(1, "Crypto.BTC/USD", "Crypto"),
+            product(2, "Crypto.ETH/USD", "Crypto"),
+            product(3, "Equity.US.ETH/USD", "Equity"),
+        ]);
+
+        assert_eq!(
+            index.resolve("Crypto.BTC/USD"),
+            Ok(PriceIdentifier::new([1; 32]))
+        );
+        assert_eq!(index.resolve("btc/usd"), Ok(PriceIdentifier::new([1; 32])));
+        assert_eq!(
+            index.resolve("Equity.US.ETH/USD"),
+            Ok(PriceIdentifier::new([3; 32]))
+        );
+        assert_eq!(
+            index.resolve("ETH/USD"),
+            Err(LookupError::AmbiguousSymbol {
+                symbol:  "ETH/USD".to_string(),
+                matches: vec![
+                    "Crypto.ETH/USD".to_string(),
+                    "Equity.US.ETH/USD".to_string()
+                ],
+            })
+        );
+        assert_eq!(
+            index.resolve("SOL/USD"),
+            Err(LookupError::UnknownSymbol("SOL/USD".to_string()))
+        );
+    }
+
+    #[test]
+    pub fn test_search_metadata() {
+        let index = MetadataIndex::new(vec![
+            ProductMetadata {
+                description: Some("Bitcoin / US Dollar".to_string()),
+                ..product(1, "Crypto.BTC/USD", "Crypto")
+            },
+            product(2, "Crypto.ETH/USD", "Crypto"),
+            product(3, "FX.EUR/USD", "FX"),
+        ]);
+
+        let symbols = |products: Vec<ProductMetadata>| -> Vec<String> {
+            products.into_iter().map(|product| product.symbol).collect()
+        };
+
+        assert_eq!(index.search(None, None).len(), 3);
+        assert_eq!(
+            symbols(index.search(Some("bitcoin"), None)),
+            vec!["Crypto.BTC/USD"]
+        );
+        assert_eq!(
+            symbols(index.search(Some("usd"), Some("crypto"))),
+            vec!["Crypto.BTC/USD", "Crypto.ETH/USD"]
+        );
+        assert_eq!(symbols(index.search(None, Some("FX"))), vec!["FX.EUR/USD"]);
+        assert!(index.search(Some("SOL"), None).is_empty());
+    }
+}
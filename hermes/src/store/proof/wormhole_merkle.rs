@@ -29,15 +29,19 @@ use {
             },
         },
     },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
 };
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct WormholeMerkleState {
     pub root: WormholeMerkleRoot,
     pub vaa:  Vec<u8>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct WormholeMerkleMessageProof {
     pub vaa:   Vec<u8>,
     pub proof: MerklePath<Keccak160>,
@@ -20,17 +20,29 @@ use {
         Message,
         MessageType,
     },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
 };
 
 pub mod local_storage;
+pub mod sled_storage;
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct AccumulatorState {
     pub slot:                  Slot,
     pub accumulator_messages:  Option<AccumulatorMessages>,
     pub wormhole_merkle_state: Option<WormholeMerkleState>,
 }
 
+impl AccumulatorState {
+    /// Whether both the accumulator messages and the wormhole merkle state were received.
+    pub fn is_complete(&self) -> bool {
+        self.accumulator_messages.is_some() && self.wormhole_merkle_state.is_some()
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct CompletedAccumulatorState {
     pub slot:                  Slot,
@@ -68,7 +80,7 @@ pub struct MessageStateTime {
     pub slot:         Slot,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct MessageState {
     pub slot:        Slot,
     pub message:     Message,
@@ -148,6 +160,25 @@ pub trait Storage: Send + Sync {
         slot: Slot,
         callback: Box<dyn (FnOnce(AccumulatorState) -> AccumulatorState) + Send>,
     ) -> Result<()>;
+
+    /// Make sure everything stored so far is durable. This is called on shutdown and is a no-op
+    /// for storages that are not persistent.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Drop the states that will never be used, such as the accumulator states that never got
+    /// their VAA. Called periodically by the store janitor, a no-op for storages that clean up as
+    /// they go.
+    async fn evict_stale_states(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether the stored messages survive a restart and are kept long enough to serve
+    /// historical aggregations such as candles.
+    fn is_persistent(&self) -> bool {
+        false
+    }
 }
 
 pub type StorageInstance = Box<dyn Storage>;
@@ -9,50 +9,202 @@ use {
         Storage,
         StorageInstance,
     },
-    crate::store::types::Slot,
-    anyhow::{
-        anyhow,
-        Result,
+    crate::{
+        metrics::{
+            AccumulatorStateOutcome,
+            EvictionReason,
+            METRICS,
+        },
+        store::types::{
+            LookupError,
+            Slot,
+            TimeWindow,
+            UnixTimestamp,
+        },
     },
+    anyhow::Result,
     async_trait::async_trait,
     dashmap::DashMap,
+    pyth_sdk::PriceIdentifier,
     pythnet_sdk::messages::{
         FeedId,
         MessageType,
     },
     std::{
-        collections::VecDeque,
-        sync::Arc,
+        cmp::Reverse,
+        collections::{
+            BinaryHeap,
+            VecDeque,
+        },
+        sync::{
+            atomic::{
+                AtomicUsize,
+                Ordering,
+            },
+            Arc,
+        },
+        time::Duration,
     },
     strum::IntoEnumIterator,
-    tokio::sync::RwLock,
+    tokio::{
+        sync::RwLock,
+        time::Instant,
+    },
 };
 
+/// Limits of the in-memory caches. The message states of a feed are evicted oldest first as soon
+/// as one of the message limits is reached, the accumulator states have their own limits.
+#[derive(Clone, Debug)]
+pub struct LocalStorageConfig {
+    /// Maximum number of message states kept per feed and message type.
+    pub max_messages_per_key:       usize,
+    /// Message states published longer than this before the latest one of their feed are
+    /// evicted.
+    pub max_message_age:            Option<Duration>,
+    /// Approximate budget, in bytes, of all the message states. Once exceeded, the oldest message
+    /// states across all the feeds are evicted, keeping at least the latest one of each.
+    pub max_message_bytes:          Option<usize>,
+    /// Maximum number of accumulator states kept, by slot.
+    pub max_accumulator_states:     usize,
+    /// Incomplete accumulator states older than this are dropped by the janitor, their other
+    /// part will most likely never arrive.
+    pub incomplete_accumulator_ttl: Duration,
+}
+
+impl Default for LocalStorageConfig {
+    fn default() -> Self {
+        Self {
+            max_messages_per_key:       1000,
+            max_message_age:            None,
+            max_message_bytes:          None,
+            max_accumulator_states:     1000,
+            incomplete_accumulator_ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AccumulatorEntry {
+    state:      AccumulatorState,
+    /// When the first part of the state was received.
+    created_at: Instant,
+}
+
 #[derive(Clone)]
 pub struct LocalStorage {
     message_cache:     Arc<DashMap<MessageStateKey, VecDeque<MessageState>>>,
-    accumulator_cache: Arc<RwLock<VecDeque<AccumulatorState>>>,
-    cache_size:        u64,
+    accumulator_cache: Arc<RwLock<VecDeque<AccumulatorEntry>>>,
+    /// Approximate size of all the message states in `message_cache`.
+    message_bytes:     Arc<AtomicUsize>,
+    config:            LocalStorageConfig,
 }
 
 impl LocalStorage {
+    /// Creates a storage keeping `cache_size` message states per feed and accumulator states.
     pub fn new_instance(cache_size: u64) -> StorageInstance {
+        Self::with_config(LocalStorageConfig {
+            max_messages_per_key: cache_size as usize,
+            max_accumulator_states: cache_size as usize,
+            ..Default::default()
+        })
+    }
+
+    pub fn with_config(config: LocalStorageConfig) -> StorageInstance {
         Box::new(Self {
             message_cache: Arc::new(DashMap::new()),
             accumulator_cache: Arc::new(RwLock::new(VecDeque::new())),
-            cache_size,
+            message_bytes: Arc::new(AtomicUsize::new(0)),
+            config,
         })
     }
 
-    fn retrieve_message_state(
+    /// Approximate memory used by a message state. Only the variable-size parts that dominate
+    /// (the raw message and the VAA of its proof) are counted on top of the struct itself.
+    fn message_state_size(message_state: &MessageState) -> usize {
+        std::mem::size_of::<MessageState>()
+            + message_state.raw_message.len()
+            + message_state.proof_set.wormhole_merkle_proof.vaa.len()
+    }
+
+    fn evict_oldest_message_state(
+        &self,
+        key_cache: &mut VecDeque<MessageState>,
+        reason: EvictionReason,
+    ) {
+        if let Some(evicted) = key_cache.pop_front() {
+            self.message_bytes
+                .fetch_sub(Self::message_state_size(&evicted), Ordering::Relaxed);
+            METRICS.record_eviction(reason);
+        }
+    }
+
+    /// Evicts the oldest message states of a key until it is within the count and age limits of
+    /// the config.
+    fn evict_message_states(&self, key_cache: &mut VecDeque<MessageState>) {
+        while key_cache.len() > self.config.max_messages_per_key {
+            self.evict_oldest_message_state(key_cache, EvictionReason::Count);
+        }
+
+        let newest = key_cache.back().map(|state| state.time().publish_time);
+        if let (Some(max_message_age), Some(newest)) = (self.config.max_message_age, newest) {
+            let cutoff = newest.saturating_sub(max_message_age.as_secs() as UnixTimestamp);
+            while key_cache
+                .front()
+                .map_or(false, |state| state.time().publish_time < cutoff)
+            {
+                self.evict_oldest_message_state(key_cache, EvictionReason::Age);
+            }
+        }
+    }
+
+    /// Evicts the oldest message states across all the keys, by publish time, until they fit in
+    /// the memory budget of the config. The latest message state of each key is kept.
+    fn evict_message_states_over_budget(&self) {
+        if let Some(max_message_bytes) = self.config.max_message_bytes {
+            // The oldest message state of each key that has more than one, the keys being
+            // referred to by their index in `keys`.
+            let mut keys = vec![];
+            let mut oldest: BinaryHeap<Reverse<(MessageStateTime, usize)>> = BinaryHeap::new();
+            if self.message_bytes.load(Ordering::Relaxed) > max_message_bytes {
+                for key_cache in self.message_cache.iter() {
+                    if key_cache.len() > 1 {
+                        oldest.push(Reverse((key_cache[0].time(), keys.len())));
+                        keys.push(key_cache.key().clone());
+                    }
+                }
+            }
+
+            while self.message_bytes.load(Ordering::Relaxed) > max_message_bytes {
+                let Reverse((_, index)) = match oldest.pop() {
+                    Some(oldest) => oldest,
+                    None => break,
+                };
+
+                if let Some(mut key_cache) = self.message_cache.get_mut(&keys[index]) {
+                    if key_cache.len() > 1 {
+                        self.evict_oldest_message_state(&mut key_cache, EvictionReason::Memory);
+                    }
+                    if key_cache.len() > 1 {
+                        oldest.push(Reverse((key_cache[0].time(), index)));
+                    }
+                }
+            }
+        }
+
+        METRICS
+            .storage_bytes
+            .set(self.message_bytes.load(Ordering::Relaxed) as i64);
+    }
+
+    fn retrieve_message_states(
         &self,
         key: MessageStateKey,
         request_time: RequestTime,
-    ) -> Option<MessageState> {
+    ) -> Option<Vec<MessageState>> {
         match self.message_cache.get(&key) {
             Some(key_cache) => {
                 match request_time {
-                    RequestTime::Latest => key_cache.back().cloned(),
+                    RequestTime::Latest => key_cache.back().cloned().map(|state| vec![state]),
                     RequestTime::FirstAfter(time) => {
                         // If the requested time is before the first element in the vector, we are
                         // not sure that the first element is the closest one.
@@ -80,7 +232,90 @@ impl LocalStorage {
 
                         // We are using `get` to handle out of bound idx. This happens if the
                         // requested time is after the last element in the vector.
-                        key_cache.get(idx).cloned()
+                        key_cache.get(idx).cloned().map(|state| vec![state])
+                    }
+                    RequestTime::Range { start, end, limit } => {
+                        // Same as above, if the range starts before the first element we cannot
+                        // be sure that the cache holds every message of the range.
+                        if start < key_cache.front()?.time().publish_time {
+                            return None;
+                        }
+
+                        let start_idx =
+                            key_cache.partition_point(|record| record.time().publish_time < start);
+
+                        Some(
+                            key_cache
+                                .range(start_idx..)
+                                .take_while(|record| record.time().publish_time <= end)
+                                .take(limit)
+                                .cloned()
+                                .collect(),
+                        )
+                    }
+                    RequestTime::AfterSlot { slot, limit } => {
+                        let mut message_states: Vec<_> = key_cache
+                            .iter()
+                            .rev()
+                            .take_while(|record| record.slot > slot)
+                            .take(limit)
+                            .cloned()
+                            .collect();
+
+                        // The cache must hold a message at or before the slot, otherwise some
+                        // messages right after it might have been evicted already. It does not
+                        // matter if only the latest messages are requested.
+                        if message_states.len() < limit && key_cache.front()?.slot > slot {
+                            return None;
+                        }
+
+                        message_states.reverse();
+                        Some(message_states)
+                    }
+                    RequestTime::SinceSlot(slot) => {
+                        let mut message_states: Vec<_> = key_cache
+                            .iter()
+                            .rev()
+                            .take_while(|record| record.slot >= slot)
+                            .cloned()
+                            .collect();
+                        message_states.reverse();
+                        Some(message_states)
+                    }
+                    RequestTime::AtSlot(slot) => Some(
+                        key_cache
+                            .iter()
+                            .rev()
+                            .take_while(|record| record.slot >= slot)
+                            .filter(|record| record.slot == slot)
+                            .cloned()
+                            .collect(),
+                    ),
+                    RequestTime::Page {
+                        start_time,
+                        start_slot,
+                        end_time,
+                        limit,
+                    } => {
+                        // Same as a range, the page must start within the cache.
+                        if start_time < key_cache.front()?.time().publish_time {
+                            return None;
+                        }
+
+                        let start = MessageStateTime {
+                            publish_time: start_time,
+                            slot:         start_slot,
+                        };
+                        let start_idx = key_cache.partition_point(|record| record.time() < start);
+
+                        Some(
+                            key_cache
+                                .range(start_idx..)
+                                .take_while(|record| record.time().publish_time <= end_time)
+                                .take(limit)
+                                .cloned()
+                                .collect(),
+                        )
                     }
                 }
             }
@@ -88,22 +323,38 @@ impl LocalStorage {
         }
     }
 
+    /// Publish times of the oldest and newest message states of the key, if any.
+    fn retained_window(&self, key: &MessageStateKey) -> Option<TimeWindow> {
+        let key_cache = self.message_cache.get(key)?;
+        Some(TimeWindow {
+            start: key_cache.front()?.time().publish_time,
+            end:   key_cache.back()?.time().publish_time,
+        })
+    }
+
     /// Store the accumulator state in the cache assuming that the lock is already acquired.
     fn store_accumulator_state_impl(
         &self,
         state: AccumulatorState,
-        cache: &mut VecDeque<AccumulatorState>,
+        cache: &mut VecDeque<AccumulatorEntry>,
     ) {
-        cache.push_back(state);
+        cache.push_back(AccumulatorEntry {
+            state,
+            created_at: Instant::now(),
+        });
 
         let mut i = cache.len().saturating_sub(1);
-        while i > 0 && cache[i - 1].slot > cache[i].slot {
+        while i > 0 && cache[i - 1].state.slot > cache[i].state.slot {
             cache.swap(i - 1, i);
             i -= 1;
         }
 
-        if cache.len() > self.cache_size as usize {
-            cache.pop_front();
+        if cache.len() > self.config.max_accumulator_states {
+            if let Some(evicted) = cache.pop_front() {
+                if !evicted.state.is_complete() {
+                    METRICS.record_accumulator_state(AccumulatorStateOutcome::EvictedIncomplete);
+                }
+            }
         }
     }
 }
@@ -113,32 +364,44 @@ impl Storage for LocalStorage {
     /// Add a new db entry to the cache.
     ///
     /// This method keeps the backed store sorted for efficiency, and removes
-    /// the oldest record in the cache if the max_size is reached. Entries are
+    /// the oldest records in the cache if a limit is reached. Entries are
     /// usually added in increasing order and likely to be inserted near the
     /// end of the deque. The function is optimized for this specific case.
+    /// Entries are unique by time: storing an entry again replaces the stored one.
     async fn store_message_states(&self, message_states: Vec<MessageState>) -> Result<()> {
         for message_state in message_states {
             let key = message_state.key();
 
-            let mut key_cache = self.message_cache.entry(key).or_insert_with(VecDeque::new);
+            let time = message_state.time();
 
-            key_cache.push_back(message_state);
+            let mut key_cache = self.message_cache.entry(key).or_insert_with(VecDeque::new);
 
-            // Shift the pushed record until it's in the right place.
-            let mut i = key_cache.len().saturating_sub(1);
-            while i > 0 && key_cache[i - 1].time() > key_cache[i].time() {
-                key_cache.swap(i - 1, i);
+            // Find the place of the record, starting from the end.
+            let mut i = key_cache.len();
+            while i > 0 && key_cache[i - 1].time() > time {
                 i -= 1;
             }
 
-            // FIXME remove equal elements by key and time
-
-            // Remove the oldest record if the max size is reached.
-            if key_cache.len() > self.cache_size as usize {
-                key_cache.pop_front();
+            self.message_bytes
+                .fetch_add(Self::message_state_size(&message_state), Ordering::Relaxed);
+
+            // A record with the same time (e.g. a slot replayed after a reconnection) replaces
+            // the stored one, so that storing the same message states again is a no-op.
+            if i > 0 && key_cache[i - 1].time() == time {
+                let replaced = std::mem::replace(&mut key_cache[i - 1], message_state);
+                self.message_bytes
+                    .fetch_sub(Self::message_state_size(&replaced), Ordering::Relaxed);
+            } else {
+                key_cache.insert(i, message_state);
             }
+
+            // Remove the oldest records if a limit is reached.
+            self.evict_message_states(&mut key_cache);
         }
 
+        // The entry of the last key is released, the memory budget is shared by all of them.
+        self.evict_message_states_over_budget();
+
         Ok(())
     }
 
@@ -148,24 +411,30 @@ impl Storage for LocalStorage {
         request_time: RequestTime,
         filter: MessageStateFilter,
     ) -> Result<Vec<MessageState>> {
-        ids.into_iter()
-            .flat_map(|id| {
-                let request_time = request_time.clone();
-                let message_types: Vec<MessageType> = match filter {
-                    MessageStateFilter::All => MessageType::iter().collect(),
-                    MessageStateFilter::Only(t) => vec![t],
+        let message_types: Vec<MessageType> = match filter {
+            MessageStateFilter::All => MessageType::iter().collect(),
+            MessageStateFilter::Only(t) => vec![t],
+        };
+
+        let mut message_states = vec![];
+        let mut missing = vec![];
+        for id in ids {
+            for message_type in message_types.iter() {
+                let key = MessageStateKey {
+                    feed_id: id,
+                    type_:   *message_type,
                 };
+                match self.retrieve_message_states(key.clone(), request_time.clone()) {
+                    Some(states) => message_states.extend(states),
+                    None => missing.push((PriceIdentifier::new(id), self.retained_window(&key))),
+                }
+            }
+        }
 
-                message_types.into_iter().map(move |message_type| {
-                    let key = MessageStateKey {
-                        feed_id: id,
-                        type_:   message_type,
-                    };
-                    self.retrieve_message_state(key, request_time.clone())
-                        .ok_or(anyhow!("Message not found"))
-                })
-            })
-            .collect()
+        match LookupError::from_missing(&request_time, missing) {
+            Some(error) => Err(error.into()),
+            None => Ok(message_states),
+        }
     }
 
     async fn message_state_keys(&self) -> Vec<MessageStateKey> {
@@ -183,8 +452,8 @@ impl Storage for LocalStorage {
 
     async fn fetch_accumulator_state(&self, slot: Slot) -> Result<Option<super::AccumulatorState>> {
         let accumulator_cache = self.accumulator_cache.read().await;
-        match accumulator_cache.binary_search_by_key(&slot, |state| state.slot) {
-            Ok(idx) => Ok(accumulator_cache.get(idx).cloned()),
+        match accumulator_cache.binary_search_by_key(&slot, |entry| entry.state.slot) {
+            Ok(idx) => Ok(accumulator_cache.get(idx).map(|entry| entry.state.clone())),
             Err(_) => Ok(None),
         }
     }
@@ -195,10 +464,10 @@ impl Storage for LocalStorage {
         callback: Box<dyn (FnOnce(AccumulatorState) -> AccumulatorState) + Send>,
     ) -> Result<()> {
         let mut accumulator_cache = self.accumulator_cache.write().await;
-        match accumulator_cache.binary_search_by_key(&slot, |state| state.slot) {
+        match accumulator_cache.binary_search_by_key(&slot, |entry| entry.state.slot) {
             Ok(idx) => {
-                let state = accumulator_cache.get_mut(idx).unwrap();
-                *state = callback(state.clone());
+                let entry = accumulator_cache.get_mut(idx).unwrap();
+                entry.state = callback(entry.state.clone());
             }
             Err(_) => {
                 let state = callback(AccumulatorState {
@@ -212,6 +481,19 @@ impl Storage for LocalStorage {
 
         Ok(())
     }
+
+    async fn evict_stale_states(&self) -> Result<()> {
+        let mut accumulator_cache = self.accumulator_cache.write().await;
+        accumulator_cache.retain(|entry| {
+            let expired = !entry.state.is_complete()
+                && entry.created_at.elapsed() >= self.config.incomplete_accumulator_ttl;
+            if expired {
+                METRICS.record_accumulator_state(AccumulatorStateOutcome::ExpiredIncomplete);
+            }
+            !expired
+        });
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -488,6 +770,315 @@ mod test {
             .is_err());
     }
 
+    #[tokio::test]
+    pub async fn test_store_and_retrieve_range_message_states_works() {
+        // Initialize a storage with a cache size of 4 per key.
+        let storage = LocalStorage::new_instance(4);
+
+        // Create and store message states with feed id [1....] at publish times 10, 13, 13, 20.
+        let message_state_1 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 10, 5).await;
+        let message_state_2 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 13, 10).await;
+        let message_state_3 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 13, 11).await;
+        let message_state_4 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 20, 15).await;
+
+        // Both ends of the range are inclusive.
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::Range {
+                        start: 10,
+                        end:   13,
+                        limit: usize::MAX,
+                    },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![
+                message_state_1,
+                message_state_2.clone(),
+                message_state_3.clone()
+            ]
+        );
+
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::Range {
+                        start: 11,
+                        end:   30,
+                        limit: usize::MAX,
+                    },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![
+                message_state_2.clone(),
+                message_state_3.clone(),
+                message_state_4
+            ]
+        );
+
+        // An open range stops after the limit.
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::Range {
+                        start: 11,
+                        end:   UnixTimestamp::MAX,
+                        limit: 2,
+                    },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![message_state_2, message_state_3]
+        );
+
+        // A range without any message is empty but valid.
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::Range {
+                        start: 14,
+                        end:   19,
+                        limit: usize::MAX,
+                    },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_store_and_retrieve_range_message_states_fails_for_past_time() {
+        // Initialize a storage with a cache size of 2 per key.
+        let storage = LocalStorage::new_instance(2);
+
+        // Create and store a message state with feed id [1....] and publish time 10 at slot 5.
+        create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 10, 5).await;
+
+        // A range starting before the oldest message cannot be served completely.
+        assert!(storage
+            .fetch_message_states(
+                vec![[1; 32]],
+                RequestTime::Range {
+                    start: 9,
+                    end:   20,
+                    limit: usize::MAX,
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage)
+            )
+            .await
+            .is_err());
+        assert!(storage
+            .fetch_message_states(
+                vec![[1; 32]],
+                RequestTime::Range {
+                    start: 9,
+                    end:   UnixTimestamp::MAX,
+                    limit: 1,
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage)
+            )
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_store_and_retrieve_message_state_at_slot_works() {
+        // Initialize a storage with a cache size of 4 per key.
+        let storage = LocalStorage::new_instance(4);
+
+        create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 10, 5).await;
+        let message_state =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 13, 10).await;
+        create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 20, 15).await;
+
+        let fetch_at_slot = |slot| {
+            storage.fetch_message_states(
+                vec![[1; 32]],
+                RequestTime::AtSlot(slot),
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+        };
+
+        assert_eq!(fetch_at_slot(10).await.unwrap(), vec![message_state]);
+
+        // A slot without a message of the feed is empty but valid.
+        assert_eq!(fetch_at_slot(11).await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    pub async fn test_store_and_retrieve_message_states_page_works() {
+        // Initialize a storage with a cache size of 4 per key.
+        let storage = LocalStorage::new_instance(4);
+
+        // Create and store message states with feed id [1....] at publish times 10, 13, 13, 20.
+        let message_state_1 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 10, 5).await;
+        let message_state_2 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 13, 10).await;
+        let message_state_3 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 13, 11).await;
+        create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 20, 15).await;
+
+        let fetch_page = |start_time, start_slot, end_time, limit| {
+            storage.fetch_message_states(
+                vec![[1; 32]],
+                RequestTime::Page {
+                    start_time,
+                    start_slot,
+                    end_time,
+                    limit,
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+        };
+
+        assert_eq!(
+            fetch_page(10, 0, 30, 2).await.unwrap(),
+            vec![message_state_1, message_state_2.clone()]
+        );
+
+        // Same as a range, a page cannot start before the oldest message.
+        assert!(fetch_page(9, 0, 30, 2).await.is_err());
+
+        // The start slot is inclusive.
+        assert_eq!(
+            fetch_page(13, 10, 30, 2).await.unwrap(),
+            vec![message_state_2, message_state_3.clone()]
+        );
+        assert_eq!(
+            fetch_page(13, 11, 19, 2).await.unwrap(),
+            vec![message_state_3]
+        );
+
+        // A feed without any message is still not found.
+        assert!(storage
+            .fetch_message_states(
+                vec![[2; 32]],
+                RequestTime::Page {
+                    start_time: 0,
+                    start_slot: 0,
+                    end_time:   30,
+                    limit:      2,
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_store_and_retrieve_message_states_after_slot_works() {
+        // Initialize a storage with a cache size of 4 per key.
+        let storage = LocalStorage::new_instance(4);
+
+        // Create and store message states with feed id [1....] at slots 5, 10, 11.
+        let message_state_1 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 10, 5).await;
+        let message_state_2 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 13, 10).await;
+        let message_state_3 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 13, 11).await;
+
+        // The given slot is excluded.
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::AfterSlot {
+                        slot:  5,
+                        limit: usize::MAX,
+                    },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![message_state_2.clone(), message_state_3.clone()]
+        );
+
+        // The given slot is included when fetching the messages since a slot.
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::SinceSlot(10),
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![message_state_2.clone(), message_state_3.clone()]
+        );
+
+        // Nothing was stored after the latest slot.
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::AfterSlot {
+                        slot:  11,
+                        limit: usize::MAX,
+                    },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![]
+        );
+
+        // Messages before the oldest stored one might be missing.
+        assert!(storage
+            .fetch_message_states(
+                vec![[1; 32]],
+                RequestTime::AfterSlot {
+                    slot:  4,
+                    limit: usize::MAX,
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage)
+            )
+            .await
+            .is_err());
+
+        // Unless only the latest messages are needed.
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::AfterSlot { slot: 4, limit: 2 },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![message_state_2.clone(), message_state_3.clone()]
+        );
+
+        // Or only the stored messages are needed.
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::SinceSlot(4),
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![message_state_1, message_state_2, message_state_3]
+        );
+    }
+
     #[tokio::test]
     pub async fn test_store_more_message_states_than_cache_size_evicts_old_messages() {
         // Initialize a storage with a cache size of 2 per key.
@@ -513,6 +1104,229 @@ mod test {
             .is_err());
     }
 
+    #[tokio::test]
+    pub async fn test_store_repeated_message_states_keeps_one_of_each() {
+        // Initialize a storage with a cache size of 3 per key.
+        let storage = LocalStorage::new_instance(3);
+
+        // Store the same slots several times, in order and out of order.
+        let message_state_1 = create_dummy_price_feed_message_state([1; 32], 10, 5);
+        let message_state_2 = create_dummy_price_feed_message_state([1; 32], 13, 10);
+        for message_state in [
+            &message_state_2,
+            &message_state_1,
+            &message_state_2,
+            &message_state_2,
+            &message_state_1,
+        ] {
+            storage
+                .store_message_states(vec![message_state.clone()])
+                .await
+                .unwrap();
+        }
+
+        // A message with the same publish time in another slot is a different record.
+        let message_state_3 = create_dummy_price_feed_message_state([1; 32], 13, 11);
+        storage
+            .store_message_states(vec![message_state_3.clone(), message_state_3.clone()])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::Range {
+                        start: 10,
+                        end:   13,
+                        limit: usize::MAX,
+                    },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage)
+                )
+                .await
+                .unwrap(),
+            vec![
+                message_state_1.clone(),
+                message_state_2.clone(),
+                message_state_3.clone()
+            ]
+        );
+
+        // The duplicates did not take the place of the oldest record.
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::FirstAfter(10),
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage)
+                )
+                .await
+                .unwrap(),
+            vec![message_state_1]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_store_repeated_message_state_replaces_the_stored_one() {
+        // Initialize a storage with a cache size of 2 per key.
+        let storage = LocalStorage::new_instance(2);
+
+        create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 10, 5).await;
+
+        // The same slot received again, e.g. after a reconnection.
+        let message_state = MessageState {
+            received_at: 20,
+            ..create_dummy_price_feed_message_state([1; 32], 10, 5)
+        };
+        storage
+            .store_message_states(vec![message_state.clone()])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::SinceSlot(0),
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage)
+                )
+                .await
+                .unwrap(),
+            vec![message_state]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_store_message_states_older_than_max_age_evicts_them() {
+        // Initialize a storage that keeps the messages of the last 10 seconds of each feed.
+        let storage = LocalStorage::with_config(LocalStorageConfig {
+            max_message_age: Some(Duration::from_secs(10)),
+            ..Default::default()
+        });
+
+        create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 10, 5).await;
+        let message_state_2 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 15, 10).await;
+        let message_state_3 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 25, 14).await;
+
+        // The message at time 10 is more than 10 seconds older than the latest one.
+        assert!(storage
+            .fetch_message_states(
+                vec![[1; 32]],
+                RequestTime::FirstAfter(10),
+                MessageStateFilter::Only(MessageType::PriceFeedMessage)
+            )
+            .await
+            .is_err());
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::Range {
+                        start: 15,
+                        end:   25,
+                        limit: usize::MAX,
+                    },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage)
+                )
+                .await
+                .unwrap(),
+            vec![message_state_2, message_state_3]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_store_message_states_over_memory_budget_evicts_old_messages() {
+        // Initialize a storage with room for about two message states.
+        let storage = LocalStorage::with_config(LocalStorageConfig {
+            max_message_bytes: Some(2 * std::mem::size_of::<MessageState>()),
+            ..Default::default()
+        });
+
+        create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 10, 5).await;
+        create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 13, 10).await;
+        create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 20, 14).await;
+
+        // The oldest message of the feed was evicted to stay within the budget.
+        assert!(storage
+            .fetch_message_states(
+                vec![[1; 32]],
+                RequestTime::FirstAfter(10),
+                MessageStateFilter::Only(MessageType::PriceFeedMessage)
+            )
+            .await
+            .is_err());
+
+        // The latest message of a feed is kept even if the budget is exceeded.
+        let message_state =
+            create_and_store_dummy_price_feed_message_state(&storage, [2; 32], 20, 14).await;
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[2; 32]],
+                    RequestTime::Latest,
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage)
+                )
+                .await
+                .unwrap(),
+            vec![message_state]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_store_message_states_over_memory_budget_evicts_oldest_of_all_feeds() {
+        // Initialize a storage with room for about three message states.
+        let storage = LocalStorage::with_config(LocalStorageConfig {
+            max_message_bytes: Some(3 * std::mem::size_of::<MessageState>()),
+            ..Default::default()
+        });
+
+        create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 10, 5).await;
+        let message_state_2 =
+            create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 30, 20).await;
+        let message_state_3 =
+            create_and_store_dummy_price_feed_message_state(&storage, [2; 32], 20, 14).await;
+        let message_state_4 =
+            create_and_store_dummy_price_feed_message_state(&storage, [2; 32], 25, 17).await;
+
+        // The oldest message overall is evicted, not the oldest one of the feed being stored.
+        assert!(storage
+            .fetch_message_states(
+                vec![[1; 32]],
+                RequestTime::FirstAfter(10),
+                MessageStateFilter::Only(MessageType::PriceFeedMessage)
+            )
+            .await
+            .is_err());
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::Latest,
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage)
+                )
+                .await
+                .unwrap(),
+            vec![message_state_2]
+        );
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[2; 32]],
+                    RequestTime::Range {
+                        start: 20,
+                        end:   25,
+                        limit: usize::MAX,
+                    },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage)
+                )
+                .await
+                .unwrap(),
+            vec![message_state_3, message_state_4]
+        );
+    }
+
     #[tokio::test]
     pub async fn test_store_and_receive_multiple_message_feed_ids_works() {
         // Initialize a storage with a cache size of 1 per key.
@@ -558,6 +1372,71 @@ mod test {
             .is_err());
     }
 
+    #[tokio::test]
+    pub async fn test_fetch_message_states_errors_tell_the_failures_apart() {
+        // Initialize a storage with a cache size of 2 per key.
+        let storage = LocalStorage::new_instance(2);
+
+        create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 10, 5).await;
+        create_and_store_dummy_price_feed_message_state(&storage, [1; 32], 13, 10).await;
+
+        let fetch_error = |ids: Vec<FeedId>, request_time: RequestTime| {
+            let storage = &storage;
+            async move {
+                storage
+                    .fetch_message_states(
+                        ids,
+                        request_time,
+                        MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                    )
+                    .await
+                    .unwrap_err()
+                    .downcast::<LookupError>()
+                    .unwrap()
+            }
+        };
+
+        let retained_window = Some(TimeWindow {
+            start: 10,
+            end:   13,
+        });
+
+        // Unknown feeds are reported before the other failures.
+        assert_eq!(
+            fetch_error(vec![[1; 32], [2; 32]], RequestTime::FirstAfter(9)).await,
+            LookupError::FeedsNotFound(vec![PriceIdentifier::new([2; 32])])
+        );
+        assert_eq!(
+            fetch_error(vec![[1; 32]], RequestTime::FirstAfter(9)).await,
+            LookupError::PublishTimeTooOld {
+                feed_ids: vec![PriceIdentifier::new([1; 32])],
+                retained_window,
+            }
+        );
+        assert_eq!(
+            fetch_error(
+                vec![[1; 32]],
+                RequestTime::Range {
+                    start: 9,
+                    end:   12,
+                    limit: usize::MAX,
+                }
+            )
+            .await,
+            LookupError::PublishTimeTooOld {
+                feed_ids: vec![PriceIdentifier::new([1; 32])],
+                retained_window,
+            }
+        );
+        assert_eq!(
+            fetch_error(vec![[1; 32]], RequestTime::FirstAfter(14)).await,
+            LookupError::PublishTimeInFuture {
+                feed_ids: vec![PriceIdentifier::new([1; 32])],
+                retained_window,
+            }
+        );
+    }
+
     pub fn create_empty_accumulator_state_at_slot(slot: Slot) -> AccumulatorState {
         AccumulatorState {
             slot,
@@ -819,6 +1698,57 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    pub async fn test_evict_stale_states_drops_expired_incomplete_accumulator_states() {
+        // Initialize a storage in which the incomplete accumulator states expire immediately.
+        let storage = LocalStorage::with_config(LocalStorageConfig {
+            incomplete_accumulator_ttl: Duration::ZERO,
+            ..Default::default()
+        });
+
+        let accumulator_messages = AccumulatorMessages {
+            magic:        [0; 4],
+            slot:         123,
+            ring_size:    3,
+            raw_messages: vec![],
+        };
+        let wormhole_merkle_state = WormholeMerkleState {
+            root: WormholeMerkleRoot {
+                root:      [0; 20],
+                slot:      123,
+                ring_size: 3,
+            },
+            vaa:  vec![],
+        };
+
+        // The state of slot 10 never gets its VAA, the one of slot 11 is complete.
+        storage
+            .store_accumulator_state(AccumulatorState {
+                slot:                  10,
+                accumulator_messages:  Some(accumulator_messages.clone()),
+                wormhole_merkle_state: None,
+            })
+            .await
+            .unwrap();
+        storage
+            .store_accumulator_state(AccumulatorState {
+                slot:                  11,
+                accumulator_messages:  Some(accumulator_messages),
+                wormhole_merkle_state: Some(wormhole_merkle_state),
+            })
+            .await
+            .unwrap();
+
+        storage.evict_stale_states().await.unwrap();
+
+        assert_eq!(storage.fetch_accumulator_state(10).await.unwrap(), None);
+        assert!(storage
+            .fetch_accumulator_state(11)
+            .await
+            .unwrap()
+            .map_or(false, |state| state.is_complete()));
+    }
+
     #[tokio::test]
     pub async fn test_update_accumulator_state_evicts_cache() {
         // Initialize a storage with a cache size of 2 per key and the accumulator state.
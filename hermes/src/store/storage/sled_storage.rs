@@ -0,0 +1,1152 @@
+use {
+    super::{
+        AccumulatorState,
+        MessageState,
+        MessageStateFilter,
+        MessageStateKey,
+        MessageStateTime,
+        RequestTime,
+        Storage,
+        StorageInstance,
+    },
+    crate::{
+        metrics::{
+            AccumulatorStateOutcome,
+            METRICS,
+        },
+        store::types::{
+            LookupError,
+            Slot,
+            TimeWindow,
+            UnixTimestamp,
+        },
+    },
+    anyhow::{
+        anyhow,
+        Result,
+    },
+    async_trait::async_trait,
+    pyth_sdk::PriceIdentifier,
+    pythnet_sdk::messages::{
+        FeedId,
+        MessageType,
+    },
+    std::{
+        collections::HashMap,
+        path::Path,
+        sync::Arc,
+        time::{
+            Duration,
+            SystemTime,
+            UNIX_EPOCH,
+        },
+    },
+    strum::IntoEnumIterator,
+    tokio::{
+        sync::{
+            Mutex,
+            OwnedMutexGuard,
+        },
+        time::Instant,
+    },
+};
+
+/// Retention limits of the on-disk database.
+#[derive(Clone, Debug)]
+pub struct SledStorageConfig {
+    /// Message states published longer than this before the latest one of their feed are
+    /// removed. The janitor also removes them relative to the current time, for the feeds that
+    /// stopped publishing.
+    pub retention:                  Duration,
+    /// Number of slots behind the newest accumulator state that are kept. Accumulator states are
+    /// only needed until both the messages and the VAA of a slot arrive.
+    pub max_accumulator_slots:      Slot,
+    /// Incomplete accumulator states older than this are dropped by the janitor, their other
+    /// part will most likely never arrive.
+    pub incomplete_accumulator_ttl: Duration,
+}
+
+impl Default for SledStorageConfig {
+    fn default() -> Self {
+        Self {
+            retention:                  Duration::from_secs(7 * 24 * 60 * 60),
+            max_accumulator_slots:      1000,
+            incomplete_accumulator_ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+/// When each incomplete accumulator state was first stored, by slot.
+type IncompleteAccumulatorStates = HashMap<Slot, Instant>;
+
+/// A persistent storage backed by the sled embedded key-value store.
+///
+/// Message states are stored in a single tree under a `feed_id | type | publish_time | slot` key.
+/// All the fields are encoded in big-endian order (with the sign bit of the publish time flipped)
+/// so that the lexicographic order of the keys matches the `MessageStateTime` order of the
+/// states. This allows answering `Latest` and `FirstAfter` requests with a single seek.
+///
+/// Sled calls block on disk IO, so they run on the blocking thread pool of Tokio. Cloning the
+/// storage only clones the handles of its trees, which is what the blocking tasks work on.
+#[derive(Clone)]
+pub struct SledStorage {
+    message_states:     sled::Tree,
+    message_state_keys: sled::Tree,
+    accumulator_states: sled::Tree,
+    /// Serializes read-modify-write cycles on the accumulator states and tracks when the
+    /// incomplete ones were stored.
+    accumulator_lock:   Arc<Mutex<IncompleteAccumulatorStates>>,
+    config:             SledStorageConfig,
+}
+
+impl SledStorage {
+    pub fn new_instance(path: &Path, config: SledStorageConfig) -> Result<StorageInstance> {
+        Ok(Box::new(Self::from_db(sled::open(path)?, config)?))
+    }
+
+    fn from_db(db: sled::Db, config: SledStorageConfig) -> Result<Self> {
+        let accumulator_states = db.open_tree("accumulator_states")?;
+
+        // The incomplete states found on disk are given a full TTL from now.
+        let mut incomplete = IncompleteAccumulatorStates::new();
+        for entry in accumulator_states.iter() {
+            let (slot, value) = entry?;
+            if !Self::decode_accumulator_state(&value)?.is_complete() {
+                incomplete.insert(
+                    Slot::from_be_bytes(slot.as_ref().try_into()?),
+                    Instant::now(),
+                );
+            }
+        }
+
+        Ok(Self {
+            message_states: db.open_tree("message_states")?,
+            message_state_keys: db.open_tree("message_state_keys")?,
+            accumulator_states,
+            accumulator_lock: Arc::new(Mutex::new(incomplete)),
+            config,
+        })
+    }
+
+    /// Runs `f` on a clone of the storage in the blocking thread pool.
+    async fn run_blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Self) -> Result<T> + Send + 'static,
+    {
+        let storage = self.clone();
+        tokio::task::spawn_blocking(move || f(&storage)).await?
+    }
+
+    /// Locks the accumulator states, for the guard to be moved to a blocking task.
+    async fn lock_accumulator_states(&self) -> OwnedMutexGuard<IncompleteAccumulatorStates> {
+        self.accumulator_lock.clone().lock_owned().await
+    }
+
+    fn encode_key_prefix(key: &MessageStateKey) -> Vec<u8> {
+        let mut prefix = Vec::with_capacity(33);
+        prefix.extend_from_slice(&key.feed_id);
+        prefix.push(key.type_ as u8);
+        prefix
+    }
+
+    fn encode_key(key: &MessageStateKey, time: &MessageStateTime) -> Vec<u8> {
+        let mut encoded = Self::encode_key_prefix(key);
+        encoded.extend_from_slice(&Self::encode_publish_time(time.publish_time));
+        encoded.extend_from_slice(&time.slot.to_be_bytes());
+        encoded
+    }
+
+    /// Flip the sign bit so negative timestamps are ordered before the positive ones.
+    fn encode_publish_time(publish_time: UnixTimestamp) -> [u8; 8] {
+        ((publish_time as u64) ^ (1 << 63)).to_be_bytes()
+    }
+
+    fn decode_message_state(value: &[u8]) -> Result<MessageState> {
+        bincode::deserialize(value).map_err(|e| anyhow!("Failed to decode message state: {}", e))
+    }
+
+    fn decode_accumulator_state(value: &[u8]) -> Result<AccumulatorState> {
+        bincode::deserialize(value)
+            .map_err(|e| anyhow!("Failed to decode accumulator state: {}", e))
+    }
+
+    /// Whether the stored message states of the key prefix go back to at least the given time.
+    /// Same as the local storage, if the requested time is before the oldest record we cannot be
+    /// sure that the oldest record is the closest one.
+    fn covers_time(&self, prefix: &[u8], time: UnixTimestamp) -> Result<bool> {
+        match self.message_states.scan_prefix(prefix).next() {
+            Some(oldest_record) => {
+                let (_, value) = oldest_record?;
+                Ok(time >= Self::decode_message_state(&value)?.time().publish_time)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Publish times of the oldest and newest message states of the key, if any. The keys are
+    /// ordered by publish time, so they are the first and last entries of the prefix.
+    fn retained_window(&self, key: &MessageStateKey) -> Result<Option<TimeWindow>> {
+        let prefix = Self::encode_key_prefix(key);
+        let mut entries = self.message_states.scan_prefix(prefix);
+        let (oldest, newest) = match (entries.next(), entries.next_back()) {
+            (Some(oldest), Some(newest)) => (oldest?.1, newest?.1),
+            (Some(oldest), None) => {
+                let (_, value) = oldest?;
+                (value.clone(), value)
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(TimeWindow {
+            start: Self::decode_message_state(&oldest)?.time().publish_time,
+            end:   Self::decode_message_state(&newest)?.time().publish_time,
+        }))
+    }
+
+    fn retrieve_message_states(
+        &self,
+        key: MessageStateKey,
+        request_time: RequestTime,
+    ) -> Result<Option<Vec<MessageState>>> {
+        let prefix = Self::encode_key_prefix(&key);
+
+        let entry = match request_time {
+            RequestTime::Latest => self.message_states.scan_prefix(&prefix).next_back(),
+            RequestTime::FirstAfter(time) => {
+                if !self.covers_time(&prefix, time)? {
+                    return Ok(None);
+                }
+
+                let lookup_key = Self::encode_key(
+                    &key,
+                    &MessageStateTime {
+                        publish_time: time,
+                        slot:         0,
+                    },
+                );
+
+                self.message_states
+                    .range(lookup_key..)
+                    .next()
+                    .filter(|entry| match entry {
+                        Ok((key, _)) => key.starts_with(&prefix),
+                        Err(_) => true,
+                    })
+            }
+            RequestTime::Range { start, end, limit } => {
+                if !self.covers_time(&prefix, start)? {
+                    return Ok(None);
+                }
+
+                let start_key = Self::encode_key(
+                    &key,
+                    &MessageStateTime {
+                        publish_time: start,
+                        slot:         0,
+                    },
+                );
+                let end_key = Self::encode_key(
+                    &key,
+                    &MessageStateTime {
+                        publish_time: end,
+                        slot:         Slot::MAX,
+                    },
+                );
+
+                return self
+                    .message_states
+                    .range(start_key..=end_key)
+                    .take(limit)
+                    .map(|entry| {
+                        let (_, value) = entry?;
+                        Self::decode_message_state(&value)
+                    })
+                    .collect::<Result<Vec<_>>>()
+                    .map(Some);
+            }
+            RequestTime::AtSlot(slot) => {
+                let mut entries = self.message_states.scan_prefix(&prefix).rev().peekable();
+                if entries.peek().is_none() {
+                    return Ok(None);
+                }
+
+                let mut message_states = vec![];
+                for entry in entries {
+                    let (_, value) = entry?;
+                    let message_state = Self::decode_message_state(&value)?;
+                    if message_state.slot < slot {
+                        break;
+                    }
+                    if message_state.slot == slot {
+                        message_states.push(message_state);
+                    }
+                }
+                return Ok(Some(message_states));
+            }
+            RequestTime::Page {
+                start_time,
+                start_slot,
+                end_time,
+                limit,
+            } => {
+                if !self.covers_time(&prefix, start_time)? {
+                    return Ok(None);
+                }
+                if start_time > end_time {
+                    return Ok(Some(vec![]));
+                }
+
+                let start_key = Self::encode_key(
+                    &key,
+                    &MessageStateTime {
+                        publish_time: start_time,
+                        slot:         start_slot,
+                    },
+                );
+                let end_key = Self::encode_key(
+                    &key,
+                    &MessageStateTime {
+                        publish_time: end_time,
+                        slot:         Slot::MAX,
+                    },
+                );
+
+                return self
+                    .message_states
+                    .range(start_key..=end_key)
+                    .take(limit)
+                    .map(|entry| {
+                        let (_, value) = entry?;
+                        Self::decode_message_state(&value)
+                    })
+                    .collect::<Result<Vec<_>>>()
+                    .map(Some);
+            }
+            RequestTime::AfterSlot { slot, limit } => {
+                let mut message_states = vec![];
+                // Same as the local storage, a message at or before the slot must be stored for
+                // the result to be complete, unless the limit is reached first.
+                let mut complete = false;
+                for entry in self.message_states.scan_prefix(&prefix).rev() {
+                    let (_, value) = entry?;
+                    let message_state = Self::decode_message_state(&value)?;
+                    if message_state.slot <= slot || message_states.len() == limit {
+                        complete = true;
+                        break;
+                    }
+                    message_states.push(message_state);
+                }
+
+                if !complete {
+                    return Ok(None);
+                }
+                message_states.reverse();
+                return Ok(Some(message_states));
+            }
+            RequestTime::SinceSlot(slot) => {
+                // The slots of the messages increase with their publish time, so the walk back
+                // from the latest message stops at the first one before the slot.
+                let mut message_states = vec![];
+                for entry in self.message_states.scan_prefix(&prefix).rev() {
+                    let (_, value) = entry?;
+                    let message_state = Self::decode_message_state(&value)?;
+                    if message_state.slot < slot {
+                        break;
+                    }
+                    message_states.push(message_state);
+                }
+
+                message_states.reverse();
+                return Ok(Some(message_states));
+            }
+        };
+
+        match entry {
+            Some(entry) => {
+                let (_, value) = entry?;
+                Ok(Some(vec![Self::decode_message_state(&value)?]))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Remove the message states of the given key that are older than the retention period
+    /// relative to the given publish time.
+    fn prune_message_states(&self, key: &MessageStateKey, newest: UnixTimestamp) -> Result<()> {
+        let cutoff = newest.saturating_sub(self.config.retention.as_secs() as UnixTimestamp);
+        let start = Self::encode_key_prefix(key);
+        let end = Self::encode_key(
+            key,
+            &MessageStateTime {
+                publish_time: cutoff,
+                slot:         0,
+            },
+        );
+
+        let mut batch = sled::Batch::default();
+        for entry in self.message_states.range(start..end) {
+            let (key, _) = entry?;
+            batch.remove(key);
+        }
+        self.message_states.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// Store the message states, then prune each of their keys once relative to its newest
+    /// message of the batch.
+    fn store_message_states_impl(&self, message_states: Vec<MessageState>) -> Result<()> {
+        let mut newest: HashMap<MessageStateKey, UnixTimestamp> = HashMap::new();
+        let mut batch = sled::Batch::default();
+        for message_state in message_states {
+            let key = message_state.key();
+            let time = message_state.time();
+
+            batch.insert(
+                Self::encode_key(&key, &time),
+                bincode::serialize(&message_state)?,
+            );
+            let newest = newest.entry(key).or_insert(time.publish_time);
+            *newest = (*newest).max(time.publish_time);
+        }
+        self.message_states.apply_batch(batch)?;
+
+        for (key, publish_time) in newest {
+            self.message_state_keys.insert(
+                Self::encode_key_prefix(&key),
+                bincode::serialize(&key.type_)?,
+            )?;
+            self.prune_message_states(&key, publish_time)?;
+        }
+
+        Ok(())
+    }
+
+    fn decode_message_state_keys(&self) -> Result<Vec<MessageStateKey>> {
+        self.message_state_keys
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry?;
+                Ok(MessageStateKey {
+                    feed_id: key
+                        .get(0..32)
+                        .ok_or_else(|| anyhow!("Invalid message state key"))?
+                        .try_into()?,
+                    type_:   bincode::deserialize::<MessageType>(&value)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Store the accumulator state assuming that the accumulator lock is already acquired.
+    fn store_accumulator_state_impl(
+        &self,
+        state: AccumulatorState,
+        incomplete: &mut IncompleteAccumulatorStates,
+    ) -> Result<()> {
+        let slot = state.slot;
+        self.accumulator_states
+            .insert(slot.to_be_bytes(), bincode::serialize(&state)?)?;
+        if state.is_complete() {
+            incomplete.remove(&slot);
+        } else {
+            incomplete.entry(slot).or_insert_with(Instant::now);
+        }
+
+        // Drop the states that are too far behind the newest one.
+        if let Some((newest, _)) = self.accumulator_states.last()? {
+            let newest = Slot::from_be_bytes(newest.as_ref().try_into()?);
+            let cutoff = newest.saturating_sub(self.config.max_accumulator_slots);
+            while let Some((oldest, value)) = self.accumulator_states.first()? {
+                let oldest_slot = Slot::from_be_bytes(oldest.as_ref().try_into()?);
+                if oldest_slot >= cutoff {
+                    break;
+                }
+                self.accumulator_states.remove(oldest)?;
+                incomplete.remove(&oldest_slot);
+                if !Self::decode_accumulator_state(&value)?.is_complete() {
+                    METRICS.record_accumulator_state(AccumulatorStateOutcome::EvictedIncomplete);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drop the expired incomplete accumulator states and the message states past the retention
+    /// period relative to the given time.
+    fn evict_stale_states_impl(
+        &self,
+        now: UnixTimestamp,
+        incomplete: &mut IncompleteAccumulatorStates,
+    ) -> Result<()> {
+        for key in self.decode_message_state_keys()? {
+            self.prune_message_states(&key, now)?;
+        }
+
+        let ttl = self.config.incomplete_accumulator_ttl;
+        let expired: Vec<Slot> = incomplete
+            .iter()
+            .filter(|(_, created_at)| created_at.elapsed() >= ttl)
+            .map(|(slot, _)| *slot)
+            .collect();
+        for slot in expired {
+            incomplete.remove(&slot);
+            if self
+                .accumulator_states
+                .remove(slot.to_be_bytes())?
+                .is_some()
+            {
+                METRICS.record_accumulator_state(AccumulatorStateOutcome::ExpiredIncomplete);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for SledStorage {
+    async fn message_state_keys(&self) -> Vec<MessageStateKey> {
+        self.run_blocking(|storage| storage.decode_message_state_keys())
+            .await
+            .unwrap_or_else(|e| {
+                log::error!("Failed to read the message state keys: {:?}", e);
+                vec![]
+            })
+    }
+
+    async fn store_message_states(&self, message_states: Vec<MessageState>) -> Result<()> {
+        self.run_blocking(move |storage| storage.store_message_states_impl(message_states))
+            .await
+    }
+
+    async fn fetch_message_states(
+        &self,
+        ids: Vec<FeedId>,
+        request_time: RequestTime,
+        filter: MessageStateFilter,
+    ) -> Result<Vec<MessageState>> {
+        let message_types: Vec<MessageType> = match filter {
+            MessageStateFilter::All => MessageType::iter().collect(),
+            MessageStateFilter::Only(t) => vec![t],
+        };
+
+        self.run_blocking(move |storage| {
+            let mut message_states = vec![];
+            let mut missing = vec![];
+            for id in ids {
+                for message_type in message_types.iter() {
+                    let key = MessageStateKey {
+                        feed_id: id,
+                        type_:   *message_type,
+                    };
+                    match storage.retrieve_message_states(key.clone(), request_time.clone())? {
+                        Some(states) => message_states.extend(states),
+                        None => {
+                            missing.push((PriceIdentifier::new(id), storage.retained_window(&key)?))
+                        }
+                    }
+                }
+            }
+
+            match LookupError::from_missing(&request_time, missing) {
+                Some(error) => Err(error.into()),
+                None => Ok(message_states),
+            }
+        })
+        .await
+    }
+
+    async fn store_accumulator_state(&self, state: AccumulatorState) -> Result<()> {
+        let mut incomplete = self.lock_accumulator_states().await;
+        self.run_blocking(move |storage| {
+            storage.store_accumulator_state_impl(state, &mut incomplete)
+        })
+        .await
+    }
+
+    async fn fetch_accumulator_state(&self, slot: Slot) -> Result<Option<AccumulatorState>> {
+        self.run_blocking(move |storage| {
+            match storage.accumulator_states.get(slot.to_be_bytes())? {
+                Some(value) => Ok(Some(Self::decode_accumulator_state(&value)?)),
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    async fn update_accumulator_state(
+        &self,
+        slot: Slot,
+        callback: Box<dyn (FnOnce(AccumulatorState) -> AccumulatorState) + Send>,
+    ) -> Result<()> {
+        let mut incomplete = self.lock_accumulator_states().await;
+        self.run_blocking(move |storage| {
+            let state = match storage.accumulator_states.get(slot.to_be_bytes())? {
+                Some(value) => Self::decode_accumulator_state(&value)?,
+                None => AccumulatorState {
+                    slot,
+                    accumulator_messages: None,
+                    wormhole_merkle_state: None,
+                },
+            };
+            storage.store_accumulator_state_impl(callback(state), &mut incomplete)
+        })
+        .await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        // All the trees share the same underlying database, flushing one of them flushes all.
+        self.message_states.flush_async().await?;
+        Ok(())
+    }
+
+    async fn evict_stale_states(&self) -> Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as UnixTimestamp;
+        let mut incomplete = self.lock_accumulator_states().await;
+        self.run_blocking(move |storage| storage.evict_stale_states_impl(now, &mut incomplete))
+            .await
+    }
+
+    fn is_persistent(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::store::{
+            proof::wormhole_merkle::{
+                WormholeMerkleMessageProof,
+                WormholeMerkleState,
+            },
+            types::{
+                AccumulatorMessages,
+                ProofSet,
+            },
+        },
+        pythnet_sdk::{
+            accumulators::merkle::MerklePath,
+            hashers::keccak256_160::Keccak160,
+            messages::{
+                Message,
+                PriceFeedMessage,
+            },
+            wire::v1::WormholeMerkleRoot,
+        },
+    };
+
+    fn test_config() -> SledStorageConfig {
+        SledStorageConfig {
+            retention: Duration::from_secs(100),
+            ..Default::default()
+        }
+    }
+
+    fn create_temporary_storage_with_config(config: SledStorageConfig) -> SledStorage {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        SledStorage::from_db(db, config).unwrap()
+    }
+
+    fn create_temporary_storage() -> SledStorage {
+        create_temporary_storage_with_config(test_config())
+    }
+
+    fn create_dummy_price_feed_message_state(
+        feed_id: FeedId,
+        publish_time: UnixTimestamp,
+        slot: Slot,
+    ) -> MessageState {
+        MessageState {
+            slot,
+            raw_message: vec![],
+            message: Message::PriceFeedMessage(PriceFeedMessage {
+                feed_id,
+                publish_time,
+                price: 1,
+                conf: 2,
+                exponent: 3,
+                ema_price: 4,
+                ema_conf: 5,
+                prev_publish_time: 6,
+            }),
+            received_at: publish_time,
+            proof_set: ProofSet {
+                wormhole_merkle_proof: WormholeMerkleMessageProof {
+                    vaa:   vec![1, 2, 3],
+                    proof: MerklePath::<Keccak160>::new(vec![[1; 20]]),
+                },
+            },
+        }
+    }
+
+    async fn fetch_price_feed_message_state(
+        storage: &SledStorage,
+        feed_id: FeedId,
+        request_time: RequestTime,
+    ) -> Option<MessageState> {
+        storage
+            .fetch_message_states(
+                vec![feed_id],
+                request_time,
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+            .await
+            .ok()
+            .and_then(|message_states| message_states.into_iter().next())
+    }
+
+    #[tokio::test]
+    pub async fn test_store_and_retrieve_message_states_works() {
+        let storage = create_temporary_storage();
+
+        // Store out of order, with a negative publish time to check the key ordering.
+        let message_state_1 = create_dummy_price_feed_message_state([1; 32], 10, 5);
+        let message_state_2 = create_dummy_price_feed_message_state([1; 32], 13, 10);
+        let message_state_3 = create_dummy_price_feed_message_state([1; 32], -5, 2);
+        let other_feed_message_state = create_dummy_price_feed_message_state([2; 32], 20, 12);
+        storage
+            .store_message_states(vec![
+                message_state_2.clone(),
+                message_state_1.clone(),
+                message_state_3.clone(),
+                other_feed_message_state.clone(),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::Latest).await,
+            Some(message_state_2.clone())
+        );
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [2; 32], RequestTime::Latest).await,
+            Some(other_feed_message_state)
+        );
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::AfterSlot {
+                        slot:  2,
+                        limit: usize::MAX,
+                    },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![message_state_1.clone(), message_state_2.clone()]
+        );
+        assert!(storage
+            .fetch_message_states(
+                vec![[1; 32]],
+                RequestTime::AfterSlot {
+                    slot:  1,
+                    limit: usize::MAX,
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+            .await
+            .is_err());
+        // Unless the limit is reached before.
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::AfterSlot { slot: 1, limit: 1 },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![message_state_2.clone()]
+        );
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::SinceSlot(5),
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![message_state_1.clone(), message_state_2.clone()]
+        );
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::SinceSlot(0),
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![
+                message_state_3.clone(),
+                message_state_1.clone(),
+                message_state_2.clone()
+            ]
+        );
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::SinceSlot(11),
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::AtSlot(5)).await,
+            Some(message_state_1.clone())
+        );
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::AtSlot(6)).await,
+            None
+        );
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::FirstAfter(-5)).await,
+            Some(message_state_3.clone())
+        );
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::FirstAfter(0)).await,
+            Some(message_state_1.clone())
+        );
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::FirstAfter(11)).await,
+            Some(message_state_2.clone())
+        );
+
+        // Before the oldest record or after the latest record there is no answer.
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::FirstAfter(-6)).await,
+            None
+        );
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::FirstAfter(14)).await,
+            None
+        );
+
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::Range {
+                        start: -5,
+                        end:   10,
+                        limit: usize::MAX,
+                    },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![message_state_3.clone(), message_state_1.clone()]
+        );
+        assert_eq!(
+            storage
+                .fetch_message_states(
+                    vec![[1; 32]],
+                    RequestTime::Range {
+                        start: 0,
+                        end:   UnixTimestamp::MAX,
+                        limit: 1,
+                    },
+                    MessageStateFilter::Only(MessageType::PriceFeedMessage),
+                )
+                .await
+                .unwrap(),
+            vec![message_state_1.clone()]
+        );
+        assert!(storage
+            .fetch_message_states(
+                vec![[1; 32]],
+                RequestTime::Range {
+                    start: -6,
+                    end:   UnixTimestamp::MAX,
+                    limit: 1,
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+            .await
+            .is_err());
+        assert!(storage
+            .fetch_message_states(
+                vec![[1; 32]],
+                RequestTime::Range {
+                    start: -6,
+                    end:   10,
+                    limit: usize::MAX,
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+            .await
+            .is_err());
+
+        // A page must start within the stored messages, and is seeked to the start slot within
+        // the start publish time.
+        let page = |start_time, start_slot, end_time, limit| {
+            storage.fetch_message_states(
+                vec![[1; 32]],
+                RequestTime::Page {
+                    start_time,
+                    start_slot,
+                    end_time,
+                    limit,
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+        };
+        assert_eq!(
+            page(-5, 0, 100, 2).await.unwrap(),
+            vec![message_state_3, message_state_1.clone()]
+        );
+        assert!(page(-100, 0, 100, 2).await.is_err());
+        assert_eq!(page(10, 6, 100, 2).await.unwrap(), vec![message_state_2]);
+        assert_eq!(page(10, 5, 12, 2).await.unwrap(), vec![message_state_1]);
+        assert_eq!(page(14, 0, 10, 2).await.unwrap(), vec![]);
+        assert!(storage
+            .fetch_message_states(
+                vec![[3; 32]],
+                RequestTime::Page {
+                    start_time: 0,
+                    start_slot: 0,
+                    end_time:   10,
+                    limit:      2,
+                },
+                MessageStateFilter::Only(MessageType::PriceFeedMessage),
+            )
+            .await
+            .is_err());
+
+        let mut keys = storage.message_state_keys().await;
+        keys.sort_by_key(|key| key.feed_id);
+        assert_eq!(
+            keys,
+            vec![
+                MessageStateKey {
+                    feed_id: [1; 32],
+                    type_:   MessageType::PriceFeedMessage,
+                },
+                MessageStateKey {
+                    feed_id: [2; 32],
+                    type_:   MessageType::PriceFeedMessage,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_store_message_states_prunes_messages_older_than_retention() {
+        let storage = create_temporary_storage();
+
+        let old_message_state = create_dummy_price_feed_message_state([1; 32], 10, 5);
+        storage
+            .store_message_states(vec![old_message_state.clone()])
+            .await
+            .unwrap();
+
+        // Still within the retention period of the newest message.
+        storage
+            .store_message_states(vec![create_dummy_price_feed_message_state(
+                [1; 32], 110, 50,
+            )])
+            .await
+            .unwrap();
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::FirstAfter(10)).await,
+            Some(old_message_state)
+        );
+
+        // Past the retention period of the newest message.
+        storage
+            .store_message_states(vec![create_dummy_price_feed_message_state(
+                [1; 32], 111, 51,
+            )])
+            .await
+            .unwrap();
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::FirstAfter(10)).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_update_accumulator_state_works() {
+        let storage = create_temporary_storage();
+
+        let accumulator_messages = AccumulatorMessages {
+            magic:        [0; 4],
+            slot:         10,
+            ring_size:    3,
+            raw_messages: vec![vec![1, 2, 3]],
+        };
+        let accumulator_messages_clone = accumulator_messages.clone();
+        storage
+            .update_accumulator_state(
+                10,
+                Box::new(|mut accumulator_state| {
+                    accumulator_state.accumulator_messages = Some(accumulator_messages_clone);
+                    accumulator_state
+                }),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage.fetch_accumulator_state(10).await.unwrap(),
+            Some(AccumulatorState {
+                slot:                  10,
+                accumulator_messages:  Some(accumulator_messages),
+                wormhole_merkle_state: None,
+            })
+        );
+        assert_eq!(storage.fetch_accumulator_state(11).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    pub async fn test_store_accumulator_state_evicts_old_slots() {
+        let storage = create_temporary_storage();
+
+        for slot in [10, 20, 10 + test_config().max_accumulator_slots + 1] {
+            storage
+                .store_accumulator_state(AccumulatorState {
+                    slot,
+                    accumulator_messages: None,
+                    wormhole_merkle_state: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(storage.fetch_accumulator_state(10).await.unwrap(), None);
+        assert!(storage.fetch_accumulator_state(20).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    pub async fn test_evict_stale_states_drops_expired_incomplete_accumulator_states() {
+        // Initialize a storage in which the incomplete accumulator states expire immediately.
+        let storage = create_temporary_storage_with_config(SledStorageConfig {
+            incomplete_accumulator_ttl: Duration::ZERO,
+            ..test_config()
+        });
+
+        let accumulator_messages = AccumulatorMessages {
+            magic:        [0; 4],
+            slot:         123,
+            ring_size:    3,
+            raw_messages: vec![],
+        };
+        let wormhole_merkle_state = WormholeMerkleState {
+            root: WormholeMerkleRoot {
+                root:      [0; 20],
+                slot:      123,
+                ring_size: 3,
+            },
+            vaa:  vec![],
+        };
+
+        // The state of slot 10 never gets its VAA, the one of slot 11 is completed later.
+        for slot in [10, 11] {
+            storage
+                .store_accumulator_state(AccumulatorState {
+                    slot,
+                    accumulator_messages: Some(accumulator_messages.clone()),
+                    wormhole_merkle_state: None,
+                })
+                .await
+                .unwrap();
+        }
+        storage
+            .update_accumulator_state(
+                11,
+                Box::new(|mut accumulator_state| {
+                    accumulator_state.wormhole_merkle_state = Some(wormhole_merkle_state);
+                    accumulator_state
+                }),
+            )
+            .await
+            .unwrap();
+
+        storage.evict_stale_states().await.unwrap();
+
+        assert_eq!(storage.fetch_accumulator_state(10).await.unwrap(), None);
+        assert!(storage
+            .fetch_accumulator_state(11)
+            .await
+            .unwrap()
+            .map_or(false, |state| state.is_complete()));
+    }
+
+    #[tokio::test]
+    pub async fn test_evict_stale_states_prunes_feeds_that_stopped_publishing() {
+        let storage = create_temporary_storage();
+
+        // Published long ago, the feed never got a newer message to prune it.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as UnixTimestamp;
+        storage
+            .store_message_states(vec![
+                create_dummy_price_feed_message_state([1; 32], now - 200, 5),
+                create_dummy_price_feed_message_state([2; 32], now, 6),
+            ])
+            .await
+            .unwrap();
+        assert!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::Latest)
+                .await
+                .is_some()
+        );
+
+        storage.evict_stale_states().await.unwrap();
+
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::Latest).await,
+            None
+        );
+        assert!(
+            fetch_price_feed_message_state(&storage, [2; 32], RequestTime::Latest)
+                .await
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_message_states_survive_reopen() {
+        let path = std::env::temp_dir().join(format!("hermes-sled-test-{}", rand::random::<u64>()));
+        let message_state = create_dummy_price_feed_message_state([1; 32], 10, 5);
+        // Without the background flusher the database is closed, and its lock released, as soon
+        // as it is dropped.
+        let open = || {
+            let db = sled::Config::new()
+                .path(&path)
+                .flush_every_ms(None)
+                .open()
+                .unwrap();
+            SledStorage::from_db(db, test_config()).unwrap()
+        };
+
+        {
+            let storage = open();
+            storage
+                .store_message_states(vec![message_state.clone()])
+                .await
+                .unwrap();
+            storage.flush().await.unwrap();
+        }
+
+        let storage = open();
+        assert_eq!(
+            fetch_price_feed_message_state(&storage, [1; 32], RequestTime::Latest).await,
+            Some(message_state)
+        );
+
+        drop(storage);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
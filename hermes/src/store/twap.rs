@@ -0,0 +1,149 @@
+//! Time-weighted average price computation on top of the `TwapMessage`s published by Pythnet.
+//!
+//! Each `TwapMessage` carries the running sums of the price and confidence of a feed over all the
+//! slots since the feed was created. The TWAP between two messages is the difference of the sums
+//! divided by the number of slots between them.
+
+use {
+    super::types::UnixTimestamp,
+    anyhow::{
+        anyhow,
+        Result,
+    },
+    pythnet_sdk::messages::{
+        FeedId,
+        TwapMessage,
+    },
+};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Twap {
+    pub feed_id:        FeedId,
+    pub price:          i64,
+    pub conf:           u64,
+    pub exponent:       i32,
+    pub start_time:     UnixTimestamp,
+    pub end_time:       UnixTimestamp,
+    /// Number of slots in the TWAP window.
+    pub num_slots:      u64,
+    /// Number of slots in the TWAP window in which the feed had no valid aggregate price.
+    pub num_down_slots: u64,
+}
+
+/// Computes the TWAP of a feed over the window between two of its `TwapMessage`s.
+pub fn calculate_twap(start: &TwapMessage, end: &TwapMessage) -> Result<Twap> {
+    if start.feed_id != end.feed_id {
+        return Err(anyhow!("TWAP messages belong to different feeds"));
+    }
+
+    if start.exponent != end.exponent {
+        return Err(anyhow!("TWAP messages have different exponents"));
+    }
+
+    if end.publish_slot <= start.publish_slot {
+        return Err(anyhow!(
+            "TWAP end slot {} is not after start slot {}",
+            end.publish_slot,
+            start.publish_slot
+        ));
+    }
+
+    let num_slots = end.publish_slot - start.publish_slot;
+    let num_down_slots = end
+        .num_down_slots
+        .checked_sub(start.num_down_slots)
+        .ok_or(anyhow!("Invalid number of down slots"))?;
+
+    let price = end
+        .cumulative_price
+        .checked_sub(start.cumulative_price)
+        .ok_or(anyhow!("Cumulative price overflow"))?
+        / num_slots as i128;
+
+    let conf = end
+        .cumulative_conf
+        .checked_sub(start.cumulative_conf)
+        .ok_or(anyhow!("Invalid cumulative confidence"))?
+        / num_slots as u128;
+
+    Ok(Twap {
+        feed_id: end.feed_id,
+        price: price.try_into()?,
+        conf: conf.try_into()?,
+        exponent: end.exponent,
+        start_time: start.publish_time,
+        end_time: end.publish_time,
+        num_slots,
+        num_down_slots,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn create_dummy_twap_message(
+        cumulative_price: i128,
+        cumulative_conf: u128,
+        num_down_slots: u64,
+        publish_slot: u64,
+    ) -> TwapMessage {
+        TwapMessage {
+            feed_id: [1; 32],
+            cumulative_price,
+            cumulative_conf,
+            num_down_slots,
+            exponent: -8,
+            publish_time: publish_slot as i64 / 2,
+            prev_publish_time: 0,
+            publish_slot,
+        }
+    }
+
+    #[test]
+    pub fn test_calculate_twap_works() {
+        let start = create_dummy_twap_message(1_000, 100, 1, 10);
+        let end = create_dummy_twap_message(6_000, 600, 3, 20);
+
+        assert_eq!(
+            calculate_twap(&start, &end).unwrap(),
+            Twap {
+                feed_id:        [1; 32],
+                price:          500,
+                conf:           50,
+                exponent:       -8,
+                start_time:     5,
+                end_time:       10,
+                num_slots:      10,
+                num_down_slots: 2,
+            }
+        );
+    }
+
+    #[test]
+    pub fn test_calculate_twap_works_with_negative_prices() {
+        let start = create_dummy_twap_message(0, 0, 0, 10);
+        let end = create_dummy_twap_message(-4_000, 400, 0, 20);
+
+        let twap = calculate_twap(&start, &end).unwrap();
+        assert_eq!(twap.price, -400);
+        assert_eq!(twap.conf, 40);
+    }
+
+    #[test]
+    pub fn test_calculate_twap_fails_for_invalid_window() {
+        let start = create_dummy_twap_message(1_000, 100, 1, 10);
+        let end = create_dummy_twap_message(6_000, 600, 3, 20);
+
+        // The window must go forward in slots.
+        assert!(calculate_twap(&end, &start).is_err());
+        assert!(calculate_twap(&start, &start).is_err());
+
+        // Both messages must belong to the same feed.
+        let other_feed_end = TwapMessage {
+            feed_id: [2; 32],
+            ..end
+        };
+        assert!(calculate_twap(&start, &other_feed_end).is_err());
+    }
+}
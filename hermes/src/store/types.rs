@@ -1,10 +1,24 @@
 use {
     super::proof::wormhole_merkle::WormholeMerkleMessageProof,
-    borsh::BorshDeserialize,
-    pythnet_sdk::messages::PriceFeedMessage,
+    anyhow::Result,
+    borsh::{
+        BorshDeserialize,
+        BorshSerialize,
+    },
+    pyth_sdk::PriceIdentifier,
+    pythnet_sdk::messages::{
+        FeedId,
+        PriceFeedMessage,
+        TwapMessage,
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    std::collections::HashSet,
 };
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct ProofSet {
     pub wormhole_merkle_proof: WormholeMerkleMessageProof,
 }
@@ -16,6 +30,35 @@ pub type UnixTimestamp = i64;
 pub enum RequestTime {
     Latest,
     FirstAfter(UnixTimestamp),
+    /// The first `limit` messages with a publish time within the inclusive `[start, end]` range.
+    Range {
+        start: UnixTimestamp,
+        end:   UnixTimestamp,
+        limit: usize,
+    },
+    /// All the messages of the slots strictly after the given one, or the `limit` latest ones if
+    /// there are more. The slots of the messages are assumed to increase with their publish time,
+    /// so the lookup walks back from the latest message.
+    AfterSlot {
+        slot:  Slot,
+        limit: usize,
+    },
+    /// The stored messages of the given slot and the slots after it. Unlike `AfterSlot`, the
+    /// storage does not need to hold a message before the slot, so older messages of the range
+    /// might be missing. Same as `AtSlot`, the lookup walks back from the latest message.
+    SinceSlot(Slot),
+    /// The message of the given slot, if one is stored. The slots of the messages are assumed to
+    /// increase with their publish time, so the lookup walks back from the latest message.
+    AtSlot(Slot),
+    /// At most `limit` messages, in publish time and slot order, starting at the given publish
+    /// time and slot (inclusive) and published at or before `end_time`. Same as `Range`, the
+    /// storage must retain the start publish time for the page to be complete.
+    Page {
+        start_time: UnixTimestamp,
+        start_slot: Slot,
+        end_time:   UnixTimestamp,
+        limit:      usize,
+    },
 }
 
 pub type RawMessage = Vec<u8>;
@@ -26,7 +69,7 @@ pub type RawMessage = Vec<u8>;
 /// the following struct. We cannot directly have messages as Vec<Messages>
 /// because they are serialized using big-endian byte order and Borsh
 /// uses little-endian byte order.
-#[derive(Clone, PartialEq, Debug, BorshDeserialize)]
+#[derive(Clone, PartialEq, Debug, BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
 pub struct AccumulatorMessages {
     pub magic:        [u8; 4],
     pub slot:         u64,
@@ -59,3 +102,274 @@ pub struct PriceFeedsWithUpdateData {
     pub price_feeds:                 Vec<PriceFeedUpdate>,
     pub wormhole_merkle_update_data: Vec<Vec<u8>>,
 }
+
+/// Price feeds that were all updated in the same slot, along with the single update data that
+/// proves them against the VAA of that slot.
+pub struct AggregatedPriceFeedsWithUpdateData {
+    pub slot:                        Slot,
+    pub price_feeds:                 Vec<PriceFeedUpdate>,
+    pub wormhole_merkle_update_data: Vec<u8>,
+}
+
+/// Position of a price feed update within a range. The updates of a range are ordered by publish
+/// time, slot and feed id.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct RangePosition {
+    pub publish_time: UnixTimestamp,
+    pub slot:         Slot,
+    pub feed_id:      FeedId,
+}
+
+/// A page of the price feed updates of a range. The update data of each update is only built
+/// when the page is iterated, so that it can be streamed.
+pub struct PriceFeedUpdatesPage {
+    pub updates: Box<dyn Iterator<Item = Result<PriceFeedUpdate>> + Send>,
+    /// Position of the last update of the page, if more updates follow it.
+    pub next:    Option<RangePosition>,
+}
+
+pub struct TwapUpdate {
+    pub twap:                        TwapMessage,
+    pub slot:                        Slot,
+    pub received_at:                 UnixTimestamp,
+    /// Wormhole merkle update data for this single twap update.
+    pub wormhole_merkle_update_data: Vec<u8>,
+}
+
+pub struct TwapsWithUpdateData {
+    pub twaps:                       Vec<TwapUpdate>,
+    pub wormhole_merkle_update_data: Vec<Vec<u8>>,
+}
+
+/// Published by the store every time the message states of an accumulator state are stored.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UpdateEvent {
+    pub slot:     Slot,
+    /// Feeds with a message in the slot.
+    pub feed_ids: HashSet<PriceIdentifier>,
+}
+
+/// Inclusive range of publish times of the message states stored for a feed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TimeWindow {
+    pub start: UnixTimestamp,
+    pub end:   UnixTimestamp,
+}
+
+impl TimeWindow {
+    /// The publish times covered by both windows, or `None` if they do not overlap.
+    pub fn intersect(&self, other: &TimeWindow) -> Option<TimeWindow> {
+        let window = TimeWindow {
+            start: self.start.max(other.start),
+            end:   self.end.min(other.end),
+        };
+        (window.start <= window.end).then_some(window)
+    }
+}
+
+/// Why the message states of a request could not be returned. The storages and the store return
+/// it through `anyhow`, so that the API can tell the failures apart with `downcast_ref`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LookupError {
+    /// Nothing is stored for the feeds.
+    FeedsNotFound(Vec<PriceIdentifier>),
+    /// The requested time is older than the oldest message stored for the feeds. The window is
+    /// the one in which all of them can be requested, `None` if the windows of the feeds do not
+    /// overlap.
+    PublishTimeTooOld {
+        feed_ids:        Vec<PriceIdentifier>,
+        retained_window: Option<TimeWindow>,
+    },
+    /// No message of the feeds was published at or after the requested time yet.
+    PublishTimeInFuture {
+        feed_ids:        Vec<PriceIdentifier>,
+        retained_window: Option<TimeWindow>,
+    },
+    /// No slot within `max_slot_lag` slots of `latest_slot` has a message of every feed.
+    NoCommonSlot {
+        latest_slot:  Slot,
+        max_slot_lag: Slot,
+    },
+    /// No price feed has the symbol in its metadata.
+    UnknownSymbol(String),
+    /// Several price feeds have the short symbol, whose full symbols are `matches`.
+    AmbiguousSymbol {
+        symbol:  String,
+        matches: Vec<String>,
+    },
+}
+
+impl LookupError {
+    /// Classifies the feeds whose message states are missing for the request time, given the
+    /// window of each of them (`None` if nothing is stored for the feed). The unknown feeds are
+    /// reported first, then the ones requested too far in the past.
+    pub fn from_missing(
+        request_time: &RequestTime,
+        missing: Vec<(PriceIdentifier, Option<TimeWindow>)>,
+    ) -> Option<Self> {
+        let mut not_found = vec![];
+        let mut too_old: Vec<(PriceIdentifier, TimeWindow)> = vec![];
+        let mut in_future: Vec<(PriceIdentifier, TimeWindow)> = vec![];
+
+        for (feed_id, window) in missing {
+            match (window, request_time) {
+                (None, _) => not_found.push(feed_id),
+                (
+                    Some(window),
+                    RequestTime::FirstAfter(time)
+                    | RequestTime::Range { start: time, .. }
+                    | RequestTime::Page {
+                        start_time: time, ..
+                    },
+                ) if *time < window.start => too_old.push((feed_id, window)),
+                (Some(window), RequestTime::AfterSlot { .. }) => too_old.push((feed_id, window)),
+                (Some(window), RequestTime::FirstAfter(_)) => in_future.push((feed_id, window)),
+                (Some(_), _) => not_found.push(feed_id),
+            }
+        }
+
+        // The window in which all the feeds can be requested, if they have one in common.
+        let retained_window = |feeds: &[(PriceIdentifier, TimeWindow)]| {
+            feeds
+                .iter()
+                .map(|(_, window)| Some(*window))
+                .reduce(|a, b| a?.intersect(&b?))
+                .flatten()
+        };
+        let feed_ids = |feeds: &[(PriceIdentifier, TimeWindow)]| -> Vec<PriceIdentifier> {
+            feeds.iter().map(|(id, _)| *id).collect()
+        };
+
+        if !not_found.is_empty() {
+            return Some(LookupError::FeedsNotFound(not_found));
+        }
+        if !too_old.is_empty() {
+            return Some(LookupError::PublishTimeTooOld {
+                feed_ids:        feed_ids(&too_old),
+                retained_window: retained_window(&too_old),
+            });
+        }
+        if !in_future.is_empty() {
+            return Some(LookupError::PublishTimeInFuture {
+                feed_ids:        feed_ids(&in_future),
+                retained_window: retained_window(&in_future),
+            });
+        }
+        None
+    }
+}
+
+impl std::fmt::Display for LookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LookupError::FeedsNotFound(feed_ids) => {
+                write!(f, "Price feeds not found: {}", format_feed_ids(feed_ids))
+            }
+            LookupError::PublishTimeTooOld {
+                feed_ids,
+                retained_window: Some(retained_window),
+            } => write!(
+                f,
+                "Publish time older than the updates retained for {}, which start at {}",
+                format_feed_ids(feed_ids),
+                retained_window.start
+            ),
+            LookupError::PublishTimeTooOld {
+                feed_ids,
+                retained_window: None,
+            } => write!(
+                f,
+                "Publish time older than the updates retained for {}, which have no publish time \
+                 in common",
+                format_feed_ids(feed_ids)
+            ),
+            LookupError::PublishTimeInFuture {
+                feed_ids,
+                retained_window: Some(retained_window),
+            } => write!(
+                f,
+                "No update of {} published at or after the publish time yet, the latest one is at {}",
+                format_feed_ids(feed_ids),
+                retained_window.end
+            ),
+            LookupError::PublishTimeInFuture {
+                feed_ids,
+                retained_window: None,
+            } => write!(
+                f,
+                "No update of {} published at or after the publish time yet",
+                format_feed_ids(feed_ids)
+            ),
+            LookupError::NoCommonSlot {
+                latest_slot,
+                max_slot_lag,
+            } => write!(
+                f,
+                "No slot within {max_slot_lag} slots of {latest_slot} has a message of every feed"
+            ),
+            LookupError::UnknownSymbol(symbol) => write!(f, "Unknown symbol: {symbol}"),
+            LookupError::AmbiguousSymbol { symbol, matches } => write!(
+                f,
+                "Symbol {} is ambiguous, use one of: {}",
+                symbol,
+                matches.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LookupError {
+}
+
+fn format_feed_ids(feed_ids: &[PriceIdentifier]) -> String {
+    feed_ids
+        .iter()
+        .map(|feed_id| feed_id.to_hex())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_missing_feeds_without_common_window() {
+        let window = |start, end| TimeWindow { start, end };
+        let feed_ids = vec![PriceIdentifier::new([1; 32]), PriceIdentifier::new([2; 32])];
+
+        assert_eq!(
+            window(10, 20).intersect(&window(15, 30)),
+            Some(window(15, 20))
+        );
+        assert_eq!(window(10, 20).intersect(&window(21, 30)), None);
+
+        // The windows of the feeds do not overlap, so none is reported rather than an empty one.
+        assert_eq!(
+            LookupError::from_missing(
+                &RequestTime::FirstAfter(5),
+                vec![
+                    (feed_ids[0], Some(window(10, 20))),
+                    (feed_ids[1], Some(window(21, 30))),
+                ],
+            ),
+            Some(LookupError::PublishTimeTooOld {
+                feed_ids:        feed_ids.clone(),
+                retained_window: None,
+            })
+        );
+        assert_eq!(
+            LookupError::from_missing(
+                &RequestTime::FirstAfter(5),
+                vec![
+                    (feed_ids[0], Some(window(10, 20))),
+                    (feed_ids[1], Some(window(15, 30))),
+                ],
+            ),
+            Some(LookupError::PublishTimeTooOld {
+                feed_ids,
+                retained_window: Some(window(15, 20)),
+            })
+        );
+    }
+}
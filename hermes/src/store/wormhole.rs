@@ -1,21 +1,23 @@
 use {
     super::Store,
+    crate::metrics::VaaRejectionReason,
     anyhow::{
         anyhow,
         Result,
     },
-    secp256k1::{
-        ecdsa::{
-            RecoverableSignature,
-            RecoveryId,
-        },
-        Message,
-        Secp256k1,
+    hermes_client::verify::{
+        verify_guardian_signatures,
+        GuardianSignatureError,
     },
+    serde::Deserialize,
     serde_wormhole::RawMessage,
-    sha3::{
-        Digest,
-        Keccak256,
+    std::{
+        path::Path,
+        str::FromStr,
+        time::{
+            SystemTime,
+            UNIX_EPOCH,
+        },
     },
     wormhole_sdk::{
         vaa::{
@@ -29,7 +31,16 @@ use {
 /// A small wrapper around [u8; 20] guardian set key types.
 #[derive(Eq, PartialEq, Clone, Hash, Debug)]
 pub struct GuardianSet {
-    pub keys: Vec<[u8; 20]>,
+    pub keys:            Vec<[u8; 20]>,
+    /// Unix timestamp after which the set can no longer sign messages, or 0 if it does not
+    /// expire. Same as the `expiration_time` of the Wormhole guardian set accounts.
+    pub expiration_time: u32,
+}
+
+impl GuardianSet {
+    pub fn is_expired(&self, now: u32) -> bool {
+        self.expiration_time != 0 && self.expiration_time < now
+    }
 }
 
 impl std::fmt::Display for GuardianSet {
@@ -73,7 +84,117 @@ pub struct GuardianSetData {
     pub expiration_time: u32,
 }
 
-/// Verifies a VAA to ensure it is signed by the Wormhole guardian set.
+/// A guardian set supplied in the configuration instead of being fetched from Pythnet.
+///
+/// They are read from a JSON file holding a list of sets:
+///
+/// ```json
+/// [{ "index": 3, "keys": ["58cc3ae5c097b213ce3c81979e1b9f9570746aa5"], "expiration_time": 0 }]
+/// ```
+///
+/// or from the command line as `<index>:<key>,<key>,...`, in which case they do not expire.
+#[derive(Eq, PartialEq, Clone, Debug, Deserialize)]
+pub struct StaticGuardianSet {
+    pub index:           u32,
+    #[serde(deserialize_with = "deserialize_guardian_keys")]
+    pub keys:            Vec<[u8; 20]>,
+    #[serde(default)]
+    pub expiration_time: u32,
+}
+
+impl StaticGuardianSet {
+    pub fn guardian_set(&self) -> GuardianSet {
+        GuardianSet {
+            keys:            self.keys.clone(),
+            expiration_time: self.expiration_time,
+        }
+    }
+}
+
+impl FromStr for StaticGuardianSet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (index, keys) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected <index>:<key>,<key>,..., got {}", s))?;
+
+        Ok(Self {
+            index:           index.parse()?,
+            keys:            keys
+                .split(',')
+                .map(parse_guardian_key)
+                .collect::<Result<_>>()?,
+            expiration_time: 0,
+        })
+    }
+}
+
+/// Reads a list of `StaticGuardianSet`s from a JSON file.
+pub fn load_guardian_sets(path: &Path) -> Result<Vec<StaticGuardianSet>> {
+    serde_json::from_slice(&std::fs::read(path)?)
+        .map_err(|e| anyhow!("Invalid guardian sets file {}: {}", path.display(), e))
+}
+
+fn parse_guardian_key(key: &str) -> Result<[u8; 20]> {
+    let key = key.trim();
+    <[u8; 20]>::try_from(hex::decode(key.strip_prefix("0x").unwrap_or(key))?)
+        .map_err(|_| anyhow!("Guardian key {} is not 20 bytes long", key))
+}
+
+fn deserialize_guardian_keys<'de, D>(deserializer: D) -> Result<Vec<[u8; 20]>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|key| parse_guardian_key(key).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Why the guardian set of a VAA was rejected. The failures of the signatures themselves are
+/// `GuardianSignatureError`s.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum GuardianSetError {
+    Unknown(u32),
+    Expired(u32),
+}
+
+impl std::fmt::Display for GuardianSetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GuardianSetError::Unknown(index) => {
+                write!(f, "Message signed by an unknown guardian set: {index}")
+            }
+            GuardianSetError::Expired(index) => {
+                write!(f, "Message signed by an expired guardian set: {index}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GuardianSetError {
+}
+
+/// The metrics reason of an error returned by `verify_vaa`.
+pub fn vaa_rejection_reason(error: &anyhow::Error) -> VaaRejectionReason {
+    if let Some(error) = error.downcast_ref::<GuardianSetError>() {
+        return match error {
+            GuardianSetError::Unknown(_) => VaaRejectionReason::UnknownGuardianSet,
+            GuardianSetError::Expired(_) => VaaRejectionReason::ExpiredGuardianSet,
+        };
+    }
+
+    match error.downcast_ref::<GuardianSignatureError>() {
+        Some(GuardianSignatureError::Unsorted { .. }) => VaaRejectionReason::UnsortedSignatures,
+        Some(GuardianSignatureError::Malformed { .. }) => VaaRejectionReason::InvalidSignature,
+        Some(GuardianSignatureError::NoQuorum { .. }) => VaaRejectionReason::NoQuorum,
+        None => VaaRejectionReason::Malformed,
+    }
+}
+
+/// Verifies a VAA to ensure it is signed by the Wormhole guardian set. Fails with a
+/// `GuardianSetError` or a `GuardianSignatureError`, see `vaa_rejection_reason`.
 pub async fn verify_vaa<'a>(
     store: &Store,
     vaa: Vaa<&'a RawMessage>,
@@ -83,51 +204,275 @@ pub async fn verify_vaa<'a>(
     let guardian_set = store.guardian_set.read().await;
     let guardian_set = guardian_set
         .get(&header.guardian_set_index)
-        .ok_or_else(|| {
-            anyhow!(
-                "Message signed by an unknown guardian set: {}",
-                header.guardian_set_index
-            )
-        })?;
+        .ok_or(GuardianSetError::Unknown(header.guardian_set_index))?;
 
-    let mut num_correct_signers = 0;
-    for sig in header.signatures.iter() {
-        let signer_id: usize = sig.index.into();
+    // Like the Wormhole contracts, a previous guardian set is only accepted until it expires.
+    let now: u32 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)?
+        .as_secs()
+        .try_into()?;
+    if guardian_set.is_expired(now) {
+        return Err(GuardianSetError::Expired(header.guardian_set_index).into());
+    }
 
-        let sig = sig.signature;
+    verify_guardian_signatures(
+        &digest.secp256k_hash,
+        &header.signatures,
+        &guardian_set.keys,
+    )?;
 
-        // Recover the public key from ecdsa signature from [u8; 65] that has (v, r, s) format
-        let recid = RecoveryId::from_i32(sig[64].into())?;
+    Ok((header, body).into())
+}
 
+#[cfg(test)]
+pub mod test {
+    use {
+        super::*,
+        crate::store::storage::local_storage::LocalStorage,
+        secp256k1::{
+            Message,
+            PublicKey,
+            Secp256k1,
+            SecretKey,
+        },
+        sha3::{
+            Digest,
+            Keccak256,
+        },
+        wormhole_sdk::Chain,
+    };
+
+    /// Deterministic keys of a guardian set of the given size, for signing test VAAs.
+    pub fn guardian_secret_keys(num_guardians: u8) -> Vec<SecretKey> {
+        (1..=num_guardians)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect()
+    }
+
+    pub fn guardian_set(secret_keys: &[SecretKey]) -> GuardianSet {
         let secp = Secp256k1::new();
+        GuardianSet {
+            keys:            secret_keys
+                .iter()
+                .map(|secret_key| {
+                    let pubkey = PublicKey::from_secret_key(&secp, secret_key);
+                    let mut keccak = Keccak256::new();
+                    keccak.update(&pubkey.serialize_uncompressed()[1..]);
+                    let address: [u8; 32] = keccak.finalize().into();
+                    address[12..].try_into().unwrap()
+                })
+                .collect(),
+            expiration_time: 0,
+        }
+    }
 
-        // To get the address we need to use the uncompressed public key
-        let pubkey: &[u8; 65] = &secp
-            .recover_ecdsa(
-                &Message::from_slice(&digest.secp256k_hash)?,
-                &RecoverableSignature::from_compact(&sig[..64], recid)?,
-            )?
-            .serialize_uncompressed();
-
-        // The address is the last 20 bytes of the Keccak256 hash of the public key
-        let mut keccak = Keccak256::new();
-        keccak.update(&pubkey[1..]);
-        let address: [u8; 32] = keccak.finalize().into();
-        let address: [u8; 20] = address[address.len() - 20..].try_into()?;
-
-        if guardian_set.keys.get(signer_id) == Some(&address) {
-            num_correct_signers += 1;
+    /// Builds a VAA of the Pythnet accumulator emitter with the given payload, signed in order by
+    /// the given guardians, identified by their index in the set and their key.
+    pub fn create_signed_vaa(
+        guardian_set_index: u32,
+        sequence: u64,
+        payload: &[u8],
+        signers: &[(u8, &SecretKey)],
+    ) -> Vec<u8> {
+        let mut body = vec![];
+        body.extend_from_slice(&1u32.to_be_bytes()); // Timestamp
+        body.extend_from_slice(&2u32.to_be_bytes()); // Nonce
+        body.extend_from_slice(&u16::from(Chain::Pythnet).to_be_bytes());
+        body.extend_from_slice(&pythnet_sdk::ACCUMULATOR_EMITTER_ADDRESS);
+        body.extend_from_slice(&sequence.to_be_bytes());
+        body.push(1); // Consistency level
+        body.extend_from_slice(payload);
+
+        // The guardians sign the double keccak256 hash of the body.
+        let hash: [u8; 32] = Keccak256::digest(Keccak256::digest(&body)).into();
+        let secp = Secp256k1::new();
+
+        let mut vaa = vec![1];
+        vaa.extend_from_slice(&guardian_set_index.to_be_bytes());
+        vaa.push(signers.len() as u8);
+        for (index, secret_key) in signers {
+            let (recovery_id, signature) = secp
+                .sign_ecdsa_recoverable(&Message::from_slice(&hash).unwrap(), secret_key)
+                .serialize_compact();
+            vaa.push(*index);
+            vaa.extend_from_slice(&signature);
+            vaa.push(recovery_id.to_i32() as u8);
         }
+        vaa.extend_from_slice(&body);
+        vaa
     }
 
-    let quorum = (guardian_set.keys.len() * 2 + 2) / 3;
-    if num_correct_signers < quorum {
-        return Err(anyhow!(
-            "Not enough correct signatures. Expected {:?}, received {:?}",
-            quorum,
-            num_correct_signers
-        ));
+    async fn verify_signed_vaa(store: &Store, vaa: &[u8]) -> Result<()> {
+        verify_vaa(store, serde_wormhole::from_slice(vaa)?)
+            .await
+            .map(|_| ())
     }
 
-    Ok((header, body).into())
+    async fn rejection_reason(store: &Store, vaa: &[u8]) -> Option<VaaRejectionReason> {
+        verify_signed_vaa(store, vaa)
+            .await
+            .err()
+            .map(|e| vaa_rejection_reason(&e))
+    }
+
+    #[tokio::test]
+    pub async fn test_verify_vaa() {
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(10);
+        let store = Store::new(update_tx, LocalStorage::new_instance(10), None);
+        let secret_keys = guardian_secret_keys(4);
+        store
+            .update_guardian_set(0, guardian_set(&secret_keys))
+            .await;
+
+        // A quorum of 3 out of 4 guardians.
+        let vaa = create_signed_vaa(
+            0,
+            1,
+            b"payload",
+            &[
+                (0, &secret_keys[0]),
+                (1, &secret_keys[1]),
+                (3, &secret_keys[3]),
+            ],
+        );
+        assert!(verify_signed_vaa(&store, &vaa).await.is_ok());
+
+        // Below the quorum.
+        let vaa = create_signed_vaa(
+            0,
+            1,
+            b"payload",
+            &[(0, &secret_keys[0]), (1, &secret_keys[1])],
+        );
+        assert_eq!(
+            rejection_reason(&store, &vaa).await,
+            Some(VaaRejectionReason::NoQuorum)
+        );
+
+        // A guardian counted several times.
+        let vaa = create_signed_vaa(
+            0,
+            1,
+            b"payload",
+            &[
+                (0, &secret_keys[0]),
+                (0, &secret_keys[0]),
+                (0, &secret_keys[0]),
+            ],
+        );
+        assert_eq!(
+            rejection_reason(&store, &vaa).await,
+            Some(VaaRejectionReason::UnsortedSignatures)
+        );
+
+        // An unknown guardian set, and a known one that did not sign.
+        let vaa = create_signed_vaa(
+            1,
+            1,
+            b"payload",
+            &[
+                (0, &secret_keys[0]),
+                (1, &secret_keys[1]),
+                (2, &secret_keys[2]),
+            ],
+        );
+        assert_eq!(
+            rejection_reason(&store, &vaa).await,
+            Some(VaaRejectionReason::UnknownGuardianSet)
+        );
+        store
+            .update_guardian_set(1, guardian_set(&guardian_secret_keys(8)[4..]))
+            .await;
+        assert_eq!(
+            rejection_reason(&store, &vaa).await,
+            Some(VaaRejectionReason::NoQuorum)
+        );
+
+        // A previous guardian set that has expired.
+        let vaa = create_signed_vaa(
+            2,
+            1,
+            b"payload",
+            &[
+                (0, &secret_keys[0]),
+                (1, &secret_keys[1]),
+                (2, &secret_keys[2]),
+            ],
+        );
+        store
+            .update_guardian_set(
+                2,
+                GuardianSet {
+                    expiration_time: 1,
+                    ..guardian_set(&secret_keys)
+                },
+            )
+            .await;
+        assert_eq!(
+            rejection_reason(&store, &vaa).await,
+            Some(VaaRejectionReason::ExpiredGuardianSet)
+        );
+    }
+
+    #[test]
+    pub fn test_guardian_set_expiration() {
+        let guardian_set = GuardianSet {
+            keys:            vec![],
+            expiration_time: 100,
+        };
+        assert!(!guardian_set.is_expired(99));
+        assert!(!guardian_set.is_expired(100));
+        assert!(guardian_set.is_expired(101));
+
+        // An expiration time of 0 means the set never expires.
+        let guardian_set = GuardianSet {
+            keys:            vec![],
+            expiration_time: 0,
+        };
+        assert!(!guardian_set.is_expired(u32::MAX));
+    }
+
+    #[test]
+    pub fn test_static_guardian_set_from_str() {
+        assert_eq!(
+            "3:0x58cc3ae5c097b213ce3c81979e1b9f9570746aa5,ff6cb952589bde862c25ef4392132fb9d4a42157"
+                .parse::<StaticGuardianSet>()
+                .unwrap(),
+            StaticGuardianSet {
+                index:           3,
+                keys:            vec![
+                    hex::decode("58cc3ae5c097b213ce3c81979e1b9f9570746aa5")
+                        .unwrap()
+                        .try_into()
+                        .unwrap(),
+                    hex::decode("ff6cb952589bde862c25ef4392132fb9d4a42157")
+                        .unwrap()
+                        .try_into()
+                        .unwrap(),
+                ],
+                expiration_time: 0,
+            }
+        );
+
+        assert!("58cc3ae5c097b213ce3c81979e1b9f9570746aa5"
+            .parse::<StaticGuardianSet>()
+            .is_err());
+        assert!("3:58cc3ae5".parse::<StaticGuardianSet>().is_err());
+    }
+
+    #[test]
+    pub fn test_static_guardian_sets_from_json() {
+        let guardian_sets: Vec<StaticGuardianSet> = serde_json::from_str(
+            r#"[
+                {"index": 2, "keys": ["58cc3ae5c097b213ce3c81979e1b9f9570746aa5"], "expiration_time": 1000},
+                {"index": 3, "keys": ["0xff6cb952589bde862c25ef4392132fb9d4a42157"]}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(guardian_sets.len(), 2);
+        assert_eq!(guardian_sets[0].guardian_set().expiration_time, 1000);
+        assert_eq!(guardian_sets[1].index, 3);
+        assert_eq!(guardian_sets[1].guardian_set().expiration_time, 0);
+    }
 }